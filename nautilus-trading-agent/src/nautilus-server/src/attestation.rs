@@ -0,0 +1,459 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::EnclaveError;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::hash::{HashFunction, Sha256};
+use nsm_api::api::AttestationDoc;
+use p384::ecdsa::signature::Verifier;
+use p384::ecdsa::{Signature, VerifyingKey};
+use serde::Serialize;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+use x509_parser::prelude::*;
+
+/// SHA-256 fingerprint of the AWS Nitro Enclaves root certificate (G1), see
+/// https://docs.aws.amazon.com/enclaves/latest/user/verify-root.html
+pub const AWS_NITRO_ROOT_CERT_SHA256: &str =
+    "641a0321a3e244efe456463195d606317ed7cdcc3c1756e09893f3c68f79bb5b";
+
+/// CBOR tag of a COSE_Sign1 structure, the NSM usually omits it.
+const COSE_SIGN1_TAG: u64 = 18;
+/// Tolerated clock difference between the NSM and the verifier.
+const CLOCK_SKEW_MS: u64 = 60 * 1000;
+
+/// Split a COSE_Sign1 attestation document into its protected header,
+/// payload and signature.
+pub fn parse_cose_sign1(document: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), EnclaveError> {
+    let value: Value = serde_cbor::from_slice(document)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid COSE_Sign1 encoding: {}", e)))?;
+    let value = match value {
+        Value::Tag(COSE_SIGN1_TAG, inner) => *inner,
+        value => value,
+    };
+
+    match value {
+        Value::Array(items) if items.len() == 4 => match (&items[0], &items[2], &items[3]) {
+            (Value::Bytes(protected), Value::Bytes(payload), Value::Bytes(signature)) => {
+                Ok((protected.clone(), payload.clone(), signature.clone()))
+            }
+            _ => Err(EnclaveError::InvalidRequest(
+                "Malformed COSE_Sign1 structure".to_string(),
+            )),
+        },
+        _ => Err(EnclaveError::InvalidRequest(
+            "Attestation document is not a COSE_Sign1 array".to_string(),
+        )),
+    }
+}
+
+/// Verify an attestation document: the certificate chain must lead to the
+/// AWS Nitro root certificate and the document must be signed by the leaf.
+/// Returns the decoded document, PCR and public key checks are left to the caller.
+pub fn verify_attestation(document: &[u8]) -> Result<AttestationDoc, EnclaveError> {
+    verify_attestation_with_root(document, AWS_NITRO_ROOT_CERT_SHA256)
+}
+
+/// Like [verify_attestation], with the root certificate given by its hex SHA-256 fingerprint.
+pub fn verify_attestation_with_root(
+    document: &[u8],
+    root_sha256: &str,
+) -> Result<AttestationDoc, EnclaveError> {
+    let (protected, payload, signature) = parse_cose_sign1(document)?;
+    let doc = AttestationDoc::from_binary(&payload).map_err(|e| {
+        EnclaveError::InvalidRequest(format!("Invalid attestation payload: {:?}", e))
+    })?;
+
+    verify_certificate_chain(&doc, root_sha256)?;
+    verify_cose_signature(&doc.certificate, &protected, &payload, &signature)?;
+    Ok(doc)
+}
+
+/// What an attestation document must satisfy besides a valid signature chain.
+#[derive(Debug, Clone)]
+pub struct VerificationPolicy {
+    /// Hex SHA-256 fingerprint of the trusted root certificate
+    pub root_sha256: String,
+    /// PCR index to expected value, unlisted PCRs are not checked
+    pub expected_pcrs: BTreeMap<usize, Vec<u8>>,
+    /// Reject documents older than this, relative to `now_ms`
+    pub max_age_ms: Option<u64>,
+    pub now_ms: u64,
+    /// Nonce the document must carry
+    pub expected_nonce: Option<Vec<u8>>,
+}
+
+impl VerificationPolicy {
+    /// Trust the AWS Nitro root and check nothing else.
+    pub fn aws(now_ms: u64) -> Self {
+        Self {
+            root_sha256: AWS_NITRO_ROOT_CERT_SHA256.to_string(),
+            expected_pcrs: BTreeMap::new(),
+            max_age_ms: None,
+            now_ms,
+            expected_nonce: None,
+        }
+    }
+}
+
+/// The parts of a verified attestation document callers act on.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedAttestation {
+    pub module_id: String,
+    pub timestamp_ms: u64,
+    #[serde(serialize_with = "serialize_pcrs")]
+    pub pcrs: BTreeMap<usize, Vec<u8>>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub public_key: Vec<u8>,
+    #[serde(serialize_with = "serialize_optional_hex")]
+    pub user_data: Option<Vec<u8>>,
+    #[serde(serialize_with = "serialize_optional_hex")]
+    pub nonce: Option<Vec<u8>>,
+}
+
+/// Verify an attestation document against a policy and extract the enclave public key.
+pub fn verify_with_policy(
+    document: &[u8],
+    policy: &VerificationPolicy,
+) -> Result<VerifiedAttestation, EnclaveError> {
+    let doc = verify_attestation_with_root(document, &policy.root_sha256)?;
+
+    if doc.timestamp > policy.now_ms + CLOCK_SKEW_MS {
+        return Err(EnclaveError::Unauthorized(
+            "Attestation timestamp is in the future".to_string(),
+        ));
+    }
+    if let Some(max_age_ms) = policy.max_age_ms {
+        if policy.now_ms.saturating_sub(doc.timestamp) > max_age_ms {
+            return Err(EnclaveError::Unauthorized("Attestation is too old".to_string()));
+        }
+    }
+
+    for (index, expected) in &policy.expected_pcrs {
+        match doc.pcrs.get(index) {
+            Some(actual) if actual.as_slice() == expected.as_slice() => {}
+            Some(actual) => {
+                return Err(EnclaveError::Unauthorized(format!(
+                    "PCR{} mismatch: expected {}, got {}",
+                    index,
+                    Hex::encode(expected),
+                    Hex::encode(actual.as_slice())
+                )))
+            }
+            None => {
+                return Err(EnclaveError::Unauthorized(format!(
+                    "Attestation has no PCR{}",
+                    index
+                )))
+            }
+        }
+    }
+
+    let nonce = doc.nonce.map(|nonce| nonce.into_vec());
+    if let Some(expected) = &policy.expected_nonce {
+        if nonce.as_ref() != Some(expected) {
+            return Err(EnclaveError::Unauthorized("Nonce mismatch".to_string()));
+        }
+    }
+
+    let public_key = doc
+        .public_key
+        .ok_or_else(|| EnclaveError::InvalidRequest("Attestation has no public key".to_string()))?
+        .into_vec();
+
+    Ok(VerifiedAttestation {
+        module_id: doc.module_id,
+        timestamp_ms: doc.timestamp,
+        pcrs: doc
+            .pcrs
+            .into_iter()
+            .map(|(index, value)| (index, value.into_vec()))
+            .collect(),
+        public_key,
+        user_data: doc.user_data.map(|data| data.into_vec()),
+        nonce,
+    })
+}
+
+/// The cabundle is ordered from the root to the last intermediate, the leaf
+/// certificate follows it. Every certificate must be valid at the document
+/// timestamp and signed by its predecessor, and every cabundle certificate
+/// must be a CA.
+fn verify_certificate_chain(doc: &AttestationDoc, root_sha256: &str) -> Result<(), EnclaveError> {
+    let root = doc
+        .cabundle
+        .first()
+        .ok_or_else(|| EnclaveError::InvalidRequest("Empty CA bundle".to_string()))?;
+    if Hex::encode(Sha256::digest(root.as_slice())) != root_sha256.to_lowercase() {
+        return Err(EnclaveError::Unauthorized(
+            "Attestation is not rooted in the pinned root certificate".to_string(),
+        ));
+    }
+
+    let time = ASN1Time::from_timestamp((doc.timestamp / 1000) as i64)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid timestamp: {}", e)))?;
+
+    let mut certificates = Vec::with_capacity(doc.cabundle.len() + 1);
+    for der in doc.cabundle.iter().chain(std::iter::once(&doc.certificate)) {
+        let (_, certificate) = X509Certificate::from_der(der.as_slice())
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid certificate: {}", e)))?;
+        certificates.push(certificate);
+    }
+
+    for (i, certificate) in certificates.iter().enumerate() {
+        if i < doc.cabundle.len() && !certificate.is_ca() {
+            return Err(EnclaveError::Unauthorized(format!(
+                "CA bundle certificate {} is not a CA",
+                certificate.subject()
+            )));
+        }
+        if !certificate.validity().is_valid_at(time) {
+            return Err(EnclaveError::Unauthorized(format!(
+                "Certificate {} is not valid at the attestation timestamp",
+                certificate.subject()
+            )));
+        }
+        let issuer = if i == 0 { certificate } else { &certificates[i - 1] };
+        certificate
+            .verify_signature(Some(issuer.public_key()))
+            .map_err(|e| {
+                EnclaveError::Unauthorized(format!(
+                    "Invalid signature on certificate {}: {}",
+                    certificate.subject(),
+                    e
+                ))
+            })?;
+    }
+    Ok(())
+}
+
+/// Verify the ES384 signature over the COSE Sig_structure with the leaf certificate key.
+fn verify_cose_signature(
+    certificate: &[u8],
+    protected: &[u8],
+    payload: &[u8],
+    signature: &[u8],
+) -> Result<(), EnclaveError> {
+    let (_, leaf) = X509Certificate::from_der(certificate)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid leaf certificate: {}", e)))?;
+    let key = VerifyingKey::from_sec1_bytes(&leaf.public_key().subject_public_key.data)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid leaf public key: {}", e)))?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid COSE signature: {}", e)))?;
+
+    let sig_structure = serde_cbor::to_vec(&Value::Array(vec![
+        Value::Text("Signature1".to_string()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(vec![]),
+        Value::Bytes(payload.to_vec()),
+    ]))
+    .map_err(|e| EnclaveError::Internal(format!("Failed to encode Sig_structure: {}", e)))?;
+
+    key.verify(&sig_structure, &signature).map_err(|_| {
+        EnclaveError::Unauthorized("Attestation signature verification failed".to_string())
+    })
+}
+
+fn serialize_hex<S: serde::Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&Hex::encode(value))
+}
+
+fn serialize_optional_hex<S: serde::Serializer>(
+    value: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.serialize_some(&Hex::encode(value)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_pcrs<S: serde::Serializer>(
+    pcrs: &BTreeMap<usize, Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(pcrs.iter().map(|(index, value)| (index, Hex::encode(value))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nsm_api::api::Digest;
+
+    /// Self-signed P-384 end-entity certificate (CA:FALSE), valid until 2126.
+    const NON_CA_CERTIFICATE: &str = concat!(
+        "308201cb30820151a003020102021432075599c3605cf3feadc293839bbf7d42254f90300a06082a8648ce3d04030230",
+        "1d311b301906035504030c126e617574696c75732074657374206c6561663020170d3236313031383139333534355a18",
+        "0f32313236303932343139333534355a301d311b301906035504030c126e617574696c75732074657374206c65616630",
+        "76301006072a8648ce3d020106052b81040022036200048f866f0ab9250097d2570d0461ea0e92811bfabd68a355129c",
+        "2c87f907b37aa836ff4888d69f9fad049695404e46fee6a09f4045fd1787cad11158a8f1c60872e62ad33152d3f5d54a",
+        "65561f02a54738c335af0a81e15ab7be34bf8217fc1dcda350304e301d0603551d0e041604144efbc8f1732207961905",
+        "23efd254a624770f3c45301f0603551d230418301680144efbc8f173220796190523efd254a624770f3c45300c060355",
+        "1d130101ff04023000300a06082a8648ce3d0403020368003065023100b21524df63beab23303b6d46b1b7660f2b3729",
+        "13d2dbf8cdd5be053c9ff7f52c45cefbaa40639a45d4de290560c3375a02303bda522881ce0743a642853b692a0d9441",
+        "0b5eb56341d2900d0035d6ad478e8af15df53bbd87c0865e0758c7f7583e47",
+    );
+
+    fn error_message<T>(result: Result<T, EnclaveError>) -> String {
+        match result {
+            Err(EnclaveError::InvalidRequest(message)) | Err(EnclaveError::Unauthorized(message)) => message,
+            other => panic!("expected a verification error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn cose(items: Vec<Value>) -> Vec<u8> {
+        serde_cbor::to_vec(&Value::Array(items)).unwrap()
+    }
+
+    /// Unsigned COSE_Sign1 document carrying the given certificates.
+    fn document(cabundle: Vec<Vec<u8>>, certificate: Vec<u8>) -> Vec<u8> {
+        let payload = AttestationDoc::new(
+            "i-test".to_string(),
+            Digest::SHA384,
+            1_700_000_000_000,
+            BTreeMap::from([(0, vec![0u8; 48])]),
+            certificate,
+            cabundle,
+            None,
+            None,
+            Some(vec![7u8; 32]),
+        )
+        .to_binary();
+        cose(vec![
+            Value::Bytes(vec![]),
+            Value::Map(BTreeMap::new()),
+            Value::Bytes(payload),
+            Value::Bytes(vec![0u8; 96]),
+        ])
+    }
+
+    #[test]
+    fn test_malformed_cose() {
+        assert!(error_message(parse_cose_sign1(b"not cbor \xff"))
+            .starts_with("Invalid COSE_Sign1 encoding"));
+        assert_eq!(
+            error_message(parse_cose_sign1(&cose(vec![Value::Bytes(vec![]); 3]))),
+            "Attestation document is not a COSE_Sign1 array"
+        );
+        assert_eq!(
+            error_message(parse_cose_sign1(&cose(vec![Value::Integer(0); 4]))),
+            "Malformed COSE_Sign1 structure"
+        );
+
+        let tagged = serde_cbor::to_vec(&Value::Tag(
+            COSE_SIGN1_TAG,
+            Box::new(Value::Array(vec![Value::Bytes(b"payload".to_vec()); 4])),
+        ))
+        .unwrap();
+        assert!(parse_cose_sign1(&tagged).is_ok());
+        assert!(error_message(verify_attestation(&tagged)).starts_with("Invalid attestation payload"));
+    }
+
+    #[test]
+    fn test_wrong_root() {
+        let certificate = Hex::decode(NON_CA_CERTIFICATE).unwrap();
+        let unpinned = document(vec![certificate.clone()], certificate);
+        assert_eq!(
+            error_message(verify_attestation(&unpinned)),
+            "Attestation is not rooted in the pinned root certificate"
+        );
+        assert_eq!(
+            error_message(verify_attestation(&document(vec![], vec![]))),
+            "Empty CA bundle"
+        );
+    }
+
+    #[test]
+    fn test_non_ca_bundle() {
+        let certificate = Hex::decode(NON_CA_CERTIFICATE).unwrap();
+        let root_sha256 = Hex::encode(Sha256::digest(&certificate));
+        let unsigned = document(vec![certificate.clone()], certificate);
+        assert_eq!(
+            error_message(verify_attestation_with_root(&unsigned, &root_sha256)),
+            "CA bundle certificate CN=nautilus test leaf is not a CA"
+        );
+    }
+
+    /// Signed documents need the test CA of the mock, run with `--features mock-nsm`.
+    #[cfg(feature = "mock-nsm")]
+    mod mock {
+        use super::*;
+        use crate::nsm::mock::MockNsm;
+        use crate::nsm::AttestationProvider;
+
+        const PCR_LENGTH: usize = 48;
+
+        fn now_ms() -> u64 {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+        }
+
+        fn mock() -> MockNsm {
+            MockNsm::new([vec![1u8; PCR_LENGTH], vec![2u8; PCR_LENGTH], vec![3u8; PCR_LENGTH]]).unwrap()
+        }
+
+        fn policy(nsm: &MockNsm, now_ms: u64) -> VerificationPolicy {
+            VerificationPolicy {
+                root_sha256: nsm.root_fingerprint(),
+                ..VerificationPolicy::aws(now_ms)
+            }
+        }
+
+        #[test]
+        fn test_policy_accepts_matching_document() {
+            let nsm = mock();
+            let document = nsm.attest(vec![7u8; 32], None, Some(b"nonce".to_vec())).unwrap();
+            let mut policy = policy(&nsm, now_ms());
+            policy.expected_pcrs.insert(0, vec![1u8; PCR_LENGTH]);
+            policy.max_age_ms = Some(60_000);
+            policy.expected_nonce = Some(b"nonce".to_vec());
+
+            let verified = verify_with_policy(&document, &policy).unwrap();
+            assert_eq!(verified.public_key, vec![7u8; 32]);
+            assert_eq!(verified.nonce, Some(b"nonce".to_vec()));
+        }
+
+        #[test]
+        fn test_pcr_mismatch() {
+            let nsm = mock();
+            let document = nsm.attest(vec![7u8; 32], None, None).unwrap();
+            let mut policy = policy(&nsm, now_ms());
+            policy.expected_pcrs.insert(1, vec![9u8; PCR_LENGTH]);
+            assert!(error_message(verify_with_policy(&document, &policy)).starts_with("PCR1 mismatch"));
+        }
+
+        #[test]
+        fn test_max_age() {
+            let nsm = mock();
+            let document = nsm.attest(vec![7u8; 32], None, None).unwrap();
+            let mut policy = policy(&nsm, now_ms() + 10 * 60_000);
+            policy.max_age_ms = Some(60_000);
+            assert_eq!(error_message(verify_with_policy(&document, &policy)), "Attestation is too old");
+        }
+
+        #[test]
+        fn test_future_timestamp() {
+            let nsm = mock();
+            let document = nsm.attest(vec![7u8; 32], None, None).unwrap();
+            let policy = policy(&nsm, now_ms() - 10 * 60_000);
+            assert_eq!(
+                error_message(verify_with_policy(&document, &policy)),
+                "Attestation timestamp is in the future"
+            );
+        }
+
+        #[test]
+        fn test_nonce_mismatch() {
+            let nsm = mock();
+            let mut policy = policy(&nsm, now_ms());
+            policy.expected_nonce = Some(b"expected".to_vec());
+
+            let document = nsm.attest(vec![7u8; 32], None, Some(b"other".to_vec())).unwrap();
+            assert_eq!(error_message(verify_with_policy(&document, &policy)), "Nonce mismatch");
+            let document = nsm.attest(vec![7u8; 32], None, None).unwrap();
+            assert_eq!(error_message(verify_with_policy(&document, &policy)), "Nonce mismatch");
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compute the PCRs of an enclave image and print the `update_pcrs` call that
+//! registers them, so anyone rebuilding the image can audit the deployed values.
+//!
+//! cargo run --bin compute_pcrs -- --eif out/nitro.eif
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use nautilus_server::eif::compute_pcrs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(about = "Compute PCR0-2 of an EIF image")]
+struct Args {
+    /// EIF image built from the Containerfile
+    #[arg(long, default_value = "out/nitro.eif")]
+    eif: PathBuf,
+    /// Enclave package ID
+    #[arg(long, default_value = "$ENCLAVE_PACKAGE_ID")]
+    package: String,
+    /// Type argument of the enclave config, the app's one time witness
+    #[arg(long, default_value = "$EXAMPLES_PACKAGE_ID::$MODULE_NAME::$OTW_NAME")]
+    type_arg: String,
+    /// EnclaveConfig object ID
+    #[arg(long, default_value = "$ENCLAVE_CONFIG_OBJECT_ID")]
+    config: String,
+    /// Cap object ID of the enclave config
+    #[arg(long, default_value = "$CAP_OBJECT_ID")]
+    cap: String,
+    /// Print the PCRs as JSON instead of the call
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let image = std::fs::read(&args.eif).with_context(|| format!("Failed to read {}", args.eif.display()))?;
+    let pcrs = compute_pcrs(&image).map_err(|e| anyhow!("{}", e))?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&pcrs)?);
+        return Ok(());
+    }
+
+    println!("PCR0={}", pcrs.pcr0);
+    println!("PCR1={}", pcrs.pcr1);
+    println!("PCR2={}", pcrs.pcr2);
+    println!();
+    let [pcr0, pcr1, pcr2] = pcrs.update_pcrs_args();
+    println!(
+        "sui client call --function update_pcrs --module enclave --package {} --type-args \"{}\" --args {} {} {} {} {}",
+        args.package, args.type_arg, args.config, args.cap, pcr0, pcr1, pcr2
+    );
+    Ok(())
+}
@@ -0,0 +1,120 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Local stand-in for the host gas sponsor. Co-signs transactions whose gas is
+//! paid by the sponsor key, up to a per-transaction and per-sender budget.
+//!
+//! SPONSOR_KEY=<base64 key from sui.keystore> cargo run --bin sponsor_service -- --port 3001
+//!
+//! Then start the enclave with SPONSOR_URL=http://localhost:3001 and
+//! SPONSOR_GAS_COIN set to a SUI coin owned by the sponsor address.
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use fastcrypto::encoding::{Base64, Encoding};
+use nautilus_server::examples::trading::chain::sign_transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use sui_types::base_types::SuiAddress;
+use sui_types::crypto::{EncodeDecodeBase64, SuiKeyPair};
+use sui_types::transaction::{TransactionData, TransactionDataAPI};
+use tokio::sync::Mutex;
+use tracing::info;
+use warp::Filter;
+
+#[derive(Parser, Debug)]
+#[command(about = "Local gas sponsor for the trading enclave")]
+struct Args {
+    /// Port to listen on
+    #[arg(long, default_value_t = 3001)]
+    port: u16,
+    /// Largest gas budget of a single transaction (MIST)
+    #[arg(long, default_value_t = 100_000_000)]
+    max_gas_budget: u64,
+    /// Total gas budgets a sender may have signed (MIST)
+    #[arg(long, default_value_t = 2_000_000_000)]
+    sender_budget: u64,
+}
+
+struct Sponsor {
+    keypair: SuiKeyPair,
+    address: SuiAddress,
+    max_gas_budget: u64,
+    sender_budget: u64,
+    /// Gas budgets signed per sender
+    signed: Mutex<HashMap<SuiAddress, u64>>,
+}
+
+#[derive(Deserialize)]
+struct SponsorRequest {
+    tx_bytes: String,
+}
+
+#[derive(Serialize)]
+struct SponsorResponse {
+    signature: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let key = std::env::var("SPONSOR_KEY").map_err(|_| anyhow!("SPONSOR_KEY is not set"))?;
+    let keypair = SuiKeyPair::decode_base64(&key).map_err(|e| anyhow!("Invalid SPONSOR_KEY: {}", e))?;
+    let address = SuiAddress::from(&keypair.public());
+
+    let sponsor = Arc::new(Sponsor {
+        keypair,
+        address,
+        max_gas_budget: args.max_gas_budget,
+        sender_budget: args.sender_budget,
+        signed: Mutex::new(HashMap::new()),
+    });
+    println!("Sponsoring gas from {} on port {}", address, args.port);
+
+    let route = warp::path("sponsor")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || sponsor.clone()))
+        .and_then(sponsor_handler);
+    warp::serve(route).run(([127, 0, 0, 1], args.port)).await;
+    Ok(())
+}
+
+async fn sponsor_handler(
+    request: SponsorRequest,
+    sponsor: Arc<Sponsor>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match sponsor_transaction(&sponsor, &request).await {
+        Ok(signature) => Ok(Box::new(warp::reply::json(&SponsorResponse { signature }))),
+        Err(e) => Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))),
+    }
+}
+
+async fn sponsor_transaction(sponsor: &Sponsor, request: &SponsorRequest) -> Result<String> {
+    let tx_bytes = Base64::decode(&request.tx_bytes).map_err(|e| anyhow!("Invalid tx_bytes: {}", e))?;
+    let tx_data: TransactionData = bcs::from_bytes(&tx_bytes)?;
+
+    if tx_data.gas_owner() != sponsor.address {
+        return Err(anyhow!("Gas is not paid by {}", sponsor.address));
+    }
+    let gas_budget = tx_data.gas_budget();
+    if gas_budget > sponsor.max_gas_budget {
+        return Err(anyhow!("Gas budget {} exceeds {}", gas_budget, sponsor.max_gas_budget));
+    }
+
+    let sender = tx_data.sender();
+    let mut signed = sponsor.signed.lock().await;
+    let used = signed.entry(sender).or_insert(0);
+    if *used + gas_budget > sponsor.sender_budget {
+        return Err(anyhow!("Sponsorship budget of {} is exhausted", sender));
+    }
+    *used += gas_budget;
+
+    info!("Sponsoring {} MIST for {}", gas_budget, sender);
+    let signature = sign_transaction(&sponsor.keypair, &tx_data);
+    Ok(Base64::encode(signature.as_ref()))
+}
@@ -0,0 +1,126 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verify an enclave attestation document offline before registering it.
+//!
+//! cargo run --bin verify_attestation -- --url http://<enclave>:3000 \
+//!     --pcr0 <hex> --pcr1 <hex> --pcr2 <hex>
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::hash::{HashFunction, Sha256};
+use nautilus_server::attestation::{
+    verify_with_policy, VerificationPolicy, AWS_NITRO_ROOT_CERT_SHA256,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(about = "Verify a Nitro enclave attestation document")]
+struct Args {
+    /// Hex encoded attestation document
+    #[arg(long, conflicts_with_all = ["file", "url"])]
+    attestation: Option<String>,
+    /// File holding the hex encoded attestation document
+    #[arg(long, conflicts_with = "url")]
+    file: Option<PathBuf>,
+    /// Enclave base URL to fetch `/attestation` from
+    #[arg(long)]
+    url: Option<String>,
+    /// Trusted root certificate (PEM or DER) instead of the AWS Nitro root
+    #[arg(long, conflicts_with = "root_sha256")]
+    root_cert: Option<PathBuf>,
+    /// Hex SHA-256 fingerprint of the trusted root, e.g. the one logged by the mock NSM
+    #[arg(long)]
+    root_sha256: Option<String>,
+    /// Expected PCR0 (hex)
+    #[arg(long)]
+    pcr0: Option<String>,
+    /// Expected PCR1 (hex)
+    #[arg(long)]
+    pcr1: Option<String>,
+    /// Expected PCR2 (hex)
+    #[arg(long)]
+    pcr2: Option<String>,
+    /// Maximum age of the document in seconds
+    #[arg(long, default_value_t = 300)]
+    max_age_secs: u64,
+    /// Expected nonce (hex), also sent to the enclave when fetching with --url
+    #[arg(long)]
+    nonce: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let document = Hex::decode(load_document(&args).await?.trim())
+        .map_err(|e| anyhow!("Invalid attestation hex: {}", e))?;
+
+    let mut expected_pcrs = BTreeMap::new();
+    for (index, pcr) in [(0, &args.pcr0), (1, &args.pcr1), (2, &args.pcr2)] {
+        if let Some(pcr) = pcr {
+            expected_pcrs.insert(index, decode_hex(pcr, "PCR")?);
+        }
+    }
+
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis() as u64;
+    let policy = VerificationPolicy {
+        root_sha256: match (&args.root_cert, &args.root_sha256) {
+            (Some(path), _) => root_fingerprint(path)?,
+            (None, Some(fingerprint)) => fingerprint.clone(),
+            (None, None) => AWS_NITRO_ROOT_CERT_SHA256.to_string(),
+        },
+        expected_pcrs,
+        max_age_ms: Some(args.max_age_secs * 1000),
+        now_ms,
+        expected_nonce: args.nonce.as_deref().map(|nonce| decode_hex(nonce, "nonce")).transpose()?,
+    };
+
+    let verified = verify_with_policy(&document, &policy).map_err(|e| anyhow!("{}", e))?;
+    println!("{}", serde_json::to_string_pretty(&verified)?);
+    Ok(())
+}
+
+async fn load_document(args: &Args) -> Result<String> {
+    if let Some(attestation) = &args.attestation {
+        return Ok(attestation.clone());
+    }
+    if let Some(path) = &args.file {
+        return std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()));
+    }
+    let url = args
+        .url
+        .as_ref()
+        .ok_or_else(|| anyhow!("One of --attestation, --file or --url is required"))?;
+
+    let mut request = reqwest::Client::new().get(format!("{}/attestation", url.trim_end_matches('/')));
+    if let Some(nonce) = &args.nonce {
+        request = request.query(&[("nonce", nonce)]);
+    }
+    let response: serde_json::Value = request.send().await?.error_for_status()?.json().await?;
+    response["attestation"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Response has no attestation field"))
+}
+
+/// SHA-256 fingerprint of a PEM or DER certificate.
+fn root_fingerprint(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let der = if bytes.starts_with(b"-----BEGIN") {
+        let (_, pem) = x509_parser::pem::parse_x509_pem(&bytes)
+            .map_err(|e| anyhow!("Invalid PEM certificate: {}", e))?;
+        pem.contents
+    } else {
+        bytes
+    };
+    Ok(Hex::encode(Sha256::digest(&der)))
+}
+
+fn decode_hex(value: &str, what: &str) -> Result<Vec<u8>> {
+    Hex::decode(value.trim_start_matches("0x")).map_err(|e| anyhow!("Invalid {} hex: {}", what, e))
+}
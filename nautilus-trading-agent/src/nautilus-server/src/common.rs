@@ -1,30 +1,26 @@
 // Copyright (c), Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::enclave_keys::EnclaveKeys;
 use crate::AppState;
 use crate::EnclaveError;
-use fastcrypto::traits::Signer;
 use fastcrypto::{encoding::Encoding, traits::ToFromBytes};
-use fastcrypto::{encoding::Hex, traits::KeyPair as FcKeyPair};
-use nsm_api::api::{Request as NsmRequest, Response as NsmResponse};
-use nsm_api::driver;
+use fastcrypto::encoding::Hex;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_bytes::ByteBuf;
 use serde_repr::Deserialize_repr;
 use serde_repr::Serialize_repr;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::info;
 
-use fastcrypto::ed25519::Ed25519KeyPair;
 /// ==== COMMON TYPES ====
 
 /// Intent message wrapper struct containing the intent scope and timestamp.
 /// This standardizes the serialized payload for signing.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IntentMessage<T: Serialize> {
     pub intent: IntentScope,
     pub timestamp_ms: u64,
@@ -33,10 +29,20 @@ pub struct IntentMessage<T: Serialize> {
 
 /// Intent scope enum. Add new scope here if needed, each corresponds to a
 /// scope for signing. Replace in with your own intent per message type being signed by the enclave.
-#[derive(Serialize_repr, Deserialize_repr, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum IntentScope {
     ProcessData = 0,
+    /// Entries of the trade journal
+    Journal = 1,
+    /// Handover from a rotated enclave key to its successor
+    KeyHandover = 2,
+    /// Binding of the trading wallet to a marketplace agent
+    AgentBinding = 3,
+    /// Performance fee settlement statements
+    Settlement = 4,
+    /// Performance reports for the marketplace
+    Performance = 5,
 }
 
 impl<T: Serialize + Debug> IntentMessage<T> {
@@ -50,10 +56,13 @@ impl<T: Serialize + Debug> IntentMessage<T> {
 }
 
 /// Wrapper struct containing the response (the intent message) and signature.
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ProcessedDataResponse<T> {
     pub response: T,
     pub signature: String,
+    /// Epoch of the enclave key that signed the response
+    #[serde(default)]
+    pub key_epoch: u64,
 }
 
 /// Wrapper struct containing the request payload.
@@ -64,23 +73,12 @@ pub struct ProcessDataRequest<T> {
 
 /// Sign the bcs bytes of the the payload with keypair.
 pub fn to_signed_response<T: Serialize + Clone>(
-    kp: &Ed25519KeyPair,
+    kp: &EnclaveKeys,
     payload: T,
     timestamp_ms: u64,
     intent: IntentScope,
 ) -> ProcessedDataResponse<IntentMessage<T>> {
-    let intent_msg = IntentMessage {
-        intent,
-        timestamp_ms,
-        data: payload.clone(),
-    };
-
-    let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
-    let sig: fastcrypto::ed25519::Ed25519Signature = kp.sign(&signing_payload);
-    ProcessedDataResponse {
-        response: intent_msg,
-        signature: Hex::encode(sig),
-    }
+    kp.current().sign_response(payload, timestamp_ms, intent)
 }
 
 /// ==== HEALTHCHECK, GET ATTESTASTION ENDPOINT IMPL ====
@@ -90,40 +88,36 @@ pub fn to_signed_response<T: Serialize + Clone>(
 pub struct GetAttestationResponse {
     /// Attestation document serialized in Hex.
     pub attestation: String,
+    /// Preimage of the document's user_data, for verifiers to recompute it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_data: Option<serde_json::Value>,
 }
 
-/// Endpoint that returns an attestation committed
-/// to the enclave's public key.
+/// Maximum nonce length accepted by the NSM.
+pub const MAX_NONCE_LENGTH: usize = 512;
+
+/// Endpoint that returns an attestation committed to the enclave's public
+/// key, and optionally to a caller supplied nonce and application user data.
 pub async fn get_attestation(
     state: &Arc<AppState>,
+    nonce: Option<Vec<u8>>,
+    user_data: Option<Vec<u8>>,
 ) -> Result<GetAttestationResponse, EnclaveError> {
     info!("get attestation called");
 
-    let pk = state.eph_kp.public();
-    let fd = driver::nsm_init();
+    if nonce.as_ref().is_some_and(|nonce| nonce.len() > MAX_NONCE_LENGTH) {
+        return Err(EnclaveError::InvalidRequest(format!(
+            "Nonce must be at most {} bytes",
+            MAX_NONCE_LENGTH
+        )));
+    }
 
-    // Send attestation request to NSM driver with public key set.
-    let request = NsmRequest::Attestation {
+    let pk = state.eph_kp.public();
+    let document = state.attestation.attest(pk.as_bytes().to_vec(), user_data, nonce)?;
+    Ok(GetAttestationResponse {
+        attestation: Hex::encode(document),
         user_data: None,
-        nonce: None,
-        public_key: Some(ByteBuf::from(pk.as_bytes().to_vec())),
-    };
-
-    let response = driver::nsm_process_request(fd, request);
-    match response {
-        NsmResponse::Attestation { document } => {
-            driver::nsm_exit(fd);
-            Ok(GetAttestationResponse {
-                attestation: Hex::encode(document),
-            })
-        }
-        _ => {
-            driver::nsm_exit(fd);
-            Err(EnclaveError::GenericError(
-                "unexpected response".to_string(),
-            ))
-        }
-    }
+    })
 }
 
 /// Health check response.
@@ -131,19 +125,62 @@ pub async fn get_attestation(
 pub struct HealthCheckResponse {
     /// Hex encoded public key booted on enclave.
     pub pk: String,
+    /// Epoch of that key, incremented on every rotation
+    pub key_epoch: u64,
     /// Status of endpoint connectivity checks
     pub endpoints_status: HashMap<String, bool>,
+    /// Round trip time of the reachable endpoints
+    pub endpoints_latency_ms: HashMap<String, u64>,
+    /// Attestation provider in use, "nitro" or "mock"
+    pub attestation_provider: String,
+    /// Whether the attestation provider can produce documents
+    pub nsm_available: bool,
+    /// Application specific status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<serde_json::Value>,
+}
+
+impl HealthCheckResponse {
+    /// All endpoints are critical dependencies, the NSM is reported but not
+    /// required so the server can be run outside an enclave.
+    pub fn is_healthy(&self) -> bool {
+        self.endpoints_status.values().all(|reachable| *reachable)
+    }
 }
 
 /// Endpoint that health checks the enclave connectivity to all
 /// domains and returns the enclave's public key.
-pub fn health_check() -> HealthCheckResponse {
-    // Simplified health check - just return OK status
+pub async fn health_check(state: &Arc<AppState>, endpoints: &[&str]) -> HealthCheckResponse {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap_or_else(|_| Client::new());
+
     let mut endpoints_status = HashMap::new();
-    endpoints_status.insert("system".to_string(), true);
-    
+    let mut endpoints_latency_ms = HashMap::new();
+    for endpoint in endpoints {
+        let start = Instant::now();
+        // Any response that is not a server error means the endpoint is up
+        let reachable = match client.get(*endpoint).send().await {
+            Ok(response) => !response.status().is_server_error(),
+            Err(e) => {
+                info!("Endpoint {} unreachable: {}", endpoint, e);
+                false
+            }
+        };
+        if reachable {
+            endpoints_latency_ms.insert(endpoint.to_string(), start.elapsed().as_millis() as u64);
+        }
+        endpoints_status.insert(endpoint.to_string(), reachable);
+    }
+
     HealthCheckResponse {
-        pk: "mock_public_key".to_string(), // In production, use actual public key
+        pk: Hex::encode(state.eph_kp.public().as_bytes()),
+        key_epoch: state.eph_kp.epoch(),
         endpoints_status,
+        endpoints_latency_ms,
+        attestation_provider: state.attestation.name().to_string(),
+        nsm_available: state.attestation.is_available(),
+        app: None,
     }
 }
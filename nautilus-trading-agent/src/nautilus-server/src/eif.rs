@@ -0,0 +1,198 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Reader for Enclave Image Files (EIF) as written by `nitro-cli build-enclave`,
+// computing the PCRs the NSM will report for the image:
+//
+// - PCR0 covers the kernel, the command line and every ramdisk.
+// - PCR1 covers the kernel, the command line and the first (bootstrap) ramdisk.
+// - PCR2 covers the remaining (application) ramdisks.
+//
+// Each PCR is a single TPM style extend of an all zero register with the
+// SHA-384 of the covered section bodies. Signature and metadata sections are
+// not measured.
+
+use crate::EnclaveError;
+use fastcrypto::encoding::{Encoding, Hex};
+use serde::Serialize;
+use sha2::{Digest, Sha384};
+
+const EIF_MAGIC: [u8; 4] = *b".eif";
+const MAX_SECTIONS: usize = 32;
+/// magic, version, flags, default memory and cpus, reserved, section count,
+/// section offsets and sizes, unused and crc32.
+const EIF_HEADER_SIZE: usize = 4 + 2 + 2 + 8 + 8 + 2 + 2 + MAX_SECTIONS * 8 * 2 + 4 + 4;
+/// section type, flags and size.
+const SECTION_HEADER_SIZE: usize = 2 + 2 + 8;
+const PCR_LENGTH: usize = 48;
+
+/// Section types of an EIF.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionType {
+    Kernel,
+    Cmdline,
+    Ramdisk,
+    Signature,
+    Metadata,
+}
+
+impl SectionType {
+    fn from_u16(value: u16) -> Result<Self, EnclaveError> {
+        match value {
+            1 => Ok(Self::Kernel),
+            2 => Ok(Self::Cmdline),
+            3 => Ok(Self::Ramdisk),
+            4 => Ok(Self::Signature),
+            5 => Ok(Self::Metadata),
+            other => Err(EnclaveError::GenericError(format!(
+                "Unknown EIF section type {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// PCR0, PCR1 and PCR2 of an enclave image.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImagePcrs {
+    pub pcr0: String,
+    pub pcr1: String,
+    pub pcr2: String,
+}
+
+impl ImagePcrs {
+    /// Arguments of `enclave::update_pcrs` after the config and cap IDs.
+    pub fn update_pcrs_args(&self) -> [String; 3] {
+        [
+            format!("0x{}", self.pcr0),
+            format!("0x{}", self.pcr1),
+            format!("0x{}", self.pcr2),
+        ]
+    }
+}
+
+/// Compute the PCRs of an EIF image.
+pub fn compute_pcrs(image: &[u8]) -> Result<ImagePcrs, EnclaveError> {
+    if image.len() < EIF_HEADER_SIZE || image[..4] != EIF_MAGIC {
+        return Err(EnclaveError::GenericError("Not an EIF image".to_string()));
+    }
+    let num_sections = read_u16(image, 4 + 2 + 2 + 8 + 8 + 2) as usize;
+    if num_sections > MAX_SECTIONS {
+        return Err(EnclaveError::GenericError(format!(
+            "EIF declares {} sections, at most {} are supported",
+            num_sections, MAX_SECTIONS
+        )));
+    }
+    let offsets_start = 4 + 2 + 2 + 8 + 8 + 2 + 2;
+
+    let mut image_hasher = Sha384::new();
+    let mut bootstrap_hasher = Sha384::new();
+    let mut app_hasher = Sha384::new();
+    let mut ramdisks = 0;
+
+    for index in 0..num_sections {
+        let offset = read_u64(image, offsets_start + index * 8) as usize;
+        let (section_type, body) = read_section(image, offset)?;
+        match section_type {
+            SectionType::Kernel | SectionType::Cmdline => {
+                image_hasher.update(body);
+                bootstrap_hasher.update(body);
+            }
+            SectionType::Ramdisk => {
+                image_hasher.update(body);
+                if ramdisks == 0 {
+                    bootstrap_hasher.update(body);
+                } else {
+                    app_hasher.update(body);
+                }
+                ramdisks += 1;
+            }
+            SectionType::Signature | SectionType::Metadata => {}
+        }
+    }
+
+    Ok(ImagePcrs {
+        pcr0: Hex::encode(extend(image_hasher)),
+        pcr1: Hex::encode(extend(bootstrap_hasher)),
+        pcr2: Hex::encode(extend(app_hasher)),
+    })
+}
+
+fn read_section(image: &[u8], offset: usize) -> Result<(SectionType, &[u8]), EnclaveError> {
+    let body_start = offset
+        .checked_add(SECTION_HEADER_SIZE)
+        .filter(|end| *end <= image.len())
+        .ok_or_else(|| EnclaveError::GenericError("EIF section header out of bounds".to_string()))?;
+    let section_type = SectionType::from_u16(read_u16(image, offset))?;
+    let size = read_u64(image, offset + 4) as usize;
+    let body_end = body_start
+        .checked_add(size)
+        .filter(|end| *end <= image.len())
+        .ok_or_else(|| EnclaveError::GenericError("EIF section body out of bounds".to_string()))?;
+    Ok((section_type, &image[body_start..body_end]))
+}
+
+/// Extend an all zero PCR with the digest of the measured data.
+fn extend(data: Sha384) -> Vec<u8> {
+    let mut pcr = Sha384::new();
+    pcr.update([0u8; PCR_LENGTH]);
+    pcr.update(data.finalize());
+    pcr.finalize().to_vec()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_image(sections: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut header = vec![0u8; EIF_HEADER_SIZE];
+        header[..4].copy_from_slice(&EIF_MAGIC);
+        header[26..28].copy_from_slice(&(sections.len() as u16).to_be_bytes());
+        let mut body = Vec::new();
+        for (index, (section_type, data)) in sections.iter().enumerate() {
+            let offset = (EIF_HEADER_SIZE + body.len()) as u64;
+            header[28 + index * 8..36 + index * 8].copy_from_slice(&offset.to_be_bytes());
+            body.extend_from_slice(&section_type.to_be_bytes());
+            body.extend_from_slice(&0u16.to_be_bytes());
+            body.extend_from_slice(&(data.len() as u64).to_be_bytes());
+            body.extend_from_slice(data);
+        }
+        [header, body].concat()
+    }
+
+    fn expected(parts: &[&[u8]]) -> String {
+        let mut hasher = Sha384::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        Hex::encode(extend(hasher))
+    }
+
+    #[test]
+    fn test_compute_pcrs() {
+        let image = build_image(&[
+            (1, &b"kernel"[..]),
+            (2, &b"cmdline"[..]),
+            (3, &b"bootstrap"[..]),
+            (3, &b"app"[..]),
+            (4, &b"signature"[..]),
+        ]);
+        let pcrs = compute_pcrs(&image).unwrap();
+        assert_eq!(pcrs.pcr0, expected(&[&b"kernel"[..], &b"cmdline"[..], &b"bootstrap"[..], &b"app"[..]]));
+        assert_eq!(pcrs.pcr1, expected(&[&b"kernel"[..], &b"cmdline"[..], &b"bootstrap"[..]]));
+        assert_eq!(pcrs.pcr2, expected(&[&b"app"[..]]));
+        assert_eq!(pcrs.pcr0.len(), PCR_LENGTH * 2);
+
+        assert!(compute_pcrs(&image[..EIF_HEADER_SIZE + 4]).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// The enclave's rotating signing key. Every key belongs to an epoch, starting
+// at 0 on boot. On rotation the old key signs a `KeyHandover` naming its
+// successor and stays available for a grace period, after which it is dropped.
+// The epoch of the signing key is reported with every signed response.
+
+use crate::common::{IntentMessage, IntentScope, ProcessedDataResponse};
+use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519PublicKey, Ed25519Signature};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, Signer, ToFromBytes};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+/// A signing key and its epoch.
+pub struct EpochKey {
+    pub epoch: u64,
+    pub keypair: Ed25519KeyPair,
+}
+
+impl EpochKey {
+    pub fn public(&self) -> &Ed25519PublicKey {
+        self.keypair.public()
+    }
+
+    /// Sign the bcs bytes of the intent message wrapping `payload`.
+    pub fn sign_response<T: Serialize + Clone>(
+        &self,
+        payload: T,
+        timestamp_ms: u64,
+        intent: IntentScope,
+    ) -> ProcessedDataResponse<IntentMessage<T>> {
+        let intent_msg = IntentMessage {
+            intent,
+            timestamp_ms,
+            data: payload,
+        };
+
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        let sig: Ed25519Signature = self.keypair.sign(&signing_payload);
+        ProcessedDataResponse {
+            response: intent_msg,
+            signature: Hex::encode(sig),
+            key_epoch: self.epoch,
+        }
+    }
+}
+
+/// Statement by the key of `old_epoch` that `new_public_key` succeeds it.
+/// Signed by the old key under `IntentScope::KeyHandover`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyHandover {
+    pub old_epoch: u64,
+    pub old_public_key: Vec<u8>,
+    pub new_epoch: u64,
+    pub new_public_key: Vec<u8>,
+    /// `Enclave<T>` object registered for the new key, if any
+    pub new_enclave_object_id: Option<String>,
+    /// When the old key is destroyed
+    pub retire_at_ms: u64,
+}
+
+struct RetiringKey {
+    key: Arc<EpochKey>,
+    retire_at_ms: u64,
+}
+
+/// The current key, keys within their grace period and all handovers so far.
+pub struct EnclaveKeys {
+    current: RwLock<Arc<EpochKey>>,
+    retiring: RwLock<Vec<RetiringKey>>,
+    handovers: RwLock<Vec<ProcessedDataResponse<IntentMessage<KeyHandover>>>>,
+}
+
+impl EnclaveKeys {
+    /// Start at epoch 0 with the boot key.
+    pub fn new(keypair: Ed25519KeyPair) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(EpochKey { epoch: 0, keypair })),
+            retiring: RwLock::new(Vec::new()),
+            handovers: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn current(&self) -> Arc<EpochKey> {
+        self.current.read().expect("key lock poisoned").clone()
+    }
+
+    /// Public key of the current epoch.
+    pub fn public(&self) -> Ed25519PublicKey {
+        self.current().public().clone()
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.current().epoch
+    }
+
+    /// The key of `epoch`, if it is current or still in its grace period.
+    pub fn key(&self, epoch: u64) -> Option<Arc<EpochKey>> {
+        let current = self.current();
+        if current.epoch == epoch {
+            return Some(current);
+        }
+        self.retiring
+            .read()
+            .expect("key lock poisoned")
+            .iter()
+            .find(|retiring| retiring.key.epoch == epoch)
+            .map(|retiring| retiring.key.clone())
+    }
+
+    /// Make `next` the current key. The old key signs the handover and is kept
+    /// until `now_ms + grace_period_ms`.
+    pub fn rotate(
+        &self,
+        next: Ed25519KeyPair,
+        new_enclave_object_id: Option<String>,
+        grace_period_ms: u64,
+        now_ms: u64,
+    ) -> ProcessedDataResponse<IntentMessage<KeyHandover>> {
+        let mut current = self.current.write().expect("key lock poisoned");
+        let old = current.clone();
+        let retire_at_ms = now_ms + grace_period_ms;
+
+        let handover = old.sign_response(
+            KeyHandover {
+                old_epoch: old.epoch,
+                old_public_key: old.public().as_bytes().to_vec(),
+                new_epoch: old.epoch + 1,
+                new_public_key: next.public().as_bytes().to_vec(),
+                new_enclave_object_id,
+                retire_at_ms,
+            },
+            now_ms,
+            IntentScope::KeyHandover,
+        );
+
+        *current = Arc::new(EpochKey {
+            epoch: old.epoch + 1,
+            keypair: next,
+        });
+        self.retiring
+            .write()
+            .expect("key lock poisoned")
+            .push(RetiringKey { key: old, retire_at_ms });
+        self.handovers
+            .write()
+            .expect("key lock poisoned")
+            .push(handover.clone());
+        handover
+    }
+
+    /// Drop the keys whose grace period is over and return their epochs. The
+    /// private keys are destroyed once in-flight signers release them.
+    pub fn retire_expired(&self, now_ms: u64) -> Vec<u64> {
+        let mut retiring = self.retiring.write().expect("key lock poisoned");
+        let (expired, kept): (Vec<_>, Vec<_>) = retiring
+            .drain(..)
+            .partition(|retiring| retiring.retire_at_ms <= now_ms);
+        *retiring = kept;
+        expired.into_iter().map(|retiring| retiring.key.epoch).collect()
+    }
+
+    /// All handovers since boot, oldest first.
+    pub fn handovers(&self) -> Vec<ProcessedDataResponse<IntentMessage<KeyHandover>>> {
+        self.handovers.read().expect("key lock poisoned").clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastcrypto::traits::VerifyingKey;
+
+    #[test]
+    fn test_rotation_handover() {
+        let keys = EnclaveKeys::new(Ed25519KeyPair::generate(&mut rand::thread_rng()));
+        let old_public = keys.public();
+
+        let handover = keys.rotate(
+            Ed25519KeyPair::generate(&mut rand::thread_rng()),
+            None,
+            1_000,
+            5_000,
+        );
+        assert_eq!(keys.epoch(), 1);
+        assert_eq!(handover.key_epoch, 0);
+        assert_eq!(handover.response.data.new_public_key, keys.public().as_bytes());
+
+        let bytes = bcs::to_bytes(&handover.response).unwrap();
+        let signature = Ed25519Signature::from_bytes(&Hex::decode(&handover.signature).unwrap()).unwrap();
+        assert!(old_public.verify(&bytes, &signature).is_ok());
+
+        assert!(keys.key(0).is_some());
+        assert!(keys.retire_expired(5_999).is_empty());
+        assert_eq!(keys.retire_expired(6_000), vec![0]);
+        assert!(keys.key(0).is_none());
+        assert_eq!(keys.handovers().len(), 1);
+    }
+}
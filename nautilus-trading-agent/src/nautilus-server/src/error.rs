@@ -0,0 +1,345 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Errors returned by the enclave. Every variant has a stable machine readable
+// code, an HTTP status and a retryability hint, returned to clients as
+//
+//   {"error": "<message>", "code": "<code>", "retryable": <bool>}
+//
+// together with the decoded abort for failed Move calls. Aborts of the
+// modules the enclave calls are named through the registry in
+// `examples::trading::aborts`.
+
+use serde::Serialize;
+use std::fmt;
+use warp::http::StatusCode;
+
+/// Enclave errors enum
+#[derive(Debug)]
+pub enum EnclaveError {
+    /// Unclassified failure, reported as a bad request
+    GenericError(String),
+    /// Malformed or invalid input
+    InvalidRequest(String),
+    /// Missing or invalid signature, API key or ownership
+    Unauthorized(String),
+    /// The wallet, object or record does not exist
+    NotFound(String),
+    /// The request conflicts with the current state
+    Conflict(String),
+    /// Not enough funds for the amount and gas
+    InsufficientBalance(String),
+    /// The Sui RPC or another upstream service is unreachable
+    Rpc(String),
+    /// The transaction could not be submitted
+    Submission(String),
+    /// The transaction was executed but failed
+    TransactionFailed { digest: String, error: String },
+    /// A Move call aborted
+    MoveAbort(MoveAbort),
+    /// Failure inside the enclave
+    Internal(String),
+}
+
+/// A Move abort decoded from a failed transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MoveAbort {
+    /// Address of the package, without 0x prefix as reported by the node
+    pub package: String,
+    pub module: String,
+    pub function: Option<String>,
+    pub code: u64,
+    /// Name of the abort constant, if known
+    pub name: Option<&'static str>,
+    /// Digest of the failed transaction, if it was executed
+    pub digest: Option<String>,
+    /// The meaning of the abort, if known
+    pub error: Option<AbortError>,
+}
+
+/// Known aborts of the DEX, marketplace, enclave and trading agent modules.
+/// Fields the chain does not report are filled in from the request when known.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AbortError {
+    // dex
+    InsufficientLiquidity { pool: Option<String> },
+    SlippageTooHigh { expected_min: Option<u64>, pool: Option<String> },
+    ZeroAmount,
+    // subscription_manager and agent_registry
+    NotSubscribed,
+    InsufficientPayment,
+    AgentNotActive,
+    NotSubscriber,
+    InvalidSubscriberSignature,
+    NotAuthorized,
+    InsufficientDeposit,
+    ExceedsDepositedAmount { requested: Option<u64> },
+    AgentWalletNotSet,
+    NoRewardsAvailable,
+    NotCreator,
+    InvalidTeeKey,
+    // enclave
+    InvalidPcrs,
+    InvalidConfigVersion,
+    InvalidCap,
+    InvalidOwner,
+    // trading_agent
+    InvalidEnclaveSignature,
+    NotConfigOwner,
+    InvalidWallet,
+}
+
+impl AbortError {
+    /// Stable error code, also the serialized `kind`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AbortError::InsufficientLiquidity { .. } => "insufficient_liquidity",
+            AbortError::SlippageTooHigh { .. } => "slippage_too_high",
+            AbortError::ZeroAmount => "zero_amount",
+            AbortError::NotSubscribed => "not_subscribed",
+            AbortError::InsufficientPayment => "insufficient_payment",
+            AbortError::AgentNotActive => "agent_not_active",
+            AbortError::NotSubscriber => "not_subscriber",
+            AbortError::InvalidSubscriberSignature => "invalid_subscriber_signature",
+            AbortError::NotAuthorized => "not_authorized",
+            AbortError::InsufficientDeposit => "insufficient_deposit",
+            AbortError::ExceedsDepositedAmount { .. } => "exceeds_deposited_amount",
+            AbortError::AgentWalletNotSet => "agent_wallet_not_set",
+            AbortError::NoRewardsAvailable => "no_rewards_available",
+            AbortError::NotCreator => "not_creator",
+            AbortError::InvalidTeeKey => "invalid_tee_key",
+            AbortError::InvalidPcrs => "invalid_pcrs",
+            AbortError::InvalidConfigVersion => "invalid_config_version",
+            AbortError::InvalidCap => "invalid_cap",
+            AbortError::InvalidOwner => "invalid_owner",
+            AbortError::InvalidEnclaveSignature => "invalid_enclave_signature",
+            AbortError::NotConfigOwner => "not_config_owner",
+            AbortError::InvalidWallet => "invalid_wallet",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AbortError::NotSubscriber
+            | AbortError::InvalidSubscriberSignature
+            | AbortError::NotAuthorized
+            | AbortError::NotCreator
+            | AbortError::InvalidCap
+            | AbortError::InvalidOwner
+            | AbortError::NotConfigOwner => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    /// Prices move, a fresh quote may pass.
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            AbortError::InsufficientLiquidity { .. } | AbortError::SlippageTooHigh { .. }
+        )
+    }
+}
+
+impl fmt::Display for AbortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbortError::SlippageTooHigh {
+                expected_min: Some(expected_min),
+                pool,
+            } => write!(
+                f,
+                "output below the minimum of {} in pool {}",
+                expected_min,
+                pool.as_deref().unwrap_or("?")
+            ),
+            AbortError::InsufficientLiquidity { pool: Some(pool) } => {
+                write!(f, "insufficient liquidity in pool {}", pool)
+            }
+            AbortError::ExceedsDepositedAmount {
+                requested: Some(requested),
+            } => write!(f, "{} exceeds the deposited amount", requested),
+            other => write!(f, "{}", other.code().replace('_', " ")),
+        }
+    }
+}
+
+/// Body of an error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub code: &'static str,
+    pub retryable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abort: Option<MoveAbort>,
+}
+
+impl EnclaveError {
+    /// Stable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EnclaveError::GenericError(_) => "bad_request",
+            EnclaveError::InvalidRequest(_) => "invalid_request",
+            EnclaveError::Unauthorized(_) => "unauthorized",
+            EnclaveError::NotFound(_) => "not_found",
+            EnclaveError::Conflict(_) => "conflict",
+            EnclaveError::InsufficientBalance(_) => "insufficient_balance",
+            EnclaveError::Rpc(_) => "rpc_unavailable",
+            EnclaveError::Submission(_) => "submission_failed",
+            EnclaveError::TransactionFailed { .. } => "transaction_failed",
+            EnclaveError::MoveAbort(abort) => abort.error.as_ref().map_or("move_abort", AbortError::code),
+            EnclaveError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            EnclaveError::GenericError(_) | EnclaveError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            EnclaveError::Unauthorized(_) => StatusCode::FORBIDDEN,
+            EnclaveError::NotFound(_) => StatusCode::NOT_FOUND,
+            EnclaveError::Conflict(_) => StatusCode::CONFLICT,
+            EnclaveError::InsufficientBalance(_) | EnclaveError::TransactionFailed { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            EnclaveError::MoveAbort(abort) => abort
+                .error
+                .as_ref()
+                .map_or(StatusCode::UNPROCESSABLE_ENTITY, AbortError::status),
+            EnclaveError::Rpc(_) => StatusCode::SERVICE_UNAVAILABLE,
+            EnclaveError::Submission(_) => StatusCode::BAD_GATEWAY,
+            EnclaveError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether the same request may succeed if retried later.
+    pub fn retryable(&self) -> bool {
+        match self {
+            EnclaveError::Rpc(_) | EnclaveError::Submission(_) => true,
+            EnclaveError::MoveAbort(abort) => abort.error.as_ref().is_some_and(AbortError::retryable),
+            _ => false,
+        }
+    }
+
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            error: self.to_string(),
+            code: self.code(),
+            retryable: self.retryable(),
+            abort: match self {
+                EnclaveError::MoveAbort(abort) => Some(abort.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    /// The JSON error reply returned by endpoint wrappers.
+    pub fn into_reply(self) -> Box<dyn warp::Reply> {
+        Box::new(warp::reply::with_status(
+            warp::reply::json(&self.to_response()),
+            self.status(),
+        ))
+    }
+
+    /// Classify the error of a transaction whose effects report a failure.
+    pub fn from_execution_failure(digest: String, error: String) -> Self {
+        match MoveAbort::parse(&error) {
+            Some(mut abort) => {
+                abort.digest = Some(digest);
+                EnclaveError::MoveAbort(abort)
+            }
+            None => EnclaveError::TransactionFailed { digest, error },
+        }
+    }
+}
+
+impl MoveAbort {
+    /// Decode an abort from a Sui execution error such as
+    /// `MoveAbort(MoveLocation { module: ModuleId { address: 58.., name:
+    /// Identifier("dex") }, function: 2, instruction: 20, function_name:
+    /// Some("swap_sui_to_usdc") }, 2) in command 1`.
+    pub fn parse(error: &str) -> Option<Self> {
+        let location = error.split_once("MoveAbort(")?.1;
+        let package = between(location, "address: ", ",")?.trim().to_string();
+        let module = between(location, "name: Identifier(\"", "\")")?.to_string();
+        let function = between(location, "function_name: Some(\"", "\")").map(str::to_string);
+        // The abort code follows the closing brace of the location
+        let code = between(location.rsplit_once("}, ")?.1, "", ")")?.trim().parse().ok()?;
+
+        Some(MoveAbort {
+            package,
+            module,
+            function,
+            code,
+            name: None,
+            digest: None,
+            error: None,
+        })
+    }
+}
+
+fn between<'a>(value: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let rest = &value[value.find(start)? + start.len()..];
+    Some(&rest[..rest.find(end)?])
+}
+
+impl fmt::Display for EnclaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnclaveError::GenericError(e)
+            | EnclaveError::InvalidRequest(e)
+            | EnclaveError::Unauthorized(e)
+            | EnclaveError::NotFound(e)
+            | EnclaveError::Conflict(e)
+            | EnclaveError::InsufficientBalance(e)
+            | EnclaveError::Rpc(e)
+            | EnclaveError::Submission(e)
+            | EnclaveError::Internal(e) => write!(f, "{}", e),
+            EnclaveError::TransactionFailed { digest, error } => {
+                write!(f, "Transaction {} failed: {}", digest, error)
+            }
+            EnclaveError::MoveAbort(abort) => {
+                write!(
+                    f,
+                    "{}::{} aborted with {}",
+                    abort.module,
+                    abort.function.as_deref().unwrap_or("?"),
+                    abort.name.map_or_else(|| abort.code.to_string(), str::to_string)
+                )?;
+                match &abort.error {
+                    Some(error) => write!(f, ": {}", error),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnclaveError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_move_abort() {
+        let error = "MoveAbort(MoveLocation { module: ModuleId { address: 58148fa87d972dd4f2c6110dce24d2320486d7cf56143024c3fae7e3c968961f, name: Identifier(\"dex\") }, function: 2, instruction: 20, function_name: Some(\"swap_sui_to_usdc\") }, 2) in command 1";
+        let e = EnclaveError::from_execution_failure("digest".to_string(), error.to_string());
+
+        assert_eq!(e.code(), "move_abort");
+        assert_eq!(e.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!e.retryable());
+        match e {
+            EnclaveError::MoveAbort(abort) => {
+                assert_eq!(abort.module, "dex");
+                assert_eq!(abort.function.as_deref(), Some("swap_sui_to_usdc"));
+                assert_eq!(abort.code, 2);
+                assert_eq!(abort.digest.as_deref(), Some("digest"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let e = EnclaveError::from_execution_failure("digest".to_string(), "InsufficientGas".to_string());
+        assert_eq!(e.code(), "transaction_failed");
+        assert!(!e.retryable());
+    }
+}
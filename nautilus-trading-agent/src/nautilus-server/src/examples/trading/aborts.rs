@@ -0,0 +1,156 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Registry of the Move abort codes of the packages the enclave calls. Aborts
+// are matched on (package, module, code), so a module of the same name in
+// another package is left undecoded. Call sites add what the chain does not
+// report, such as the minimum output of a swap, through `AbortContext`.
+
+use super::{DEX_PACKAGE_ID, ENCLAVE_PACKAGE_ID, POOL_ID, SUBSCRIPTION_MANAGER_PACKAGE_ID, TRADING_PACKAGE_ID};
+use crate::error::{AbortError, MoveAbort};
+use crate::EnclaveError;
+
+/// Request details used to fill in the payload of a decoded abort.
+#[derive(Debug, Default, Clone)]
+pub struct AbortContext {
+    pub pool: Option<String>,
+    pub expected_min: Option<u64>,
+    pub amount: Option<u64>,
+}
+
+impl AbortContext {
+    /// Context of a swap against the configured pool.
+    pub fn swap(min_output: u64) -> Self {
+        Self {
+            pool: Some(POOL_ID.to_string()),
+            expected_min: Some(min_output),
+            amount: None,
+        }
+    }
+
+    /// Context of a withdrawal of `amount`.
+    pub fn withdrawal(amount: u64) -> Self {
+        Self {
+            amount: Some(amount),
+            ..Self::default()
+        }
+    }
+}
+
+/// Name and meaning of the abort, if it belongs to a known package.
+pub fn lookup(abort: &MoveAbort, context: &AbortContext) -> Option<(&'static str, AbortError)> {
+    let package = if is_package(&abort.package, DEX_PACKAGE_ID) {
+        "dex"
+    } else if is_package(&abort.package, SUBSCRIPTION_MANAGER_PACKAGE_ID) {
+        "marketplace"
+    } else if is_package(&abort.package, ENCLAVE_PACKAGE_ID) {
+        "enclave"
+    } else if is_package(&abort.package, TRADING_PACKAGE_ID) {
+        "trading"
+    } else {
+        return None;
+    };
+
+    let decoded = match (package, abort.module.as_str(), abort.code) {
+        ("dex", "dex", 1) => ("EInsufficientLiquidity", AbortError::InsufficientLiquidity {
+            pool: context.pool.clone(),
+        }),
+        ("dex", "dex", 2) => ("ESlippageTooHigh", AbortError::SlippageTooHigh {
+            expected_min: context.expected_min,
+            pool: context.pool.clone(),
+        }),
+        ("dex", "dex", 3) => ("EZeroAmount", AbortError::ZeroAmount),
+
+        ("marketplace", "subscription_manager", 1) => ("ENotSubscribed", AbortError::NotSubscribed),
+        ("marketplace", "subscription_manager", 2) => ("EInsufficientPayment", AbortError::InsufficientPayment),
+        ("marketplace", "subscription_manager", 3) => ("EAgentNotActive", AbortError::AgentNotActive),
+        ("marketplace", "subscription_manager", 4) => ("ENotSubscriber", AbortError::NotSubscriber),
+        ("marketplace", "subscription_manager", 5) => ("EInvalidSignature", AbortError::InvalidSubscriberSignature),
+        ("marketplace", "subscription_manager", 6) => ("ENotAuthorized", AbortError::NotAuthorized),
+        ("marketplace", "subscription_manager", 7) => ("EInsufficientDeposit", AbortError::InsufficientDeposit),
+        ("marketplace", "subscription_manager", 8) => ("EExceedsDepositedAmount", AbortError::ExceedsDepositedAmount {
+            requested: context.amount,
+        }),
+        ("marketplace", "subscription_manager", 9) => ("EAgentWalletNotSet", AbortError::AgentWalletNotSet),
+        ("marketplace", "subscription_manager", 10) => ("ENoRewardsAvailable", AbortError::NoRewardsAvailable),
+        ("marketplace", "agent_registry", 1) => ("ENotCreator", AbortError::NotCreator),
+        ("marketplace", "agent_registry", 2) => ("EAgentNotActive", AbortError::AgentNotActive),
+        ("marketplace", "agent_registry", 3) => ("EInvalidTEEKey", AbortError::InvalidTeeKey),
+
+        ("enclave", "enclave", 0) => ("EInvalidPCRs", AbortError::InvalidPcrs),
+        ("enclave", "enclave", 1) => ("EInvalidConfigVersion", AbortError::InvalidConfigVersion),
+        ("enclave", "enclave", 2) => ("EInvalidCap", AbortError::InvalidCap),
+        ("enclave", "enclave", 3) => ("EInvalidOwner", AbortError::InvalidOwner),
+
+        ("trading", "trading_agent", 1) => ("EInvalidSignature", AbortError::InvalidEnclaveSignature),
+        ("trading", "trading_agent", 2) => ("EUnauthorized", AbortError::NotConfigOwner),
+        ("trading", "trading_agent", 3) => ("EInvalidWallet", AbortError::InvalidWallet),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/// Name a Move abort through the registry, leaving other errors unchanged.
+/// Decoding again with a richer context replaces the payload.
+pub fn decode(error: EnclaveError, context: &AbortContext) -> EnclaveError {
+    match error {
+        EnclaveError::MoveAbort(mut abort) => {
+            if let Some((name, decoded)) = lookup(&abort, context) {
+                abort.name = Some(name);
+                abort.error = Some(decoded);
+            }
+            EnclaveError::MoveAbort(abort)
+        }
+        other => other,
+    }
+}
+
+/// Compare package addresses, which the node reports without `0x` and leading zeros may differ.
+fn is_package(reported: &str, package_id: &str) -> bool {
+    let normalize = |address: &str| {
+        address
+            .trim_start_matches("0x")
+            .trim_start_matches('0')
+            .to_ascii_lowercase()
+    };
+    normalize(reported) == normalize(package_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_slippage_abort() {
+        let error = format!(
+            "MoveAbort(MoveLocation {{ module: ModuleId {{ address: {}, name: Identifier(\"dex\") }}, function: 2, instruction: 20, function_name: Some(\"swap_sui_to_usdc\") }}, 2) in command 1",
+            DEX_PACKAGE_ID.trim_start_matches("0x")
+        );
+        let e = EnclaveError::from_execution_failure("digest".to_string(), error);
+        let e = decode(e, &AbortContext::swap(500_000));
+
+        assert_eq!(e.code(), "slippage_too_high");
+        assert!(e.retryable());
+        match e {
+            EnclaveError::MoveAbort(abort) => {
+                assert_eq!(abort.name, Some("ESlippageTooHigh"));
+                assert_eq!(
+                    abort.error,
+                    Some(AbortError::SlippageTooHigh {
+                        expected_min: Some(500_000),
+                        pool: Some(POOL_ID.to_string()),
+                    })
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Same module name in an unknown package
+        let error = "MoveAbort(MoveLocation { module: ModuleId { address: 01, name: Identifier(\"dex\") }, function: 2, instruction: 20, function_name: None }, 2) in command 0";
+        let e = decode(
+            EnclaveError::from_execution_failure("digest".to_string(), error.to_string()),
+            &AbortContext::default(),
+        );
+        assert_eq!(e.code(), "move_abort");
+    }
+}
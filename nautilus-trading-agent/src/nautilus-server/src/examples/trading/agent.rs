@@ -0,0 +1,155 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// The marketplace `TradingAgent` the wallet trades for. Its metadata is loaded
+// on boot if `AGENT_ID` is set, otherwise when first needed, and reloaded
+// whenever the event listener sees an `AgentCreated` or `AgentStatusUpdated`
+// event for it. The last failure to load it is reported in `/health`.
+//
+// - trades are refused while the creator has deactivated the agent,
+// - deposits outside `min_deposit`/`max_deposit` mint no shares and are refunded,
+// - the object version the enclave acts under is reported in `/health`.
+//
+// `update_agent_metadata` emits no event, so changes it makes (not the status
+// or the limits) are only picked up on the next reload.
+
+use super::chain::{is_same_type, read_versioned_object, sui_client};
+use super::SUBSCRIPTION_MANAGER_PACKAGE_ID;
+use crate::EnclaveError;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const AGENT_ID_ENV: &str = "AGENT_ID";
+
+lazy_static! {
+    static ref AGENT: RwLock<Option<AgentMetadata>> = RwLock::new(None);
+    static ref LOAD_ERROR: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// BCS layout of `agent_registry::TradingAgent`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct TradingAgentObject {
+    id: ObjectID,
+    name: String,
+    description: String,
+    subscription_fee_per_month: u64,
+    min_deposit: u64,
+    max_deposit: u64,
+    tee_public_key: Vec<u8>,
+    tee_wallet_address: SuiAddress,
+    creator: SuiAddress,
+    is_active: bool,
+    created_at: u64,
+    total_subscribers: u64,
+}
+
+/// The agent's metadata as last loaded, reported in `/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentMetadata {
+    pub agent_id: String,
+    /// Version of the `TradingAgent` object the metadata was read at
+    pub version: u64,
+    pub name: String,
+    pub creator: String,
+    pub is_active: bool,
+    pub min_deposit: u64,
+    pub max_deposit: u64,
+    pub tee_wallet_address: String,
+}
+
+/// Load the agent named by `AGENT_ID` on boot. Fails if it is not a
+/// `TradingAgent`; if it cannot be read, the error is reported in `/health`
+/// and loading is retried when the agent is next needed.
+pub async fn load_agent() -> Result<(), EnclaveError> {
+    let Ok(agent_id) = std::env::var(AGENT_ID_ENV) else {
+        info!("{} not set, the agent is loaded with the wallet", AGENT_ID_ENV);
+        return Ok(());
+    };
+    let agent_id = agent_id
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", AGENT_ID_ENV, e)))?;
+    match load(agent_id).await {
+        Ok(_) => Ok(()),
+        Err(e @ EnclaveError::InvalidRequest(_)) => Err(e),
+        Err(e) => {
+            warn!("Failed to load agent {}: {}", agent_id, e);
+            Ok(())
+        }
+    }
+}
+
+/// Load the metadata of `agent_id` from chain, replacing what was loaded before.
+pub async fn refresh(client: &SuiClient, agent_id: ObjectID) -> Result<AgentMetadata, EnclaveError> {
+    let result = read_agent(client, agent_id).await;
+    *LOAD_ERROR.write().await = result
+        .as_ref()
+        .err()
+        .map(|e| format!("Failed to load agent {}: {}", agent_id, e));
+    let metadata = result?;
+    *AGENT.write().await = Some(metadata.clone());
+    Ok(metadata)
+}
+
+async fn read_agent(client: &SuiClient, agent_id: ObjectID) -> Result<AgentMetadata, EnclaveError> {
+    let (object_type, version, agent) = read_versioned_object::<TradingAgentObject>(client, agent_id).await?;
+    let expected = format!("{}::agent_registry::TradingAgent", SUBSCRIPTION_MANAGER_PACKAGE_ID);
+    if !is_same_type(&object_type, &expected) {
+        return Err(EnclaveError::InvalidRequest(format!("{} is not a TradingAgent", agent_id)));
+    }
+
+    let metadata = AgentMetadata {
+        agent_id: agent_id.to_string(),
+        version: version.value(),
+        name: agent.name,
+        creator: agent.creator.to_string(),
+        is_active: agent.is_active,
+        min_deposit: agent.min_deposit,
+        max_deposit: agent.max_deposit,
+        tee_wallet_address: agent.tee_wallet_address.to_string(),
+    };
+    info!(
+        "Loaded agent {} at version {} (active: {}, deposits {}..{})",
+        agent_id, metadata.version, metadata.is_active, metadata.min_deposit, metadata.max_deposit
+    );
+    Ok(metadata)
+}
+
+/// Metadata of `agent_id`, loading it if it was not loaded yet.
+pub async fn load(agent_id: ObjectID) -> Result<AgentMetadata, EnclaveError> {
+    if let Some(metadata) = current().await.filter(|metadata| metadata.agent_id == agent_id.to_string()) {
+        return Ok(metadata);
+    }
+    let client = match sui_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            *LOAD_ERROR.write().await = Some(format!("Failed to load agent {}: {}", agent_id, e));
+            return Err(e);
+        }
+    };
+    refresh(&client, agent_id).await
+}
+
+pub async fn current() -> Option<AgentMetadata> {
+    AGENT.read().await.clone()
+}
+
+/// Why the agent could not be loaded the last time it was tried, if it failed.
+pub async fn load_error() -> Option<String> {
+    LOAD_ERROR.read().await.clone()
+}
+
+/// Fail if the creator deactivated `agent_id`.
+pub async fn check_active(agent_id: &str) -> Result<(), EnclaveError> {
+    let agent_id = agent_id
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?;
+    if !load(agent_id).await?.is_active {
+        return Err(EnclaveError::Conflict(format!("Agent {} was deactivated by its creator", agent_id)));
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Verification of messages signed by users with their Sui wallets.
+
+use crate::EnclaveError;
+use fastcrypto::encoding::{Base64, Encoding};
+use fastcrypto::traits::ToFromBytes;
+use shared_crypto::intent::{Intent, IntentMessage, PersonalMessage};
+use sui_types::base_types::SuiAddress;
+use sui_types::crypto::{Signature, SuiSignature};
+
+/// Signed messages older than this are rejected.
+pub const MAX_SIGNATURE_AGE_MS: u64 = 5 * 60 * 1000;
+
+/// Verify a base64 encoded Sui signature (flag || signature || public key)
+/// over `message`, signed as a personal message by `signer`.
+pub fn verify_personal_message(
+    signer: &SuiAddress,
+    message: &[u8],
+    signature: &str,
+) -> Result<(), EnclaveError> {
+    let bytes = Base64::decode(signature)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid signature encoding: {}", e)))?;
+    let signature = Signature::from_bytes(&bytes)
+        .map_err(|e| EnclaveError::Unauthorized(format!("Invalid signature: {}", e)))?;
+
+    let intent_msg = IntentMessage::new(
+        Intent::personal_message(),
+        PersonalMessage {
+            message: message.to_vec(),
+        },
+    );
+    signature
+        .verify_secure(&intent_msg, *signer, signature.scheme())
+        .map_err(|_| EnclaveError::Unauthorized(format!("Message is not signed by {}", signer)))
+}
+
+/// Reject signed messages that are too old or from the future.
+pub fn check_signature_timestamp(timestamp_ms: u64, now_ms: u64) -> Result<(), EnclaveError> {
+    if timestamp_ms > now_ms + MAX_SIGNATURE_AGE_MS || now_ms.saturating_sub(timestamp_ms) > MAX_SIGNATURE_AGE_MS {
+        return Err(EnclaveError::Unauthorized("Signed message has expired".to_string()));
+    }
+    Ok(())
+}
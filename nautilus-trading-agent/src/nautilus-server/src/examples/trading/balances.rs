@@ -0,0 +1,287 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Everything the trading wallet owns, reported by `/wallet_status`: the balance
+// of every coin type across all pages of `get_all_coins`, with the symbol and
+// decimals of its `CoinMetadata`, and the owned objects that are not coins,
+// such as the DEX's LP tokens.
+//
+// Coins are valued in USD where a price is known: MOCK_USDC at 1 USD, SUI at
+// the pool price, and other coin types from `COIN_USD_PRICES`
+// (`<coin type>=<micro USD per coin>,...`). Values are in micro USD, since
+// signed payloads are BCS encoded.
+
+use super::chain::is_same_type;
+use super::shares::Nav;
+use super::DEX_PACKAGE_ID;
+use crate::EnclaveError;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use sui_json_rpc_types::{SuiObjectDataOptions, SuiObjectResponseQuery, SuiRawData};
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::coin::Coin;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+const COIN_USD_PRICES_ENV: &str = "COIN_USD_PRICES";
+const SUI_COIN_TYPE: &str = "0x2::sui::SUI";
+const USD_MICROS: u64 = 1_000_000;
+const DEFAULT_USDC_DECIMALS: u8 = 6;
+const MAX_OBJECTS: usize = 1_000;
+
+lazy_static! {
+    static ref METADATA: RwLock<HashMap<String, Option<CoinMetadata>>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoinMetadata {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Balance of one coin type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoinBalance {
+    pub coin_type: String,
+    /// None if the coin type has no `CoinMetadata`
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    pub balance: u64,
+    /// Number of coin objects holding the balance
+    pub coin_objects: u64,
+    /// None if no price is known for the coin type
+    pub usd_value_micros: Option<u64>,
+}
+
+/// An owned object that is not a coin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnedObject {
+    pub object_id: String,
+    pub object_type: String,
+    pub version: u64,
+    /// Liquidity of a DEX LP token
+    pub lp_amount: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WalletAssets {
+    pub coins: Vec<CoinBalance>,
+    pub objects: Vec<OwnedObject>,
+    /// More than `MAX_OBJECTS` objects are owned, only the first are reported
+    pub objects_truncated: bool,
+    /// Value of the coins with a known price, None if none has one
+    pub usd_value_micros: Option<u64>,
+}
+
+/// BCS layout of `dex::LPToken`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct LpTokenObject {
+    id: ObjectID,
+    pool_id: ObjectID,
+    amount: u64,
+}
+
+pub fn usdc_coin_type() -> String {
+    format!("{}::mock_usdc::MOCK_USDC", DEX_PACKAGE_ID)
+}
+
+/// Balance and number of coin objects of every coin type `owner` holds.
+pub async fn coin_balances(
+    client: &SuiClient,
+    owner: SuiAddress,
+) -> Result<BTreeMap<String, (u64, u64)>, EnclaveError> {
+    let mut balances: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut cursor = None;
+    loop {
+        let page = client
+            .coin_read_api()
+            .get_all_coins(owner, cursor, None)
+            .await
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to fetch coins: {}", e)))?;
+        for coin in page.data {
+            let (balance, count) = balances.entry(coin.coin_type).or_default();
+            *balance = balance.saturating_add(coin.balance);
+            *count += 1;
+        }
+        if !page.has_next_page {
+            break;
+        }
+        cursor = page.next_cursor;
+    }
+    Ok(balances)
+}
+
+/// Metadata of `coin_type`, cached once read. None if the coin type has none.
+async fn coin_metadata(client: &SuiClient, coin_type: &str) -> Option<CoinMetadata> {
+    if let Some(metadata) = METADATA.read().await.get(coin_type) {
+        return metadata.clone();
+    }
+    match client.coin_read_api().get_coin_metadata(coin_type.to_string()).await {
+        Ok(metadata) => {
+            let metadata = metadata.map(|metadata| CoinMetadata {
+                symbol: metadata.symbol,
+                decimals: metadata.decimals,
+            });
+            METADATA.write().await.insert(coin_type.to_string(), metadata.clone());
+            metadata
+        }
+        Err(e) => {
+            warn!("Failed to fetch the metadata of {}: {}", coin_type, e);
+            None
+        }
+    }
+}
+
+/// The first `MAX_OBJECTS` objects owned by `owner` that are not coins, and
+/// whether there were more.
+async fn owned_objects(client: &SuiClient, owner: SuiAddress) -> Result<(Vec<OwnedObject>, bool), EnclaveError> {
+    let lp_token_type = format!("{}::dex::LPToken", DEX_PACKAGE_ID);
+    let mut objects = Vec::new();
+    let mut cursor = None;
+    loop {
+        let query = SuiObjectResponseQuery::new_with_options(SuiObjectDataOptions::new().with_type().with_bcs());
+        let page = client
+            .read_api()
+            .get_owned_objects(owner, Some(query), cursor, None)
+            .await
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to fetch owned objects: {}", e)))?;
+        for data in page.data.into_iter().filter_map(|response| response.data) {
+            let Ok(object_type) = data.object_type().map(|object_type| object_type.to_string()) else {
+                continue;
+            };
+            if sui_types::parse_sui_struct_tag(&object_type).is_ok_and(|tag| Coin::is_coin(&tag)) {
+                continue;
+            }
+            if objects.len() == MAX_OBJECTS {
+                return Ok((objects, true));
+            }
+
+            let lp_amount = match &data.bcs {
+                Some(SuiRawData::MoveObject(object)) if is_same_type(&object_type, &lp_token_type) => {
+                    bcs::from_bytes::<LpTokenObject>(&object.bcs_bytes).ok().map(|token| token.amount)
+                }
+                _ => None,
+            };
+            objects.push(OwnedObject {
+                object_id: data.object_id.to_string(),
+                object_type,
+                version: data.version.value(),
+                lp_amount,
+            });
+        }
+        if !page.has_next_page {
+            break;
+        }
+        cursor = page.next_cursor;
+    }
+    Ok((objects, false))
+}
+
+/// Prices from `COIN_USD_PRICES`, in micro USD per whole coin.
+fn configured_prices() -> Result<Vec<(String, u64)>, EnclaveError> {
+    let Ok(prices) = std::env::var(COIN_USD_PRICES_ENV) else {
+        return Ok(Vec::new());
+    };
+    prices
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (coin_type, price) = entry
+                .rsplit_once('=')
+                .ok_or_else(|| EnclaveError::Internal(format!("Invalid {} entry: {}", COIN_USD_PRICES_ENV, entry)))?;
+            let price = price
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| EnclaveError::Internal(format!("Invalid {} price for {}: {}", COIN_USD_PRICES_ENV, coin_type, e)))?;
+            Ok((coin_type.trim().to_string(), price))
+        })
+        .collect()
+}
+
+/// Value of `balance` base units of a coin with `decimals` at `price_micros` per whole coin.
+pub fn usd_value(balance: u64, decimals: u8, price_micros: u64) -> Option<u64> {
+    let scale = 10u128.checked_pow(decimals as u32)?;
+    u64::try_from(balance as u128 * price_micros as u128 / scale).ok()
+}
+
+/// Value a coin balance in micro USD. SUI is converted to USDC at the pool
+/// price of `nav`, and USDC counts as 1 USD.
+fn value_coin(
+    coin_type: &str,
+    balance: u64,
+    decimals: Option<u8>,
+    usdc_decimals: u8,
+    nav: Option<&Nav>,
+    prices: &[(String, u64)],
+) -> Option<u64> {
+    if let Some((_, price)) = prices.iter().find(|(priced, _)| is_same_type(coin_type, priced)) {
+        return usd_value(balance, decimals?, *price);
+    }
+    if is_same_type(coin_type, &usdc_coin_type()) {
+        return usd_value(balance, usdc_decimals, USD_MICROS);
+    }
+    if is_same_type(coin_type, SUI_COIN_TYPE) {
+        let nav = nav.filter(|nav| nav.sui_reserve > 0)?;
+        let usdc = u64::try_from(balance as u128 * nav.usdc_reserve as u128 / nav.sui_reserve as u128).ok()?;
+        return usd_value(usdc, usdc_decimals, USD_MICROS);
+    }
+    None
+}
+
+/// Every coin and non-coin object `address` owns, valued at the pool price of `nav` if given.
+pub async fn fetch(client: &SuiClient, address: &str, nav: Option<&Nav>) -> Result<WalletAssets, EnclaveError> {
+    let owner: SuiAddress = address
+        .parse()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid address format: {}", e)))?;
+    let prices = configured_prices()?;
+    let usdc_decimals = coin_metadata(client, &usdc_coin_type())
+        .await
+        .map_or(DEFAULT_USDC_DECIMALS, |metadata| metadata.decimals);
+
+    let mut assets = WalletAssets::default();
+    for (coin_type, (balance, coin_objects)) in coin_balances(client, owner).await? {
+        let metadata = coin_metadata(client, &coin_type).await;
+        let decimals = metadata.as_ref().map(|metadata| metadata.decimals);
+        let usd_value_micros = value_coin(&coin_type, balance, decimals, usdc_decimals, nav, &prices);
+        if let Some(value) = usd_value_micros {
+            assets.usd_value_micros = Some(assets.usd_value_micros.unwrap_or(0).saturating_add(value));
+        }
+        assets.coins.push(CoinBalance {
+            coin_type,
+            symbol: metadata.map(|metadata| metadata.symbol),
+            decimals,
+            balance,
+            coin_objects,
+            usd_value_micros,
+        });
+    }
+    (assets.objects, assets.objects_truncated) = owned_objects(client, owner).await?;
+    Ok(assets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coin_valuation() {
+        // 2.5 USDC, 1.5 SUI at 3 USDC per SUI
+        assert_eq!(usd_value(2_500_000, 6, USD_MICROS), Some(2_500_000));
+        let nav = Nav::new(0, 0, 1_000_000_000_000, 3_000_000_000);
+        assert_eq!(
+            value_coin(SUI_COIN_TYPE, 1_500_000_000, Some(9), 6, Some(&nav), &[]),
+            Some(4_500_000)
+        );
+        assert_eq!(value_coin(SUI_COIN_TYPE, 1_500_000_000, Some(9), 6, None, &[]), None);
+
+        // Other coins only with a configured price
+        let coin_type = "0x123::token::TOKEN";
+        assert_eq!(value_coin(coin_type, 2_000, Some(3), 6, None, &[]), None);
+        let prices = vec![(coin_type.to_string(), 250_000)];
+        assert_eq!(value_coin(coin_type, 2_000, Some(3), 6, None, &prices), Some(500_000));
+        assert_eq!(value_coin(coin_type, 2_000, None, 6, None, &prices), None);
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Binding of the enclave wallet to a marketplace agent. When `/init_wallet` is
+// given an `agent_id` the enclave signs an `AgentBinding` under
+// `IntentScope::AgentBinding`, naming the wallet and signing key to set as
+// `tee_wallet_address` and `tee_public_key` of the `TradingAgent` and as the
+// agent wallet in the `SubscriptionManager`. It is not part of the signed init
+// response, which must stay identical to the Move `InitWalletResponse`.
+//
+// The enclave never submits the binding itself: the creator calls
+// `subscription_manager::update_agent_wallet` with the `DeployerCap` from
+// their own account and pays the gas. `/bind_agent` returns the binding again
+// to a request signed by the wallet owner. The key named in the binding goes
+// stale on rotation, so every rotation re-signs it with the new key; the
+// latest binding is reported in `/health`.
+
+use super::auth::{check_signature_timestamp, verify_personal_message};
+use super::{get_current_timestamp, TRADING_WALLET};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use fastcrypto::traits::ToFromBytes;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use tokio::sync::RwLock;
+use tracing::info;
+
+lazy_static! {
+    /// Binding signed by the current key, once the wallet is bound to an agent.
+    static ref BINDING: RwLock<Option<SignedBinding>> = RwLock::new(None);
+}
+
+pub type SignedBinding = ProcessedDataResponse<IntentMessage<AgentBinding>>;
+
+/// Statement that `tee_wallet_address` trades for `agent_id`, signed under
+/// `IntentScope::AgentBinding`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentBinding {
+    pub agent_id: String,
+    pub tee_wallet_address: String,
+    /// Enclave key signing the binding and the trade responses
+    pub tee_public_key: Vec<u8>,
+    pub owner: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BindAgentRequest {
+    pub timestamp_ms: u64,
+    /// Wallet owner signature over [bind_message]
+    pub signature: String,
+}
+
+/// Message the wallet owner signs to request the binding.
+pub fn bind_message(agent_id: &str, wallet_address: &str, timestamp_ms: u64) -> String {
+    format!("nautilus-bind-agent:{}:{}:{}", agent_id, wallet_address, timestamp_ms)
+}
+
+/// Sign the binding of `wallet_address` to `agent_id` with the current key and keep it.
+pub async fn sign_binding(state: &AppState, agent_id: &ObjectID, wallet_address: &str, owner: &str) -> SignedBinding {
+    let binding = AgentBinding {
+        agent_id: agent_id.to_string(),
+        tee_wallet_address: wallet_address.to_string(),
+        tee_public_key: state.eph_kp.public().as_bytes().to_vec(),
+        owner: owner.to_string(),
+    };
+    let signed = to_signed_response(&state.eph_kp, binding, get_current_timestamp(), IntentScope::AgentBinding);
+    *BINDING.write().await = Some(signed.clone());
+    signed
+}
+
+/// Re-sign the binding after the key rotated, if the wallet is bound to an agent.
+pub async fn resign(state: &AppState) -> Result<(), EnclaveError> {
+    let (agent_id, wallet_address, owner) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => match &wallet.agent_id {
+            Some(agent_id) => (agent_id.clone(), wallet.address.clone(), wallet.owner.clone()),
+            None => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+    let agent_id = agent_id
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?;
+    sign_binding(state, &agent_id, &wallet_address, &owner).await;
+    info!(
+        "Re-signed the binding of agent {} for key epoch {}, update its tee_public_key",
+        agent_id,
+        state.eph_kp.epoch()
+    );
+    Ok(())
+}
+
+/// The latest binding, reported in `/health`.
+pub async fn current() -> Option<SignedBinding> {
+    BINDING.read().await.clone()
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn bind_agent_wrapper(
+    request: ProcessDataRequest<BindAgentRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match bind_agent_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+async fn bind_agent_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<BindAgentRequest>,
+) -> Result<SignedBinding, EnclaveError> {
+    let (agent_id, wallet_address, owner) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.agent_id.clone(), wallet.address.clone(), wallet.owner.clone()),
+        None => return Err(EnclaveError::NotFound("Wallet not initialized".to_string())),
+    };
+    let agent_id = agent_id
+        .ok_or_else(|| EnclaveError::Conflict("The wallet is not bound to an agent".to_string()))?
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?;
+    let owner_address = owner
+        .parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid owner address: {}", e)))?;
+
+    check_signature_timestamp(request.payload.timestamp_ms, get_current_timestamp())?;
+    let message = bind_message(&agent_id.to_string(), &wallet_address, request.payload.timestamp_ms);
+    verify_personal_message(&owner_address, message.as_bytes(), &request.payload.signature)?;
+
+    Ok(sign_binding(&state, &agent_id, &wallet_address, &owner).await)
+}
@@ -0,0 +1,272 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Helpers for reading on-chain state from inside the enclave.
+
+use super::aborts::{self, AbortContext};
+use super::events::json_u64;
+use super::{ENCLAVE_PACKAGE_ID, SUI_RPC_URL, TRADING_PACKAGE_ID};
+use crate::EnclaveError;
+use fastcrypto::hash::HashFunction;
+use fastcrypto::traits::Signer;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use shared_crypto::intent::{Intent, IntentMessage};
+use sui_json_rpc_types::{
+    SuiExecutionStatus, SuiObjectDataOptions, SuiRawData, SuiTransactionBlockEffectsAPI,
+    SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+};
+use sui_sdk::{SuiClient, SuiClientBuilder};
+use sui_types::base_types::{ObjectID, ObjectRef, SequenceNumber, SuiAddress};
+use sui_types::crypto::{DefaultHash, Signature, SuiKeyPair};
+use sui_types::dynamic_field::DynamicFieldName;
+use sui_types::object::Owner;
+use sui_types::transaction::{ObjectArg, Transaction, TransactionData};
+use sui_types::TypeTag;
+
+pub async fn sui_client() -> Result<SuiClient, EnclaveError> {
+    SuiClientBuilder::default()
+        .build(SUI_RPC_URL)
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to create Sui client: {}", e)))
+}
+
+/// Fetch a Move object and decode its BCS contents into `T`. The object's
+/// type is returned alongside so callers can check it.
+pub async fn read_move_object<T: DeserializeOwned>(
+    client: &SuiClient,
+    object_id: ObjectID,
+) -> Result<(String, T), EnclaveError> {
+    let (object_type, _, value) = read_versioned_object(client, object_id).await?;
+    Ok((object_type, value))
+}
+
+/// Like `read_move_object`, also returning the version the object was read at.
+pub async fn read_versioned_object<T: DeserializeOwned>(
+    client: &SuiClient,
+    object_id: ObjectID,
+) -> Result<(String, SequenceNumber, T), EnclaveError> {
+    let response = client
+        .read_api()
+        .get_object_with_options(object_id, SuiObjectDataOptions::new().with_type().with_bcs())
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to fetch object {}: {}", object_id, e)))?;
+
+    let data = response
+        .data
+        .ok_or_else(|| EnclaveError::NotFound(format!("Object {} not found", object_id)))?;
+    let object_type = data
+        .object_type()
+        .map_err(|e| EnclaveError::Rpc(format!("Missing type for {}: {}", object_id, e)))?
+        .to_string();
+
+    match data.bcs {
+        Some(SuiRawData::MoveObject(object)) => {
+            let value = bcs::from_bytes(&object.bcs_bytes).map_err(|e| {
+                EnclaveError::InvalidRequest(format!("Failed to decode object {}: {}", object_id, e))
+            })?;
+            Ok((object_type, data.version, value))
+        }
+        _ => Err(EnclaveError::InvalidRequest(format!(
+            "Object {} is not a Move object",
+            object_id
+        ))),
+    }
+}
+
+/// BCS layout of `sui::table::Table`.
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct TableObject {
+    pub id: ObjectID,
+    pub size: u64,
+}
+
+/// BCS layout of `sui::dynamic_field::Field`, the object holding a table entry.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct FieldObject<K, V> {
+    id: ObjectID,
+    name: K,
+    value: V,
+}
+
+/// Read the value stored under `key` in the table `table_id`, None if there is no entry.
+pub async fn read_table_entry<K: DeserializeOwned, V: DeserializeOwned>(
+    client: &SuiClient,
+    table_id: ObjectID,
+    key_type: TypeTag,
+    key: serde_json::Value,
+) -> Result<Option<V>, EnclaveError> {
+    let response = client
+        .read_api()
+        .get_dynamic_field_object(table_id, DynamicFieldName { type_: key_type, value: key })
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to fetch entry of table {}: {}", table_id, e)))?;
+
+    match response.data {
+        Some(data) => {
+            let (_, field) = read_move_object::<FieldObject<K, V>>(client, data.object_id).await?;
+            Ok(Some(field.value))
+        }
+        None => Ok(None),
+    }
+}
+
+/// BCS layout of `enclave::Enclave<T>`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct EnclaveObject {
+    id: ObjectID,
+    pk: Vec<u8>,
+    config_version: u64,
+    owner: SuiAddress,
+}
+
+/// Public key of the trading agent `Enclave<T>` object `enclave_id`.
+pub async fn enclave_public_key(client: &SuiClient, enclave_id: ObjectID) -> Result<Vec<u8>, EnclaveError> {
+    let (object_type, enclave) = read_move_object::<EnclaveObject>(client, enclave_id).await?;
+    let expected_type = format!(
+        "{}::enclave::Enclave<{}::trading_agent::TRADING_AGENT>",
+        ENCLAVE_PACKAGE_ID, TRADING_PACKAGE_ID
+    );
+    if !is_same_type(&object_type, &expected_type) {
+        return Err(EnclaveError::InvalidRequest(format!(
+            "Object {} is not a trading agent enclave",
+            enclave_id
+        )));
+    }
+    Ok(enclave.pk)
+}
+
+/// Compare two Move struct types, ignoring differences in address formatting.
+pub fn is_same_type(actual: &str, expected: &str) -> bool {
+    match (
+        sui_types::parse_sui_struct_tag(actual),
+        sui_types::parse_sui_struct_tag(expected),
+    ) {
+        (Ok(actual), Ok(expected)) => actual == expected,
+        _ => false,
+    }
+}
+
+/// Build the transaction input for a shared object, looking up its initial shared version.
+pub async fn shared_object_arg(
+    client: &SuiClient,
+    object_id: ObjectID,
+    mutable: bool,
+) -> Result<ObjectArg, EnclaveError> {
+    let response = client
+        .read_api()
+        .get_object_with_options(object_id, SuiObjectDataOptions::new().with_owner())
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to fetch object {}: {}", object_id, e)))?;
+
+    match response.data.and_then(|data| data.owner) {
+        Some(Owner::Shared {
+            initial_shared_version,
+        }) => Ok(ObjectArg::SharedObject {
+            id: object_id,
+            initial_shared_version,
+            mutable,
+        }),
+        _ => Err(EnclaveError::InvalidRequest(format!(
+            "Object {} is not shared",
+            object_id
+        ))),
+    }
+}
+
+/// Pick a SUI coin of `owner` holding at least `min_balance` to pay for gas.
+pub async fn gas_coin(
+    client: &SuiClient,
+    owner: SuiAddress,
+    min_balance: u64,
+) -> Result<ObjectRef, EnclaveError> {
+    let coins = client
+        .coin_read_api()
+        .get_coins(owner, Some("0x2::sui::SUI".to_string()), None, None)
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to get SUI coins: {}", e)))?;
+
+    coins
+        .data
+        .into_iter()
+        .find(|coin| coin.balance >= min_balance)
+        .map(|coin| coin.object_ref())
+        .ok_or_else(|| EnclaveError::InsufficientBalance(format!("Need at least {} MIST for gas", min_balance)))
+}
+
+/// Sign a transaction with `keypair` and execute it, failing if the effects report an error.
+pub async fn sign_and_execute(
+    client: &SuiClient,
+    keypair: &SuiKeyPair,
+    tx_data: TransactionData,
+) -> Result<SuiTransactionBlockResponse, EnclaveError> {
+    let signature = sign_transaction(keypair, &tx_data);
+    execute_transaction(client, tx_data, vec![signature]).await
+}
+
+/// Sign transaction data as sender or gas sponsor.
+pub fn sign_transaction(keypair: &SuiKeyPair, tx_data: &TransactionData) -> Signature {
+    let intent_msg = IntentMessage::new(Intent::sui_transaction(), tx_data.clone());
+    let mut hasher = DefaultHash::default();
+    hasher.update(bcs::to_bytes(&intent_msg).expect("should not fail"));
+    let digest = hasher.finalize().digest;
+    keypair.sign(&digest)
+}
+
+/// Execute a transaction with all required signatures, failing if the effects report an error.
+pub async fn execute_transaction(
+    client: &SuiClient,
+    tx_data: TransactionData,
+    signatures: Vec<Signature>,
+) -> Result<SuiTransactionBlockResponse, EnclaveError> {
+    let transaction = Transaction::from_data(tx_data, signatures);
+    let response = client
+        .quorum_driver_api()
+        .execute_transaction_block(
+            transaction,
+            SuiTransactionBlockResponseOptions::full_content(),
+            None,
+        )
+        .await
+        .map_err(|e| EnclaveError::Submission(format!("Transaction execution failed: {}", e)))?;
+
+    check_status(&response)?;
+    Ok(response)
+}
+
+/// Whether the transaction `digest` executed successfully, None if the node
+/// does not know it, e.g. because it never reached the network.
+pub async fn transaction_succeeded(client: &SuiClient, digest: &str) -> Option<bool> {
+    let digest = digest.parse().ok()?;
+    let response = client
+        .read_api()
+        .get_transaction_with_options(digest, SuiTransactionBlockResponseOptions::new().with_effects())
+        .await
+        .ok()?;
+    Some(response.effects?.status().is_ok())
+}
+
+/// Output of the swap executed in `response`, from the DEX `SwapEvent`.
+pub fn swap_output(response: &SuiTransactionBlockResponse) -> Result<u64, EnclaveError> {
+    response
+        .events
+        .iter()
+        .flat_map(|events| &events.data)
+        .find(|event| event.type_.module.as_str() == "dex" && event.type_.name.as_str() == "SwapEvent")
+        .and_then(|event| event.parsed_json.get("amount_out").and_then(json_u64))
+        .ok_or_else(|| EnclaveError::Internal(format!("Swap {} emitted no SwapEvent", response.digest)))
+}
+
+/// Fail with the decoded error if the effects of an executed transaction report a failure.
+pub fn check_status(response: &SuiTransactionBlockResponse) -> Result<(), EnclaveError> {
+    match response.effects.as_ref().map(|effects| effects.status().clone()) {
+        Some(SuiExecutionStatus::Failure { error }) => {
+            let error = EnclaveError::from_execution_failure(response.digest.to_string(), error);
+            Err(aborts::decode(error, &AbortContext::default()))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Hybrid public key encryption over ristretto255: an ephemeral Diffie-Hellman
+// key is combined with the recipient key, expanded with HKDF and used for
+// AES-256-GCM. Used to move wallet secrets out of the enclave.
+
+use crate::EnclaveError;
+use fastcrypto::aes::{Aes256Gcm, AesKey, AuthenticatedCipher, InitializationVector};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::groups::ristretto255::{RistrettoPoint, RistrettoScalar};
+use fastcrypto::groups::{GroupElement, Scalar};
+use fastcrypto::hmac::{hkdf_sha3_256, HkdfIkm};
+use fastcrypto::traits::{Generate, ToFromBytes};
+use serde::{Deserialize, Serialize};
+use typenum::U12;
+
+const ECIES_SALT: &[u8] = b"nautilus-ecies-v1";
+
+/// Ciphertext together with the sender's ephemeral public key, all hex encoded.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncryptedPayload {
+    pub ephemeral_key: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Receiving side key pair.
+pub struct EncryptionKeyPair {
+    secret: RistrettoScalar,
+    public: RistrettoPoint,
+}
+
+impl EncryptionKeyPair {
+    pub fn generate() -> Self {
+        let secret = RistrettoScalar::rand(&mut rand::thread_rng());
+        Self {
+            secret,
+            public: RistrettoPoint::generator() * secret,
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.compress()
+    }
+
+    pub fn decrypt(&self, payload: &EncryptedPayload, aad: &[u8]) -> Result<Vec<u8>, EnclaveError> {
+        let ephemeral = decode_point(&hex_decode(&payload.ephemeral_key)?)?;
+        let cipher = derive_cipher(&(ephemeral * self.secret), &ephemeral, &self.public)?;
+        let iv = InitializationVector::<U12>::from_bytes(&hex_decode(&payload.nonce)?)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid nonce: {}", e)))?;
+        cipher
+            .decrypt_authenticated(&iv, aad, &hex_decode(&payload.ciphertext)?)
+            .map_err(|_| EnclaveError::GenericError("Decryption failed".to_string()))
+    }
+}
+
+/// Encrypt `plaintext` to a compressed ristretto255 public key.
+pub fn encrypt(
+    recipient: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<EncryptedPayload, EnclaveError> {
+    let recipient = decode_point(recipient)?;
+    let ephemeral_secret = RistrettoScalar::rand(&mut rand::thread_rng());
+    let ephemeral = RistrettoPoint::generator() * ephemeral_secret;
+
+    let cipher = derive_cipher(&(recipient * ephemeral_secret), &ephemeral, &recipient)?;
+    let iv = InitializationVector::<U12>::generate(&mut rand::thread_rng());
+    let ciphertext = cipher.encrypt_authenticated(&iv, aad, plaintext);
+
+    Ok(EncryptedPayload {
+        ephemeral_key: Hex::encode(ephemeral.compress()),
+        nonce: Hex::encode(iv.as_bytes()),
+        ciphertext: Hex::encode(ciphertext),
+    })
+}
+
+fn derive_cipher(
+    shared: &RistrettoPoint,
+    ephemeral: &RistrettoPoint,
+    recipient: &RistrettoPoint,
+) -> Result<Aes256Gcm<U12>, EnclaveError> {
+    let ikm = HkdfIkm::from_bytes(&shared.compress())
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid shared secret: {}", e)))?;
+    let info = [ephemeral.compress(), recipient.compress()].concat();
+    let key = hkdf_sha3_256(&ikm, ECIES_SALT, &info, 32)
+        .map_err(|e| EnclaveError::GenericError(format!("Key derivation failed: {}", e)))?;
+    let key = AesKey::from_bytes(&key)
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid AES key: {}", e)))?;
+    Ok(Aes256Gcm::<U12>::new(key))
+}
+
+fn decode_point(bytes: &[u8]) -> Result<RistrettoPoint, EnclaveError> {
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| EnclaveError::GenericError("Public key must be 32 bytes".to_string()))?;
+    let point = RistrettoPoint::decompress(&bytes)
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid public key: {}", e)))?;
+    if point == RistrettoPoint::zero() {
+        return Err(EnclaveError::GenericError("Invalid public key".to_string()));
+    }
+    Ok(point)
+}
+
+fn hex_decode(value: &str) -> Result<Vec<u8>, EnclaveError> {
+    Hex::decode(value).map_err(|e| EnclaveError::GenericError(format!("Invalid hex: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let recipient = EncryptionKeyPair::generate();
+        let payload = encrypt(&recipient.public_key(), b"aad", b"wallet secrets").unwrap();
+
+        assert_eq!(recipient.decrypt(&payload, b"aad").unwrap(), b"wallet secrets");
+        assert!(recipient.decrypt(&payload, b"other aad").is_err());
+        assert!(EncryptionKeyPair::generate().decrypt(&payload, b"aad").is_err());
+    }
+}
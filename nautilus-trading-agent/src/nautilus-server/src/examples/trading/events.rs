@@ -0,0 +1,632 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Listener for marketplace events. Polls `queryEvents` every
+// `EVENT_POLL_INTERVAL_SECS` (default 10) from the last processed event and
+// applies, in order:
+//
+// - `AgentWalletUpdated`: which agents this enclave's wallet manages,
+// - `UserSubscribed`: the subscribers of each agent and their expiry,
+// - `UserDeposited`: mints shares in the share ledger for managed agents,
+//   refunding deposits outside the agent's deposit limits (which requires
+//   `EVENT_STATE_PATH`),
+// - `RewardsClaimed`: rewards claimed per subscriber,
+// - `AgentCreated`, `AgentStatusUpdated`: reloads the wallet's agent.
+//
+// With `EVENT_STATE_PATH` set, the cursor is saved together with the state it
+// produced (including the share ledgers), so a restart resumes where it left
+// off without applying an event twice. Refunds are saved as submitted, with
+// their transaction digest, before they are sent. One whose transaction may
+// have executed is not sent again: later syncs look up the digest, drop the
+// refund once it executed and send it again if it failed.
+//
+// Events are read and refunds sent without holding the listener state or the
+// share ledgers, which are only locked to apply a sync's events at once.
+
+use super::chain::{self, sui_client};
+use super::shares::{self, Deposit, Refund, ShareLedger, LEDGERS};
+use super::{agent, expiry, get_current_timestamp};
+use super::{SUBSCRIPTION_MANAGER_PACKAGE_ID, TRADING_WALLET};
+use crate::EnclaveError;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use sui_json_rpc_types::EventFilter;
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::event::EventID;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+const EVENT_STATE_PATH_ENV: &str = "EVENT_STATE_PATH";
+const EVENT_POLL_INTERVAL_ENV: &str = "EVENT_POLL_INTERVAL_SECS";
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 10;
+const EVENT_PAGE_SIZE: usize = 50;
+/// NAV reads before giving up on a sync while deposits keep arriving.
+const MAX_NAV_READS: usize = 3;
+
+lazy_static! {
+    static ref STATE: Mutex<ListenerState> = Mutex::new(ListenerState::default());
+    /// Held by the running sync, which only locks `STATE` and the ledgers briefly.
+    static ref SYNC: Mutex<()> = Mutex::new(());
+}
+
+/// A subscriber as announced by `UserSubscribed`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscriber {
+    pub subscription_id: ObjectID,
+    pub subscription_end: u64,
+}
+
+/// Everything derived from the events up to `cursor`, and the withdrawal
+/// signatures accepted so far.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct ListenerState {
+    cursor: Option<EventID>,
+    events_processed: u64,
+    agent_wallets: BTreeMap<ObjectID, SuiAddress>,
+    subscribers: BTreeMap<ObjectID, BTreeMap<SuiAddress, Subscriber>>,
+    rewards_claimed: BTreeMap<ObjectID, BTreeMap<SuiAddress, u64>>,
+    /// Timestamp of the last accepted withdrawal signature per subscriber
+    #[serde(default)]
+    withdrawals_authorized: BTreeMap<SuiAddress, u64>,
+}
+
+/// Snapshot written to `EVENT_STATE_PATH`.
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    listener: ListenerState,
+    ledgers: BTreeMap<ObjectID, ShareLedger>,
+}
+
+/// Listener progress, reported in `/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventStatus {
+    pub cursor: Option<EventID>,
+    pub events_processed: u64,
+    pub managed_agents: Vec<String>,
+    pub subscribers: usize,
+}
+
+/// Restore the saved state and start polling.
+pub async fn start_event_listener() -> Result<(), EnclaveError> {
+    let interval = std::env::var(EVENT_POLL_INTERVAL_ENV)
+        .ok()
+        .map(|secs| secs.parse::<u64>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", EVENT_POLL_INTERVAL_ENV, e)))?
+        .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+
+    if let Ok(path) = std::env::var(EVENT_STATE_PATH_ENV) {
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                let saved: SavedState = serde_json::from_slice(&bytes)
+                    .map_err(|e| EnclaveError::Internal(format!("Invalid event state {}: {}", path, e)))?;
+                info!("Resuming subscription events after {:?}", saved.listener.cursor);
+                for refund in saved.ledgers.values().flat_map(|ledger| &ledger.refunds) {
+                    if let (Some(submitted_ms), None) = (refund.submitted_ms, &refund.tx_digest) {
+                        warn!(
+                            "Refund of {} MIST to {} for deposit {:?} was submitted at {} but not confirmed, check it on chain",
+                            refund.amount, refund.user, refund.deposit, submitted_ms
+                        );
+                    }
+                }
+                *LEDGERS.lock().await = saved.ledgers;
+                *STATE.lock().await = saved.listener;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(EnclaveError::Internal(format!("Failed to read {}: {}", path, e))),
+        }
+    } else {
+        info!(
+            "{} not set, subscription events are replayed after a restart and subscription withdrawals are refused",
+            EVENT_STATE_PATH_ENV
+        );
+    }
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = sync().await {
+                warn!("Polling subscription events failed: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+    Ok(())
+}
+
+/// What the events read in a sync ask for besides the listener state.
+#[derive(Debug, Default)]
+struct Batch {
+    deposits: BTreeMap<ObjectID, Vec<Deposit>>,
+    updated_agents: BTreeSet<ObjectID>,
+}
+
+/// Apply all events since the cursor. Waits for the wallet, since deposits are
+/// only attributed to agents it manages.
+pub async fn sync() -> Result<(), EnclaveError> {
+    let (keypair, wallet_address, wallet_agent) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.keypair.clone(), wallet.address.clone(), wallet.agent_id.clone()),
+        None => return Ok(()),
+    };
+    let wallet = wallet_address
+        .parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid wallet address: {}", e)))?;
+    let wallet_agent = wallet_agent
+        .map(|agent_id| agent_id.parse::<ObjectID>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?;
+    let client = sui_client().await?;
+    if let Some(agent_id) = wallet_agent {
+        agent::load(agent_id).await?;
+    }
+
+    // One sync at a time, the listener state and ledgers are only locked to read and apply
+    let _sync = SYNC.lock().await;
+    // Refunds confirmed since the last sync have left the wallet, so they are not owed in the NAV
+    confirm_refunds(&client).await;
+    let filter = EventFilter::Package(
+        SUBSCRIPTION_MANAGER_PACKAGE_ID
+            .parse()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid package ID: {}", e)))?,
+    );
+
+    // Read every event up to the head before minting, so the deposits are
+    // priced at the NAV less all of them. The events are read again after the
+    // NAV, so it never includes a deposit that was not read
+    let mut listener = STATE.lock().await.clone();
+    let mut batch = Batch::default();
+    read_events(&client, &filter, &mut listener, wallet, &mut batch).await?;
+    let mut nav = None;
+    if !batch.deposits.is_empty() {
+        for _ in 0..MAX_NAV_READS {
+            let value = shares::nav(&client, &wallet_address).await?.value;
+            if read_events(&client, &filter, &mut listener, wallet, &mut batch).await? == 0 {
+                nav = Some(value);
+                break;
+            }
+        }
+        if nav.is_none() {
+            return Err(EnclaveError::Conflict(
+                "Deposits kept arriving while reading the NAV, retrying on the next poll".to_string(),
+            ));
+        }
+    }
+
+    if let Some(agent_id) = wallet_agent.filter(|agent_id| batch.updated_agents.contains(agent_id)) {
+        agent::refresh(&client, agent_id).await?;
+    }
+    let metadata = agent::current().await;
+    let limits_of = |agent_id: &ObjectID| {
+        metadata
+            .as_ref()
+            .filter(|metadata| metadata.agent_id == agent_id.to_string())
+            .map(|metadata| (metadata.min_deposit, metadata.max_deposit))
+    };
+    if batch.deposits.keys().any(|agent_id| limits_of(agent_id).is_some()) {
+        require_persistence("enforce deposit limits")?;
+    }
+
+    // Apply the events at once
+    let due: Vec<(ObjectID, Refund)> = {
+        let mut state = STATE.lock().await;
+        let mut ledgers = LEDGERS.lock().await;
+        if let Some(nav) = nav {
+            for (agent_id, deposits) in batch.deposits {
+                let limits = limits_of(&agent_id);
+                ledgers.entry(agent_id).or_default().apply_deposits(deposits, nav, limits);
+            }
+        }
+        let changed = listener.cursor != state.cursor;
+        // Withdrawal signatures may have been accepted since the state was read
+        listener.withdrawals_authorized = std::mem::take(&mut state.withdrawals_authorized);
+        *state = listener;
+        if changed {
+            save_locked(&state, &ledgers)?;
+        }
+        ledgers
+            .iter()
+            .flat_map(|(agent_id, ledger)| {
+                ledger
+                    .refunds
+                    .iter()
+                    .filter(|refund| refund.submitted_ms.is_none())
+                    .map(move |refund| (*agent_id, refund.clone()))
+            })
+            .collect()
+    };
+
+    // Pay back deposits rejected by the deposit limits, without the locks
+    let sender = SuiAddress::from(&keypair.public());
+    for (agent_id, refund) in due {
+        let tx_data =
+            match expiry::return_transaction(&client, sender, agent_id, refund.user, refund.amount, refund.amount).await {
+                Ok(tx_data) => tx_data,
+                Err(e) => {
+                    warn!("Failed to refund {} MIST to {}: {}", refund.amount, refund.user, e);
+                    continue;
+                }
+            };
+        // Saved as submitted with its digest first, so neither a crash nor a
+        // restart can pay it twice and a later sync can confirm it
+        let tx_digest = tx_data.digest().to_string();
+        set_submitted(agent_id, &refund, Some((get_current_timestamp(), tx_digest))).await?;
+        match chain::sign_and_execute(&client, &keypair, tx_data).await {
+            Ok(response) => {
+                info!("Refunded {} MIST to {} in {}", refund.amount, refund.user, response.digest);
+                remove_refund(agent_id, &refund).await;
+            }
+            // The transaction may have reached the network
+            Err(EnclaveError::Submission(e)) => warn!(
+                "Refund of {} MIST to {} was submitted but not confirmed, confirming it on the next sync: {}",
+                refund.amount, refund.user, e
+            ),
+            Err(e) => {
+                warn!("Failed to refund {} MIST to {}: {}", refund.amount, refund.user, e);
+                if let Err(e) = set_submitted(agent_id, &refund, None).await {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Confirm the refunds whose transaction may have executed by their digest: an
+/// executed refund is no longer owed, a failed one is sent again. Refunds
+/// submitted without a digest are left for the operator to reconcile.
+async fn confirm_refunds(client: &SuiClient) {
+    let submitted: Vec<(ObjectID, Refund)> = LEDGERS
+        .lock()
+        .await
+        .iter()
+        .flat_map(|(agent_id, ledger)| {
+            ledger
+                .refunds
+                .iter()
+                .filter(|refund| refund.submitted_ms.is_some() && refund.tx_digest.is_some())
+                .map(move |refund| (*agent_id, refund.clone()))
+        })
+        .collect();
+    for (agent_id, refund) in submitted {
+        let tx_digest = refund.tx_digest.clone().unwrap_or_default();
+        match chain::transaction_succeeded(client, &tx_digest).await {
+            Some(true) => {
+                info!("Refund of {} MIST to {} confirmed in {}", refund.amount, refund.user, tx_digest);
+                remove_refund(agent_id, &refund).await;
+            }
+            Some(false) => {
+                warn!("Refund of {} MIST to {} failed in {}, sending it again", refund.amount, refund.user, tx_digest);
+                if let Err(e) = set_submitted(agent_id, &refund, None).await {
+                    warn!("{}", e);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Record `refund` as submitted at the time and digest given, or as not
+/// submitted, and save it.
+async fn set_submitted(agent_id: ObjectID, refund: &Refund, submitted: Option<(u64, String)>) -> Result<(), EnclaveError> {
+    let state = STATE.lock().await;
+    let mut ledgers = LEDGERS.lock().await;
+    let Some(owed) = ledgers
+        .get_mut(&agent_id)
+        .and_then(|ledger| ledger.refunds.iter_mut().find(|owed| owed.deposit == refund.deposit))
+    else {
+        return Ok(());
+    };
+    let previous = (owed.submitted_ms, owed.tx_digest.clone());
+    (owed.submitted_ms, owed.tx_digest) = submitted.unzip();
+    if let Err(e) = save_locked(&state, &ledgers) {
+        if let Some(owed) = ledgers
+            .get_mut(&agent_id)
+            .and_then(|ledger| ledger.refunds.iter_mut().find(|owed| owed.deposit == refund.deposit))
+        {
+            (owed.submitted_ms, owed.tx_digest) = previous;
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Drop a refund that was paid and save the ledgers.
+async fn remove_refund(agent_id: ObjectID, refund: &Refund) {
+    if let Some(ledger) = LEDGERS.lock().await.get_mut(&agent_id) {
+        ledger.refunds.retain(|owed| owed.deposit != refund.deposit);
+    }
+    save().await;
+}
+
+/// Apply the events after the cursor of `state` up to the head, and return how many were read.
+async fn read_events(
+    client: &SuiClient,
+    filter: &EventFilter,
+    state: &mut ListenerState,
+    wallet: SuiAddress,
+    batch: &mut Batch,
+) -> Result<usize, EnclaveError> {
+    let mut read = 0;
+    loop {
+        let page = client
+            .event_api()
+            .query_events(filter.clone(), state.cursor.clone(), Some(EVENT_PAGE_SIZE), false)
+            .await
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to query marketplace events: {}", e)))?;
+        for event in &page.data {
+            state.apply(event.type_.name.as_str(), &event.id, &event.parsed_json, wallet, batch);
+        }
+        read += page.data.len();
+        if let Some(last) = page.data.last() {
+            state.cursor = Some(last.id.clone());
+        }
+        if !page.has_next_page {
+            return Ok(read);
+        }
+    }
+}
+
+impl ListenerState {
+    /// Record `timestamp_ms` as the last withdrawal signature of `subscriber`
+    /// unless it is not newer than the last one, and return the previous one.
+    fn accept_withdrawal(&mut self, subscriber: SuiAddress, timestamp_ms: u64) -> Result<Option<u64>, EnclaveError> {
+        let previous = self.withdrawals_authorized.get(&subscriber).copied();
+        if previous.is_some_and(|last| timestamp_ms <= last) {
+            return Err(EnclaveError::Unauthorized("Signed message already used".to_string()));
+        }
+        self.withdrawals_authorized.insert(subscriber, timestamp_ms);
+        Ok(previous)
+    }
+
+    /// Apply the event `name` with `json` fields; deposits for agents managed by `wallet` are collected for minting.
+    fn apply(&mut self, name: &str, id: &EventID, json: &serde_json::Value, wallet: SuiAddress, batch: &mut Batch) {
+        let agent_id = match json_object_id(json, "agent_id") {
+            Some(agent_id) => agent_id,
+            None => return,
+        };
+        self.events_processed += 1;
+
+        match name {
+            "AgentWalletUpdated" => {
+                if let Some(new_wallet) = json_address(json, "new_wallet") {
+                    self.agent_wallets.insert(agent_id, new_wallet);
+                }
+            }
+            "UserSubscribed" => {
+                if let (Some(subscriber), Some(subscription_id), Some(subscription_end)) = (
+                    json_address(json, "subscriber"),
+                    json_object_id(json, "subscription_id"),
+                    json.get("subscription_end").and_then(json_u64),
+                ) {
+                    self.subscribers.entry(agent_id).or_default().insert(
+                        subscriber,
+                        Subscriber {
+                            subscription_id,
+                            subscription_end,
+                        },
+                    );
+                }
+            }
+            "UserDeposited" if self.agent_wallets.get(&agent_id) == Some(&wallet) => {
+                if let (Some(user), Some(amount)) =
+                    (json_address(json, "user"), json.get("amount").and_then(json_u64))
+                {
+                    batch.deposits.entry(agent_id).or_default().push(Deposit {
+                        id: id.clone(),
+                        user,
+                        amount,
+                    });
+                }
+            }
+            "RewardsClaimed" => {
+                if let (Some(user), Some(reward)) =
+                    (json_address(json, "user"), json.get("reward_amount").and_then(json_u64))
+                {
+                    *self.rewards_claimed.entry(agent_id).or_default().entry(user).or_default() += reward;
+                }
+            }
+            "AgentCreated" | "AgentStatusUpdated" => {
+                batch.updated_agents.insert(agent_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Save the listener state and share ledgers, e.g. after a withdrawal burned shares.
+pub async fn save() {
+    let state = STATE.lock().await;
+    let ledgers = LEDGERS.lock().await;
+    if let Err(e) = save_locked(&state, &ledgers) {
+        warn!("{}", e);
+    }
+}
+
+/// Accept the withdrawal signed by `subscriber` at `timestamp_ms` once, in
+/// order, and save it before funds move so a restart cannot accept it again.
+pub async fn authorize_withdrawal(subscriber: SuiAddress, timestamp_ms: u64) -> Result<(), EnclaveError> {
+    let mut state = STATE.lock().await;
+    let previous = state.accept_withdrawal(subscriber, timestamp_ms)?;
+    let ledgers = LEDGERS.lock().await;
+    if let Err(e) = save_locked(&state, &ledgers) {
+        match previous {
+            Some(previous) => state.withdrawals_authorized.insert(subscriber, previous),
+            None => state.withdrawals_authorized.remove(&subscriber),
+        };
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Fail unless the event state is persisted, before `action` moves funds based
+/// on it: after a restart the events would be applied again.
+pub fn require_persistence(action: &str) -> Result<(), EnclaveError> {
+    if std::env::var(EVENT_STATE_PATH_ENV).is_err() {
+        return Err(EnclaveError::Internal(format!(
+            "{} must be set to {}",
+            EVENT_STATE_PATH_ENV, action
+        )));
+    }
+    Ok(())
+}
+
+fn save_locked(state: &ListenerState, ledgers: &BTreeMap<ObjectID, ShareLedger>) -> Result<(), EnclaveError> {
+    let Ok(path) = std::env::var(EVENT_STATE_PATH_ENV) else {
+        return Ok(());
+    };
+    let saved = SavedState {
+        listener: state.clone(),
+        ledgers: ledgers.clone(),
+    };
+    // Write then rename, so a crash never leaves a partial snapshot
+    let tmp = format!("{}.tmp", path);
+    let result = serde_json::to_vec(&saved)
+        .map_err(std::io::Error::other)
+        .and_then(|bytes| std::fs::write(&tmp, bytes))
+        .and_then(|_| std::fs::rename(&tmp, &path));
+    result.map_err(|e| EnclaveError::Internal(format!("Failed to save event state to {}: {}", path, e)))
+}
+
+/// Subscribers of `agent_id` seen in `UserSubscribed` events.
+pub async fn subscribers(agent_id: ObjectID) -> BTreeMap<SuiAddress, Subscriber> {
+    STATE
+        .lock()
+        .await
+        .subscribers
+        .get(&agent_id)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Whether `user` subscribed to `agent_id`, as far as the processed events show.
+pub async fn is_subscriber(agent_id: ObjectID, user: &SuiAddress) -> bool {
+    STATE
+        .lock()
+        .await
+        .subscribers
+        .get(&agent_id)
+        .is_some_and(|subscribers| subscribers.contains_key(user))
+}
+
+pub async fn status() -> EventStatus {
+    let state = STATE.lock().await;
+    EventStatus {
+        cursor: state.cursor.clone(),
+        events_processed: state.events_processed,
+        managed_agents: state.agent_wallets.keys().map(|agent_id| agent_id.to_string()).collect(),
+        subscribers: state.subscribers.values().map(BTreeMap::len).sum(),
+    }
+}
+
+fn json_object_id(json: &serde_json::Value, field: &str) -> Option<ObjectID> {
+    json.get(field)?.as_str()?.parse().ok()
+}
+
+fn json_address(json: &serde_json::Value, field: &str) -> Option<SuiAddress> {
+    json.get(field)?.as_str()?.parse().ok()
+}
+
+/// u64 fields are rendered as strings in event JSON.
+pub fn json_u64(value: &serde_json::Value) -> Option<u64> {
+    match value {
+        serde_json::Value::String(value) => value.parse().ok(),
+        value => value.as_u64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sui_types::digests::TransactionDigest;
+
+    #[test]
+    fn test_apply_events() {
+        let agent = ObjectID::random();
+        let wallet = SuiAddress::random_for_testing_only();
+        let user = SuiAddress::random_for_testing_only();
+        let id = |event_seq| EventID {
+            tx_digest: TransactionDigest::random(),
+            event_seq,
+        };
+        let mut state = ListenerState::default();
+        let mut batch = Batch::default();
+
+        // Deposits before the agent is bound to our wallet are not ours
+        let deposit = serde_json::json!({ "agent_id": agent.to_string(), "user": user.to_string(), "amount": "100", "timestamp": "1" });
+        state.apply("UserDeposited", &id(0), &deposit, wallet, &mut batch);
+        assert!(batch.deposits.is_empty());
+
+        let updated = serde_json::json!({ "agent_id": agent.to_string(), "old_wallet": "0x0", "new_wallet": wallet.to_string(), "timestamp": "2" });
+        state.apply("AgentWalletUpdated", &id(1), &updated, wallet, &mut batch);
+        let subscribed = serde_json::json!({
+            "agent_id": agent.to_string(),
+            "subscription_id": ObjectID::random().to_string(),
+            "subscriber": user.to_string(),
+            "subscription_fee_paid": "10",
+            "subscription_end": "5000",
+            "timestamp": "3",
+        });
+        state.apply("UserSubscribed", &id(2), &subscribed, wallet, &mut batch);
+        state.apply("UserDeposited", &id(3), &deposit, wallet, &mut batch);
+
+        assert_eq!(batch.deposits[&agent].len(), 1);
+        assert_eq!(batch.deposits[&agent][0].amount, 100);
+        assert_eq!(state.subscribers[&agent][&user].subscription_end, 5000);
+        assert_eq!(state.events_processed, 4);
+
+        // The saved state restores the same listener state
+        let saved = serde_json::to_vec(&state).unwrap();
+        let restored: ListenerState = serde_json::from_slice(&saved).unwrap();
+        assert_eq!(restored.agent_wallets.get(&agent), Some(&wallet));
+        assert_eq!(restored.subscribers[&agent].len(), 1);
+    }
+
+    #[test]
+    fn test_withdrawal_signatures_are_used_once() {
+        let user = SuiAddress::random_for_testing_only();
+        let mut state = ListenerState::default();
+        assert_eq!(state.accept_withdrawal(user, 1_000).unwrap(), None);
+
+        // Replayed, or older than the last accepted one
+        assert!(matches!(state.accept_withdrawal(user, 1_000), Err(EnclaveError::Unauthorized(_))));
+        assert!(matches!(state.accept_withdrawal(user, 999), Err(EnclaveError::Unauthorized(_))));
+        assert_eq!(state.accept_withdrawal(user, 1_001).unwrap(), Some(1_000));
+
+        // Still rejected after a restart from the saved state
+        let saved = serde_json::to_vec(&state).unwrap();
+        let mut restored: ListenerState = serde_json::from_slice(&saved).unwrap();
+        assert!(matches!(restored.accept_withdrawal(user, 1_001), Err(EnclaveError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn test_deposits_across_pages() {
+        let agent = ObjectID::random();
+        let wallet = SuiAddress::random_for_testing_only();
+        let alice = SuiAddress::random_for_testing_only();
+        let bob = SuiAddress::random_for_testing_only();
+        let id = |event_seq| EventID {
+            tx_digest: TransactionDigest::random(),
+            event_seq,
+        };
+        let deposit = |user: SuiAddress| {
+            serde_json::json!({ "agent_id": agent.to_string(), "user": user.to_string(), "amount": "1000", "timestamp": "1" })
+        };
+        let mut state = ListenerState::default();
+        let mut batch = Batch::default();
+
+        // Two pages, each with one deposit, read before the NAV
+        let updated = serde_json::json!({ "agent_id": agent.to_string(), "old_wallet": "0x0", "new_wallet": wallet.to_string(), "timestamp": "0" });
+        state.apply("AgentWalletUpdated", &id(0), &updated, wallet, &mut batch);
+        state.apply("UserDeposited", &id(1), &deposit(alice), wallet, &mut batch);
+        state.apply("UserDeposited", &id(0), &deposit(bob), wallet, &mut batch);
+
+        // The wallet held 500 of the creator's before both deposits
+        let mut ledger = ShareLedger::default();
+        ledger.apply_deposits(batch.deposits.remove(&agent).unwrap(), 2_500, None);
+        assert_eq!(ledger.unowned, 500);
+        assert_eq!(ledger.value_of(ledger.positions[&alice].shares, 2_500), 1_000);
+        assert_eq!(ledger.value_of(ledger.positions[&bob].shares, 2_500), 1_000);
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Subscription expiry. Every `EXPIRY_CHECK_INTERVAL_SECS` (default 300) the
+// depositors of the wallet's agent are checked against their `subscription_end`,
+// as seen by the event listener or read from the `SubscriptionManager`:
+//
+// - an expired position is marked and excluded from trading, trades may only
+//   use the wallet's balance in proportion to the shares of active positions,
+// - a renewed subscription makes the position active again,
+// - with `AUTO_RETURN_EXPIRED=true`, an expired position is settled (its
+//   performance fee charged), paid out in SUI to the subscriber and closed.
+//   The settlement is signed under `IntentScope::Settlement` and journaled.
+//   This requires `EVENT_STATE_PATH`, so a restart cannot return it again.
+
+use super::chain::{gas_coin, shared_object_arg, sign_and_execute, sui_client};
+use super::fees::{self, FeeLine};
+use super::journal::{self, JournalEvent};
+use super::shares::{self, LEDGERS};
+use super::subscriptions::load_subscription;
+use super::{events, get_current_timestamp, SUBSCRIPTION_MANAGER_ID, SUBSCRIPTION_MANAGER_PACKAGE_ID, TRADING_WALLET};
+use crate::common::{to_signed_response, IntentScope};
+use crate::{AppState, EnclaveError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::crypto::SuiKeyPair;
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_types::transaction::{Argument, Command, TransactionData};
+use sui_types::Identifier;
+use tracing::{info, warn};
+
+const EXPIRY_CHECK_INTERVAL_ENV: &str = "EXPIRY_CHECK_INTERVAL_SECS";
+const AUTO_RETURN_ENV: &str = "AUTO_RETURN_EXPIRED";
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 300;
+const RETURN_GAS_BUDGET: u64 = 20_000_000;
+
+/// Final settlement of an expired position returned to its subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpirySettlement {
+    pub agent_id: String,
+    pub subscriber: String,
+    pub subscription_end: u64,
+    pub nav: u64,
+    /// Performance fee charged before the return, if any
+    pub fee: Option<FeeLine>,
+    pub shares: u64,
+    /// Amount paid to the subscriber, in MIST
+    pub value: u64,
+    /// Part of `value` deducted from the subscriber's deposit in the manager
+    pub deducted: u64,
+    pub tx_digest: String,
+}
+
+/// Start the periodic expiry check.
+pub async fn start_expiry_checks(state: Arc<AppState>) -> Result<(), EnclaveError> {
+    let interval = std::env::var(EXPIRY_CHECK_INTERVAL_ENV)
+        .ok()
+        .map(|secs| secs.parse::<u64>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", EXPIRY_CHECK_INTERVAL_ENV, e)))?
+        .unwrap_or(DEFAULT_CHECK_INTERVAL_SECS);
+    let auto_return = std::env::var(AUTO_RETURN_ENV)
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+    if auto_return {
+        events::require_persistence(&format!("set {}", AUTO_RETURN_ENV))?;
+    } else {
+        info!("Expired positions are kept until withdrawn, set {} to return them", AUTO_RETURN_ENV);
+    }
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            if let Err(e) = check(&state, auto_return).await {
+                warn!("Subscription expiry check failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Mark the positions of lapsed subscriptions and optionally return them.
+pub async fn check(state: &AppState, auto_return: bool) -> Result<(), EnclaveError> {
+    events::sync().await?;
+    let (keypair, wallet_address, agent_id) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => match &wallet.agent_id {
+            Some(agent_id) => (
+                wallet.keypair.clone(),
+                wallet.address.clone(),
+                agent_id
+                    .parse::<ObjectID>()
+                    .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?,
+            ),
+            None => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+
+    let depositors: Vec<SuiAddress> = match LEDGERS.lock().await.get(&agent_id) {
+        Some(ledger) => ledger
+            .positions
+            .iter()
+            .filter(|(_, position)| position.shares > 0)
+            .map(|(user, _)| *user)
+            .collect(),
+        None => return Ok(()),
+    };
+    if depositors.is_empty() {
+        return Ok(());
+    }
+
+    // Expiry as seen by the listener, read from the manager for subscribers it has not seen
+    let client = sui_client().await?;
+    let subscribers = events::subscribers(agent_id).await;
+    let mut ends = BTreeMap::new();
+    for user in depositors {
+        let end = match subscribers.get(&user) {
+            Some(subscriber) => subscriber.subscription_end,
+            None => match load_subscription(&client, agent_id, user).await? {
+                Some(subscription) if subscription.is_active => subscription.subscription_end,
+                _ => 0,
+            },
+        };
+        ends.insert(user, end);
+    }
+
+    let now_ms = get_current_timestamp();
+    let expired = {
+        let mut ledgers = LEDGERS.lock().await;
+        let ledger = ledgers.entry(agent_id).or_default();
+        let mut expired = Vec::new();
+        for (user, end) in &ends {
+            let Some(position) = ledger.positions.get_mut(user) else {
+                continue;
+            };
+            if *end <= now_ms {
+                if position.expired_at_ms.is_none() {
+                    info!("Subscription of {} to agent {} expired, its shares are no longer traded", user, agent_id);
+                    position.expired_at_ms = Some(now_ms);
+                }
+                expired.push((*user, *end));
+            } else if position.expired_at_ms.take().is_some() {
+                info!("Subscription of {} to agent {} was renewed", user, agent_id);
+            }
+        }
+        expired
+    };
+    events::save().await;
+
+    if auto_return {
+        for (user, end) in expired {
+            if let Err(e) = return_position(state, &client, &keypair, &wallet_address, agent_id, user, end).await {
+                warn!("Failed to return the position of {}: {}", user, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fail if a trade of `amount` would use more of `balance` than belongs to active positions.
+pub async fn check_allocation(agent_id: &str, balance: u64, amount: u64) -> Result<(), EnclaveError> {
+    let agent_id = agent_id
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?;
+    let ledgers = LEDGERS.lock().await;
+    let Some(ledger) = ledgers.get(&agent_id).filter(|ledger| ledger.total_shares > 0) else {
+        return Ok(());
+    };
+    let allocatable = (balance as u128 * ledger.active_shares() as u128 / ledger.total_shares as u128) as u64;
+    if amount > allocatable {
+        return Err(EnclaveError::InsufficientBalance(format!(
+            "Trade of {} exceeds the {} held for active subscriptions",
+            amount, allocatable
+        )));
+    }
+    Ok(())
+}
+
+/// Settle the position of `user`, pay it out and close it.
+async fn return_position(
+    state: &AppState,
+    client: &SuiClient,
+    keypair: &SuiKeyPair,
+    wallet_address: &str,
+    agent_id: ObjectID,
+    user: SuiAddress,
+    subscription_end: u64,
+) -> Result<(), EnclaveError> {
+    let nav = shares::nav(client, wallet_address).await?;
+    let fee_bps = fees::fee_bps()?;
+    let deposited = load_subscription(client, agent_id, user).await?.map(|subscription| subscription.deposited);
+
+    // Close the position before funds move, so it cannot be paid twice and the
+    // ledger is not locked while the payout executes
+    let (fee, shares, value) = {
+        let mut ledgers = LEDGERS.lock().await;
+        let ledger = ledgers.entry(agent_id).or_default();
+        let fee = fees::settle_position(ledger, &user, nav.value, fee_bps);
+        let (shares, value) = ledger.reserve_close(&user, nav.value);
+        (fee, shares, value)
+    };
+    events::save().await;
+    let deducted = deposited.map_or(0, |deposited| deposited.min(value));
+
+    let result = if value > 0 {
+        submit_return(client, keypair, agent_id, user, value, deducted).await
+    } else {
+        Ok(String::new())
+    };
+    let mut ledgers = LEDGERS.lock().await;
+    let ledger = ledgers.entry(agent_id).or_default();
+    let tx_digest = match result {
+        Ok(tx_digest) => {
+            ledger.finish_withdrawal(value);
+            tx_digest
+        }
+        // The transaction may have executed, so the position stays closed
+        Err(e @ EnclaveError::Submission(_)) => {
+            ledger.finish_withdrawal(value);
+            warn!("Return of {} to {} may have executed, its position stays closed", value, user);
+            return Err(e);
+        }
+        Err(e) => {
+            ledger.cancel_withdrawal(&user, value, shares);
+            if let Some(fee) = &fee {
+                fees::unsettle_position(ledger, &user, fee);
+            }
+            drop(ledgers);
+            events::save().await;
+            return Err(e);
+        }
+    };
+    drop(ledgers);
+    info!("Returned {} MIST to {} after its subscription to {} expired", value, user, agent_id);
+
+    let timestamp_ms = get_current_timestamp();
+    let settlement = ExpirySettlement {
+        agent_id: agent_id.to_string(),
+        subscriber: user.to_string(),
+        subscription_end,
+        nav: nav.value,
+        fee,
+        shares,
+        value,
+        deducted,
+        tx_digest: tx_digest.clone(),
+    };
+    let signed = to_signed_response(&state.eph_kp, settlement, timestamp_ms, IntentScope::Settlement);
+    journal::record(
+        &state.eph_kp,
+        JournalEvent {
+            kind: "expiry_return".to_string(),
+            wallet_address: wallet_address.to_string(),
+            request: serde_json::to_string(&signed).unwrap_or_default(),
+            quote: None,
+            tx_digest,
+            amount_in: value,
+            amount_out: None,
+            recipient: Some(user.to_string()),
+        },
+        timestamp_ms,
+    )
+    .await;
+    Ok(())
+}
+
+/// Pay `value` to `user`, deducting `deducted` from its deposit in the manager.
+pub async fn submit_return(
+    client: &SuiClient,
+    keypair: &SuiKeyPair,
+    agent_id: ObjectID,
+    user: SuiAddress,
+    value: u64,
+    deducted: u64,
+) -> Result<String, EnclaveError> {
+    let sender = SuiAddress::from(&keypair.public());
+    let tx_data = return_transaction(client, sender, agent_id, user, value, deducted).await?;
+    let response = sign_and_execute(client, keypair, tx_data).await?;
+    Ok(response.digest.to_string())
+}
+
+/// Transaction of `sender` paying `value` to `user`, as [submit_return] sends it.
+pub async fn return_transaction(
+    client: &SuiClient,
+    sender: SuiAddress,
+    agent_id: ObjectID,
+    user: SuiAddress,
+    value: u64,
+    deducted: u64,
+) -> Result<TransactionData, EnclaveError> {
+    let mut ptb = ProgrammableTransactionBuilder::new();
+    let amount_arg = ptb
+        .pure(value)
+        .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+    let user_arg = ptb
+        .pure(user)
+        .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+
+    if deducted > 0 {
+        let manager_id = SUBSCRIPTION_MANAGER_ID
+            .parse::<ObjectID>()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid subscription manager ID: {}", e)))?;
+        let arguments = vec![
+            ptb.obj(shared_object_arg(client, manager_id, true).await?),
+            ptb.pure(agent_id),
+            ptb.pure(deducted),
+        ]
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+        ptb.programmable_move_call(
+            SUBSCRIPTION_MANAGER_PACKAGE_ID
+                .parse()
+                .map_err(|e| EnclaveError::Internal(format!("Invalid package ID: {}", e)))?,
+            Identifier::new("subscription_manager").expect("valid identifier"),
+            Identifier::new("withdraw_from_agent").expect("valid identifier"),
+            vec![],
+            arguments.into_iter().chain(std::iter::once(user_arg)).collect(),
+        );
+    }
+    let coin = ptb.command(Command::SplitCoins(Argument::GasCoin, vec![amount_arg]));
+    ptb.command(Command::TransferObjects(vec![coin], user_arg));
+
+    let gas_price = client
+        .read_api()
+        .get_reference_gas_price()
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
+    Ok(TransactionData::new_programmable(
+        sender,
+        vec![gas_coin(client, sender, value + RETURN_GAS_BUDGET).await?],
+        ptb.finish(),
+        RETURN_GAS_BUDGET,
+        gas_price,
+    ))
+}
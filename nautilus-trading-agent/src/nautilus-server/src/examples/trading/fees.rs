@@ -0,0 +1,452 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// High-water-mark performance fees on the share ledger. Each position keeps the
+// share price up to which it was charged. A settlement charges
+// `PERFORMANCE_FEE_BPS` (default 2000, i.e. 20%) of the gain of every position
+// whose share price rose above its mark, and raises the mark to the current
+// price, so a gain is only charged once and losses must be recovered first.
+//
+// The fee is taken in shares, moved from the subscriber to the ledger's
+// `fee_shares` held for the agent creator, which keeps the share price
+// unchanged. Every settlement returns a statement signed under
+// `IntentScope::Settlement` that commits to the NAV and the journal head and is
+// recorded in the journal. A dry run returns the same statement marked
+// `dry_run`, which charges nothing.
+//
+// `/claim_fees` pays the fee shares out in SUI, at the current NAV, to the
+// creator of the `TradingAgent` and burns them. The operator's API key is not
+// enough: the creator must sign the claim, so payouts happen when they choose.
+
+use super::auth::{check_signature_timestamp, verify_personal_message};
+use super::chain::sui_client;
+use super::journal::{self, JournalEvent};
+use super::shares::{nav, ShareLedger, LEDGERS, PRICE_SCALE};
+use super::{agent, events, expiry, get_current_timestamp, TRADING_WALLET};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::crypto::SuiKeyPair;
+use tracing::{info, warn};
+
+const PERFORMANCE_FEE_BPS_ENV: &str = "PERFORMANCE_FEE_BPS";
+const DEFAULT_PERFORMANCE_FEE_BPS: u64 = 2_000;
+const MAX_BPS: u64 = 10_000;
+
+/// Fee charged to one subscriber in a settlement.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FeeLine {
+    pub subscriber: String,
+    /// Shares held before the fee
+    pub shares: u64,
+    pub high_water_mark: u64,
+    /// Gain above the high-water mark, in MIST
+    pub gain: u64,
+    pub fee: u64,
+    pub fee_shares: u64,
+}
+
+/// Performance fees of an agent since its previous settlement, signed under
+/// `IntentScope::Settlement`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlementStatement {
+    pub agent_id: String,
+    pub wallet_address: String,
+    /// Number of the settlement for this agent, starting at 1
+    pub sequence: u64,
+    pub period_start_ms: u64,
+    pub period_end_ms: u64,
+    pub nav: u64,
+    pub total_shares: u64,
+    /// Value of `PRICE_SCALE` shares in MIST
+    pub share_price: u64,
+    pub fee_bps: u64,
+    pub lines: Vec<FeeLine>,
+    pub total_fee: u64,
+    /// Fee shares held for the creator after this settlement, and their value
+    pub fee_shares: u64,
+    pub fee_shares_value: u64,
+    /// Trades journaled during the period
+    pub trades: u64,
+    /// Hash of the latest journal entry when the statement was made
+    pub journal_head: String,
+    /// Computed without charging the fees, the ledger is unchanged
+    pub dry_run: bool,
+}
+
+/// Fee shares paid out to the agent creator, signed under `IntentScope::Settlement`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeePayout {
+    pub agent_id: String,
+    pub creator: String,
+    pub nav: u64,
+    /// Fee shares burned for the payout
+    pub fee_shares: u64,
+    /// Amount paid to the creator, in MIST
+    pub value: u64,
+    pub tx_digest: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettleRequest {
+    /// Agent to settle, defaults to the agent the wallet is bound to
+    pub agent_id: Option<String>,
+    pub api_key: String,
+    /// Compute the statement without charging the fees
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClaimFeesRequest {
+    /// Agent to pay the fees of, defaults to the agent the wallet is bound to
+    pub agent_id: Option<String>,
+    pub api_key: String,
+    pub timestamp_ms: u64,
+    /// Agent creator signature over [claim_fees_message]
+    pub signature: String,
+}
+
+/// Message the agent creator signs to claim the fees.
+pub fn claim_fees_message(agent_id: &str, wallet_address: &str, timestamp_ms: u64) -> String {
+    format!("nautilus-claim-fees:{}:{}:{}", agent_id, wallet_address, timestamp_ms)
+}
+
+/// Fee rate from `PERFORMANCE_FEE_BPS`.
+pub fn fee_bps() -> Result<u64, EnclaveError> {
+    let bps = std::env::var(PERFORMANCE_FEE_BPS_ENV)
+        .ok()
+        .map(|bps| bps.parse::<u64>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", PERFORMANCE_FEE_BPS_ENV, e)))?
+        .unwrap_or(DEFAULT_PERFORMANCE_FEE_BPS);
+    if bps > MAX_BPS {
+        return Err(EnclaveError::Internal(format!("{} exceeds {}", PERFORMANCE_FEE_BPS_ENV, MAX_BPS)));
+    }
+    Ok(bps)
+}
+
+/// Charge the fees of every position above its high-water mark at `nav` and
+/// raise the marks. Positions without a mark (restored from before fees were
+/// tracked) get the current price and are not charged.
+pub fn settle(ledger: &mut ShareLedger, nav: u64, fee_bps: u64) -> Vec<FeeLine> {
+    let subscribers: Vec<SuiAddress> = ledger.positions.keys().copied().collect();
+    subscribers
+        .iter()
+        .filter_map(|subscriber| settle_position(ledger, subscriber, nav, fee_bps))
+        .collect()
+}
+
+/// Charge the fee of a single position, as `settle` does.
+pub fn settle_position(ledger: &mut ShareLedger, subscriber: &SuiAddress, nav: u64, fee_bps: u64) -> Option<FeeLine> {
+    let price = ledger.share_price(nav);
+    let position = ledger.positions.get_mut(subscriber)?;
+    if position.shares == 0 {
+        return None;
+    }
+    if position.high_water_mark == 0 {
+        position.high_water_mark = price;
+        return None;
+    }
+    if price <= position.high_water_mark {
+        return None;
+    }
+
+    let gain = (position.shares as u128 * (price - position.high_water_mark) as u128 / PRICE_SCALE as u128) as u64;
+    let fee = (gain as u128 * fee_bps as u128 / MAX_BPS as u128) as u64;
+    let fee_shares = (fee as u128 * PRICE_SCALE as u128 / price as u128) as u64;
+    let line = FeeLine {
+        subscriber: subscriber.to_string(),
+        shares: position.shares,
+        high_water_mark: position.high_water_mark,
+        gain,
+        fee,
+        fee_shares,
+    };
+
+    position.shares -= fee_shares;
+    position.fees_paid += fee;
+    position.high_water_mark = price;
+    ledger.fee_shares += fee_shares;
+    Some(line)
+}
+
+/// Undo the fee `line` charged by `settle_position`, as far as its shares are still held.
+pub fn unsettle_position(ledger: &mut ShareLedger, subscriber: &SuiAddress, line: &FeeLine) {
+    let fee_shares = line.fee_shares.min(ledger.fee_shares);
+    ledger.fee_shares -= fee_shares;
+    let position = ledger.positions.entry(*subscriber).or_default();
+    position.shares += fee_shares;
+    position.fees_paid = position.fees_paid.saturating_sub(line.fee);
+    position.high_water_mark = line.high_water_mark;
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn settle_wrapper(
+    request: ProcessDataRequest<SettleRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match settle_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn claim_fees_wrapper(
+    request: ProcessDataRequest<ClaimFeesRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match claim_fees_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+/// Keypair and address of the wallet, and the agent given or else the one the wallet is bound to.
+async fn wallet_and_agent(agent_id: Option<String>) -> Result<(Arc<SuiKeyPair>, String, ObjectID), EnclaveError> {
+    let (keypair, wallet_address, wallet_agent) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.keypair.clone(), wallet.address.clone(), wallet.agent_id.clone()),
+        None => return Err(EnclaveError::NotFound("Wallet not initialized".to_string())),
+    };
+    let agent_id = agent_id
+        .or(wallet_agent)
+        .ok_or_else(|| EnclaveError::InvalidRequest("No agent_id given and the wallet is not bound to an agent".to_string()))?
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid agent ID: {}", e)))?;
+    Ok((keypair, wallet_address, agent_id))
+}
+
+async fn settle_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<SettleRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<SettlementStatement>>, EnclaveError> {
+    if state.api_key.is_empty() || request.payload.api_key != state.api_key {
+        return Err(EnclaveError::Unauthorized("Invalid API key".to_string()));
+    }
+    let fee_bps = fee_bps()?;
+
+    // Deposits not yet seen by the event listener are minted at their own price first
+    events::sync().await?;
+    let (_, wallet_address, agent_id) = wallet_and_agent(request.payload.agent_id).await?;
+
+    let client = sui_client().await?;
+    let nav = nav(&client, &wallet_address).await?;
+    let now_ms = get_current_timestamp();
+    let entries = journal::entries().await;
+
+    let mut ledgers = LEDGERS.lock().await;
+    let stored = ledgers
+        .get_mut(&agent_id)
+        .ok_or_else(|| EnclaveError::NotFound(format!("No deposits for agent {}", agent_id)))?;
+    let mut ledger = stored.clone();
+    let lines = settle(&mut ledger, nav.value, fee_bps);
+    ledger.settlements += 1;
+    let period_start_ms = ledger.last_settlement_ms;
+    ledger.last_settlement_ms = now_ms;
+
+    let statement = SettlementStatement {
+        agent_id: agent_id.to_string(),
+        wallet_address: wallet_address.clone(),
+        sequence: ledger.settlements,
+        period_start_ms,
+        period_end_ms: now_ms,
+        nav: nav.value,
+        total_shares: ledger.total_shares,
+        share_price: ledger.share_price(nav.value),
+        fee_bps,
+        total_fee: lines.iter().map(|line| line.fee).sum(),
+        lines,
+        fee_shares: ledger.fee_shares,
+        fee_shares_value: ledger.value_of(ledger.fee_shares, nav.value),
+        trades: entries
+            .iter()
+            .filter(|entry| {
+                entry.record.data.event.kind == "trade"
+                    && entry.record.timestamp_ms > period_start_ms
+                    && entry.record.timestamp_ms <= now_ms
+            })
+            .count() as u64,
+        journal_head: entries.last().map(|entry| entry.hash.clone()).unwrap_or_default(),
+        dry_run: request.payload.dry_run,
+    };
+    if request.payload.dry_run {
+        return Ok(to_signed_response(&state.eph_kp, statement, now_ms, IntentScope::Settlement));
+    }
+    *stored = ledger;
+    drop(ledgers);
+    events::save().await;
+    info!(
+        "Settlement {} of agent {}: {} MIST in fees",
+        statement.sequence, agent_id, statement.total_fee
+    );
+
+    journal::record(
+        &state.eph_kp,
+        JournalEvent {
+            kind: "settlement".to_string(),
+            wallet_address,
+            request: serde_json::to_string(&statement).unwrap_or_default(),
+            quote: None,
+            tx_digest: String::new(),
+            amount_in: statement.total_fee,
+            amount_out: None,
+            recipient: None,
+        },
+        now_ms,
+    )
+    .await;
+
+    Ok(to_signed_response(&state.eph_kp, statement, now_ms, IntentScope::Settlement))
+}
+
+async fn claim_fees_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<ClaimFeesRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<FeePayout>>, EnclaveError> {
+    if state.api_key.is_empty() || request.payload.api_key != state.api_key {
+        return Err(EnclaveError::Unauthorized("Invalid API key".to_string()));
+    }
+    events::require_persistence("pay out fees")?;
+    check_signature_timestamp(request.payload.timestamp_ms, get_current_timestamp())?;
+
+    let (keypair, wallet_address, agent_id) = wallet_and_agent(request.payload.agent_id).await?;
+    let creator = agent::load(agent_id)
+        .await?
+        .creator
+        .parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid creator address: {}", e)))?;
+    let message = claim_fees_message(&agent_id.to_string(), &wallet_address, request.payload.timestamp_ms);
+    verify_personal_message(&creator, message.as_bytes(), &request.payload.signature)?;
+
+    events::sync().await?;
+    let client = sui_client().await?;
+    let nav = nav(&client, &wallet_address).await?;
+
+    // Burn the shares before funds move, so they cannot be paid twice and the
+    // ledger is not locked while the payout executes
+    let (fee_shares, value) = LEDGERS
+        .lock()
+        .await
+        .get_mut(&agent_id)
+        .filter(|ledger| ledger.fee_shares > 0)
+        .ok_or_else(|| EnclaveError::NotFound(format!("No fees held for agent {}", agent_id)))?
+        .reserve_fees(nav.value);
+    events::save().await;
+
+    let result = if value > 0 {
+        expiry::submit_return(&client, &keypair, agent_id, creator, value, 0).await
+    } else {
+        Ok(String::new())
+    };
+    let mut ledgers = LEDGERS.lock().await;
+    let ledger = ledgers.entry(agent_id).or_default();
+    let tx_digest = match result {
+        Ok(tx_digest) => {
+            ledger.finish_withdrawal(value);
+            tx_digest
+        }
+        // The transaction may have executed, so the shares stay burned
+        Err(e @ EnclaveError::Submission(_)) => {
+            ledger.finish_withdrawal(value);
+            warn!("Fee payout of {} for agent {} may have executed, its shares stay burned", value, agent_id);
+            return Err(e);
+        }
+        Err(e) => {
+            ledger.cancel_fees(fee_shares, value);
+            drop(ledgers);
+            events::save().await;
+            return Err(e);
+        }
+    };
+    drop(ledgers);
+    info!("Paid {} MIST of fees of agent {} to {} in {}", value, agent_id, creator, tx_digest);
+
+    let now_ms = get_current_timestamp();
+    let payout = FeePayout {
+        agent_id: agent_id.to_string(),
+        creator: creator.to_string(),
+        nav: nav.value,
+        fee_shares,
+        value,
+        tx_digest: tx_digest.clone(),
+    };
+    journal::record(
+        &state.eph_kp,
+        JournalEvent {
+            kind: "fee_payout".to_string(),
+            wallet_address,
+            request: serde_json::to_string(&payout).unwrap_or_default(),
+            quote: None,
+            tx_digest,
+            amount_in: value,
+            amount_out: None,
+            recipient: Some(creator.to_string()),
+        },
+        now_ms,
+    )
+    .await;
+
+    Ok(to_signed_response(&state.eph_kp, payout, now_ms, IntentScope::Settlement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_high_water_mark_fees() {
+        let alice = SuiAddress::random_for_testing_only();
+        let mut ledger = ShareLedger::default();
+        ledger.mint(alice, 1_000_000, 0);
+
+        // NAV up 50%: 20% of the 500_000 gain
+        let lines = settle(&mut ledger, 1_500_000, 2_000);
+        assert_eq!(lines[0].gain, 500_000);
+        assert_eq!(lines[0].fee, 100_000);
+        assert_eq!(lines[0].fee_shares, 66_666);
+        assert_eq!(ledger.fee_shares, 66_666);
+        assert_eq!(ledger.share_price(1_500_000), 1_500_000_000);
+
+        // Falling back and recovering to the mark charges nothing
+        assert!(settle(&mut ledger, 1_200_000, 2_000).is_empty());
+        assert!(settle(&mut ledger, 1_500_000, 2_000).is_empty());
+
+        // A later deposit is only charged for gains after it
+        let bob = SuiAddress::random_for_testing_only();
+        ledger.mint(bob, 1_500_000, 1_500_000);
+        assert_eq!(ledger.positions[&bob].high_water_mark, 1_500_000_000);
+        let lines = settle(&mut ledger, 3_300_000, 1_000);
+        let bob_line = lines.iter().find(|line| line.subscriber == bob.to_string()).unwrap();
+        assert_eq!(bob_line.gain, 150_000);
+        assert_eq!(bob_line.fee, 15_000);
+    }
+
+    #[test]
+    fn test_failed_return_restores_position() {
+        let alice = SuiAddress::random_for_testing_only();
+        let mut ledger = ShareLedger::default();
+        ledger.mint(alice, 1_000_000, 0);
+        let before = ledger.positions[&alice].clone();
+
+        let line = settle_position(&mut ledger, &alice, 1_500_000, 2_000).unwrap();
+        let (shares, value) = ledger.reserve_close(&alice, 1_500_000);
+        assert_eq!(shares, 933_334);
+        assert_eq!(ledger.total_shares, 66_666);
+
+        ledger.cancel_withdrawal(&alice, value, shares);
+        unsettle_position(&mut ledger, &alice, &line);
+        let after = &ledger.positions[&alice];
+        assert_eq!(after.shares, before.shares);
+        assert_eq!(after.high_water_mark, before.high_water_mark);
+        assert_eq!(after.withdrawn, 0);
+        assert_eq!(after.fees_paid, 0);
+        assert_eq!(ledger.fee_shares, 0);
+        assert_eq!(ledger.total_shares, 1_000_000);
+        assert_eq!(ledger.value_of(1_000_000, 1_500_000), 1_500_000);
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Append-only journal of every trade, withdrawal and transfer executed by the
+// enclave. Each entry commits to the hash of the previous one and is signed by
+// the enclave key under `IntentScope::Journal`, so a gap or a rewritten entry
+// is detectable by anyone holding a later entry.
+//
+// Entries are kept in memory and, if `JOURNAL_PATH` is set, appended to that
+// file as JSON lines and reloaded (after verification) on boot. The file is in
+// the host's hands, so a signature alone does not make an entry authentic:
+// every signer must be the key of an on-chain `Enclave<T>`, or have handed over
+// to one. Key rotations are journaled as "key_handover" entries signed by the
+// new key, which thereby vouches for its predecessor. Entries whose signer
+// cannot be traced to an enclave key (e.g. registered by hand, or whose
+// `Enclave<T>` was destroyed) are logged and marked `unverified` in `/history`.
+
+use super::chain::{enclave_public_key, sui_client};
+use super::{get_current_timestamp, registration, relayer};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::enclave_keys::{EnclaveKeys, KeyHandover};
+use crate::{AppState, EnclaveError};
+use fastcrypto::ed25519::{Ed25519PublicKey, Ed25519Signature};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::hash::{Blake2b256, HashFunction};
+use fastcrypto::traits::{ToFromBytes, VerifyingKey};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use sui_types::base_types::ObjectID;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const JOURNAL_PATH_ENV: &str = "JOURNAL_PATH";
+const GENESIS_HASH: [u8; 32] = [0u8; 32];
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+lazy_static! {
+    static ref JOURNAL: Arc<RwLock<Vec<JournalEntry>>> = Arc::new(RwLock::new(Vec::new()));
+}
+
+/// What happened, as reported by the handler that executed it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalEvent {
+    /// "trade", "withdraw", "transfer", "subscription_withdraw", "settlement",
+    /// "fee_payout", "expiry_return" or "key_handover"
+    pub kind: String,
+    pub wallet_address: String,
+    /// JSON encoded request payload
+    pub request: String,
+    /// Minimum output the trade was quoted at
+    pub quote: Option<u64>,
+    pub tx_digest: String,
+    pub amount_in: u64,
+    pub amount_out: Option<u64>,
+    pub recipient: Option<String>,
+}
+
+/// The signed part of an entry.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalRecord {
+    pub sequence: u64,
+    pub prev_hash: String,
+    pub event: JournalEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    pub record: IntentMessage<JournalRecord>,
+    /// Blake2b256 of the BCS encoded record
+    pub hash: String,
+    /// Enclave signature over the BCS encoded record
+    pub signature: String,
+    /// Enclave public key at the time of signing
+    pub signer: String,
+    /// `Enclave<T>` object of `signer`, if known when signing. Not signed, it
+    /// is checked against the chain on load
+    #[serde(default)]
+    pub signer_enclave_id: Option<String>,
+    /// Set on load if `signer` could not be traced to an enclave key. Not
+    /// signed, recomputed on every load
+    #[serde(default)]
+    pub unverified: bool,
+}
+
+impl JournalEntry {
+    fn verify(&self, expected_sequence: u64, expected_prev_hash: &str) -> Result<(), EnclaveError> {
+        let bytes = bcs::to_bytes(&self.record)
+            .map_err(|e| EnclaveError::Internal(format!("Failed to encode record: {}", e)))?;
+        let data = &self.record.data;
+        if self.record.intent != IntentScope::Journal
+            || data.sequence != expected_sequence
+            || data.prev_hash != expected_prev_hash
+            || self.hash != Hex::encode(Blake2b256::digest(&bytes))
+        {
+            return Err(EnclaveError::Internal(format!(
+                "Journal chain broken at entry {}",
+                expected_sequence
+            )));
+        }
+
+        let signer = Hex::decode(&self.signer)
+            .ok()
+            .and_then(|bytes| Ed25519PublicKey::from_bytes(&bytes).ok());
+        let signature = Hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Ed25519Signature::from_bytes(&bytes).ok());
+        match (signer, signature) {
+            (Some(signer), Some(signature)) if signer.verify(&bytes, &signature).is_ok() => Ok(()),
+            _ => Err(EnclaveError::Internal(format!(
+                "Invalid signature on journal entry {}",
+                expected_sequence
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryRequest {
+    /// Sequence number to start from, defaults to the first entry
+    pub cursor: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryResponse {
+    pub entries: Vec<JournalEntry>,
+    pub next_cursor: Option<u64>,
+    pub total: u64,
+    /// Hash of the latest entry, commits to the whole journal
+    pub head_hash: String,
+}
+
+/// Reload and verify the persisted journal on boot.
+pub async fn load_journal() -> Result<(), EnclaveError> {
+    let path = match std::env::var(JOURNAL_PATH_ENV) {
+        Ok(path) => path,
+        Err(_) => {
+            info!("{} not set, journal is kept in memory only", JOURNAL_PATH_ENV);
+            return Ok(());
+        }
+    };
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(EnclaveError::Internal(format!(
+                "Failed to open journal {}: {}",
+                path, e
+            )))
+        }
+    };
+
+    let mut entries: Vec<JournalEntry> = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line
+            .map_err(|e| EnclaveError::Internal(format!("Failed to read journal: {}", e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry = serde_json::from_str(&line)
+            .map_err(|e| EnclaveError::Internal(format!("Invalid journal entry: {}", e)))?;
+        let prev_hash = entries
+            .last()
+            .map_or_else(|| Hex::encode(GENESIS_HASH), |last| last.hash.clone());
+        entry.verify(entries.len() as u64, &prev_hash)?;
+        entries.push(entry);
+    }
+
+    let unverified = match unanchored_signers(&entries).await {
+        Ok(unverified) => unverified,
+        Err(e) => {
+            warn!("Cannot check the journal signers, all entries are unverified: {}", e);
+            entries.iter().map(|entry| entry.signer.clone()).collect()
+        }
+    };
+    for entry in &mut entries {
+        entry.unverified = unverified.contains(&entry.signer);
+    }
+    for signer in &unverified {
+        let count = entries.iter().filter(|entry| &entry.signer == signer).count();
+        warn!("Journal signer {} is not a registered enclave key, its {} entries are unverified", signer, count);
+    }
+
+    info!("Loaded {} journal entries from {}", entries.len(), path);
+    *JOURNAL.write().await = entries;
+    Ok(())
+}
+
+/// Signers of `entries` that are not an enclave key: the key of an on-chain
+/// `Enclave<T>`, or the predecessor of one in a journaled handover.
+async fn unanchored_signers(entries: &[JournalEntry]) -> Result<BTreeSet<String>, EnclaveError> {
+    let mut enclave_ids: BTreeMap<&str, BTreeSet<ObjectID>> = BTreeMap::new();
+    let mut successors: BTreeMap<String, String> = BTreeMap::new();
+    for entry in entries {
+        if let Some(enclave_id) = entry.signer_enclave_id.as_ref().and_then(|id| id.parse().ok()) {
+            enclave_ids.entry(&entry.signer).or_default().insert(enclave_id);
+        }
+        if entry.record.data.event.kind == "key_handover" {
+            let handover = match serde_json::from_str::<ProcessedDataResponse<IntentMessage<KeyHandover>>>(
+                &entry.record.data.event.request,
+            ) {
+                Ok(handover) => handover.response.data,
+                Err(e) => {
+                    warn!("Invalid key handover in journal entry {}: {}", entry.record.data.sequence, e);
+                    continue;
+                }
+            };
+            // Only the new key can vouch for the old one
+            if Hex::encode(&handover.new_public_key) == entry.signer {
+                successors.insert(Hex::encode(&handover.old_public_key), entry.signer.clone());
+                if let Some(enclave_id) = handover.new_enclave_object_id.and_then(|id| id.parse().ok()) {
+                    enclave_ids.entry(&entry.signer).or_default().insert(enclave_id);
+                }
+            }
+        }
+    }
+
+    let mut unanchored = BTreeSet::new();
+    if entries.is_empty() {
+        return Ok(unanchored);
+    }
+    let client = sui_client().await?;
+    let mut anchored: BTreeSet<String> = BTreeSet::new();
+    for signer in entries.iter().map(|entry| entry.signer.as_str()).collect::<BTreeSet<_>>() {
+        let mut key = signer.to_string();
+        for _ in 0..=successors.len() {
+            if anchored.contains(&key) {
+                break;
+            }
+            let mut registered = false;
+            for enclave_id in enclave_ids.get(key.as_str()).into_iter().flatten() {
+                match enclave_public_key(&client, *enclave_id).await {
+                    Ok(pk) if Hex::encode(&pk) == key => {
+                        registered = true;
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Cannot check enclave {} of journal signer {}: {}", enclave_id, key, e),
+                }
+            }
+            if registered {
+                anchored.insert(key.clone());
+                break;
+            }
+            match successors.get(&key) {
+                Some(next) => key = next.clone(),
+                None => break,
+            }
+        }
+        if anchored.contains(&key) {
+            anchored.insert(signer.to_string());
+        } else {
+            unanchored.insert(signer.to_string());
+        }
+    }
+    Ok(unanchored)
+}
+
+/// Append an event to the journal. Persistence failures are logged but do not
+/// fail the caller, the on-chain transaction has already happened.
+pub async fn record(kp: &EnclaveKeys, event: JournalEvent, timestamp_ms: u64) -> JournalEntry {
+    append(&JOURNAL, kp, event, timestamp_ms).await
+}
+
+async fn append(
+    journal: &RwLock<Vec<JournalEntry>>,
+    kp: &EnclaveKeys,
+    event: JournalEvent,
+    timestamp_ms: u64,
+) -> JournalEntry {
+    let key = kp.current();
+    let signer_enclave_id = registration::registered_enclave_id(key.epoch)
+        .await
+        .map(|id| id.to_string())
+        .or_else(|| std::env::var(relayer::ENCLAVE_OBJECT_ID_ENV).ok());
+    let mut journal = journal.write().await;
+    let record = JournalRecord {
+        sequence: journal.len() as u64,
+        prev_hash: journal
+            .last()
+            .map_or_else(|| Hex::encode(GENESIS_HASH), |last| last.hash.clone()),
+        event,
+    };
+
+    let signed = key.sign_response(record, timestamp_ms, IntentScope::Journal);
+    let bytes = bcs::to_bytes(&signed.response).expect("should not fail");
+    let entry = JournalEntry {
+        record: signed.response,
+        hash: Hex::encode(Blake2b256::digest(&bytes)),
+        signature: signed.signature,
+        signer: Hex::encode(key.public().as_bytes()),
+        signer_enclave_id,
+        unverified: false,
+    };
+
+    if let Ok(path) = std::env::var(JOURNAL_PATH_ENV) {
+        if let Err(e) = persist(&path, &entry) {
+            warn!("Failed to persist journal entry {}: {}", entry.record.data.sequence, e);
+        }
+    }
+    info!(
+        "Journal entry {}: {} {}",
+        entry.record.data.sequence, entry.record.data.event.kind, entry.record.data.event.tx_digest
+    );
+
+    journal.push(entry.clone());
+    entry
+}
+
+fn persist(path: &str, entry: &JournalEntry) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn history_wrapper(
+    request: ProcessDataRequest<HistoryRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match history_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+// ====== Internal Functions ======
+
+async fn history_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<HistoryRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<HistoryResponse>>, EnclaveError> {
+    let journal = JOURNAL.read().await;
+    let start = request.payload.cursor.unwrap_or(0) as usize;
+    let limit = request
+        .payload
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let entries: Vec<JournalEntry> = journal.iter().skip(start).take(limit).cloned().collect();
+    let end = start + entries.len();
+    let response = HistoryResponse {
+        entries,
+        next_cursor: (end < journal.len()).then_some(end as u64),
+        total: journal.len() as u64,
+        head_hash: journal
+            .last()
+            .map_or_else(|| Hex::encode(GENESIS_HASH), |last| last.hash.clone()),
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+/// Journal entries, oldest first, for analytics inside the enclave.
+pub async fn entries() -> Vec<JournalEntry> {
+    JOURNAL.read().await.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastcrypto::ed25519::Ed25519KeyPair;
+    use fastcrypto::traits::KeyPair;
+
+    fn event(tx_digest: &str) -> JournalEvent {
+        JournalEvent {
+            kind: "trade".to_string(),
+            wallet_address: "0x1".to_string(),
+            request: "{}".to_string(),
+            quote: Some(1),
+            tx_digest: tx_digest.to_string(),
+            amount_in: 10,
+            amount_out: None,
+            recipient: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_chained() {
+        let kp = EnclaveKeys::new(Ed25519KeyPair::generate(&mut rand::thread_rng()));
+        let journal = RwLock::new(Vec::new());
+        let first = append(&journal, &kp, event("a"), 1).await;
+        let second = append(&journal, &kp, event("b"), 2).await;
+
+        first.verify(0, &Hex::encode(GENESIS_HASH)).unwrap();
+        second.verify(1, &first.hash).unwrap();
+        assert_eq!(journal.read().await.len(), 2);
+
+        let mut tampered = second.clone();
+        tampered.record.data.event.amount_in = 11;
+        assert!(tampered.verify(1, &first.hash).is_err());
+    }
+}
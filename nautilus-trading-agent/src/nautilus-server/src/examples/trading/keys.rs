@@ -0,0 +1,162 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Hierarchical wallet keys. A single master seed is drawn from the NSM on first
+// boot and every wallet key is derived from it, so only the sealed seed needs to
+// be persisted to re-derive all wallets after a restart.
+
+use crate::EnclaveError;
+use fastcrypto::aes::{Aes256Gcm, AesKey, AuthenticatedCipher, InitializationVector};
+use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519PrivateKey};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::hash::{Blake2b256, HashFunction};
+use fastcrypto::hmac::{hkdf_sha3_256, HkdfIkm};
+use fastcrypto::traits::{Generate, ToFromBytes};
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::crypto::SuiKeyPair;
+#[cfg(feature = "mock-nsm")]
+use tracing::warn;
+use typenum::U12;
+
+pub const MASTER_SEED_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+
+// Domain separators, bump the version if the derivation ever changes.
+const WALLET_KEY_SALT: &[u8] = b"nautilus-trading-wallet-v1";
+const SEAL_AAD: &[u8] = b"nautilus-master-seed-v1";
+const FINGERPRINT_DOMAIN: &[u8] = b"nautilus-master-seed-fingerprint";
+
+/// Root secret from which all wallet keys are derived.
+pub struct MasterSeed([u8; MASTER_SEED_LENGTH]);
+
+impl MasterSeed {
+    /// Draw a fresh seed from the NSM entropy source. Only builds with the
+    /// `mock-nsm` feature (local development) fall back to the OS RNG.
+    pub fn generate() -> Result<Self, EnclaveError> {
+        let mut seed = [0u8; MASTER_SEED_LENGTH];
+        match aws::get_entropy(MASTER_SEED_LENGTH) {
+            Ok(entropy) => seed.copy_from_slice(&entropy[..MASTER_SEED_LENGTH]),
+            #[cfg(feature = "mock-nsm")]
+            Err(e) => {
+                use rand::RngCore;
+                warn!("NSM entropy unavailable ({}), using OS randomness", e.message);
+                rand::thread_rng().fill_bytes(&mut seed);
+            }
+            #[cfg(not(feature = "mock-nsm"))]
+            Err(e) => {
+                return Err(EnclaveError::Internal(format!("NSM entropy unavailable: {}", e.message)));
+            }
+        }
+        Ok(Self(seed))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EnclaveError> {
+        let seed: [u8; MASTER_SEED_LENGTH] = bytes.try_into().map_err(|_| {
+            EnclaveError::GenericError(format!(
+                "Master seed must be {} bytes",
+                MASTER_SEED_LENGTH
+            ))
+        })?;
+        Ok(Self(seed))
+    }
+
+    /// Raw seed bytes, only for handing the seed to another attested enclave.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Deterministically derive the Ed25519 wallet key for an owner and optional agent.
+    pub fn derive_wallet_keypair(
+        &self,
+        owner: &SuiAddress,
+        agent_id: Option<&ObjectID>,
+    ) -> Result<SuiKeyPair, EnclaveError> {
+        let mut info = owner.as_ref().to_vec();
+        if let Some(agent_id) = agent_id {
+            info.extend_from_slice(agent_id.as_ref());
+        }
+
+        let ikm = HkdfIkm::from_bytes(&self.0)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid master seed: {}", e)))?;
+        let secret = hkdf_sha3_256(&ikm, WALLET_KEY_SALT, &info, 32)
+            .map_err(|e| EnclaveError::GenericError(format!("Key derivation failed: {}", e)))?;
+        let private_key = Ed25519PrivateKey::from_bytes(&secret)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid derived key: {}", e)))?;
+
+        Ok(SuiKeyPair::Ed25519(Ed25519KeyPair::from(private_key)))
+    }
+
+    /// Encrypt the seed with AES-256-GCM under the sealing key, returning hex(nonce || ciphertext).
+    pub fn seal(&self, sealing_key: &[u8]) -> Result<String, EnclaveError> {
+        let cipher = Aes256Gcm::<U12>::new(
+            AesKey::from_bytes(sealing_key)
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid sealing key: {}", e)))?,
+        );
+        let iv = InitializationVector::<U12>::generate(&mut rand::thread_rng());
+        let ciphertext = cipher.encrypt_authenticated(&iv, SEAL_AAD, &self.0);
+
+        let mut sealed = iv.as_bytes().to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(Hex::encode(sealed))
+    }
+
+    /// Reverse of [MasterSeed::seal].
+    pub fn unseal(sealed: &str, sealing_key: &[u8]) -> Result<Self, EnclaveError> {
+        let sealed = Hex::decode(sealed)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid sealed seed: {}", e)))?;
+        if sealed.len() <= NONCE_LENGTH {
+            return Err(EnclaveError::GenericError("Sealed seed too short".to_string()));
+        }
+
+        let cipher = Aes256Gcm::<U12>::new(
+            AesKey::from_bytes(sealing_key)
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid sealing key: {}", e)))?,
+        );
+        let iv = InitializationVector::<U12>::from_bytes(&sealed[..NONCE_LENGTH])
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid nonce: {}", e)))?;
+        let seed = cipher
+            .decrypt_authenticated(&iv, SEAL_AAD, &sealed[NONCE_LENGTH..])
+            .map_err(|_| EnclaveError::GenericError("Failed to unseal master seed".to_string()))?;
+
+        Self::from_bytes(&seed)
+    }
+
+    /// Short public identifier of the seed, safe to log and return to clients.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Blake2b256::default();
+        hasher.update(FINGERPRINT_DOMAIN);
+        hasher.update(self.0);
+        let hash = hasher.finalize();
+        Hex::encode(&hash.as_ref()[..8])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wallet_derivation_is_deterministic() {
+        let seed = MasterSeed::from_bytes(&[7u8; MASTER_SEED_LENGTH]).unwrap();
+        let owner = SuiAddress::random_for_testing_only();
+        let agent = ObjectID::random();
+
+        let a = seed.derive_wallet_keypair(&owner, Some(&agent)).unwrap();
+        let b = seed.derive_wallet_keypair(&owner, Some(&agent)).unwrap();
+        let other = seed.derive_wallet_keypair(&owner, None).unwrap();
+
+        assert_eq!(SuiAddress::from(&a.public()), SuiAddress::from(&b.public()));
+        assert_ne!(SuiAddress::from(&a.public()), SuiAddress::from(&other.public()));
+    }
+
+    #[test]
+    fn test_seal_roundtrip() {
+        let seed = MasterSeed::from_bytes(&[9u8; MASTER_SEED_LENGTH]).unwrap();
+        let sealing_key = [1u8; 32];
+
+        let sealed = seed.seal(&sealing_key).unwrap();
+        let unsealed = MasterSeed::unseal(&sealed, &sealing_key).unwrap();
+        assert_eq!(seed.fingerprint(), unsealed.fingerprint());
+        assert!(MasterSeed::unseal(&sealed, &[2u8; 32]).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Enclave-to-enclave migration of wallet secrets across image upgrades.
+//
+// 1. The new enclave answers `/migration_request` with an attestation document
+//    committing to its signing key (public_key) and a fresh encryption key (user_data).
+// 2. The old enclave verifies that document on `/migration_export`, checks its
+//    PCRs against the on-chain `EnclaveConfig`, encrypts the master seed and
+//    wallet metadata to the attested encryption key and stops using them.
+// 3. The new enclave checks the signed export against the source `Enclave<T>`
+//    object on-chain on `/migration_import` and installs the secrets.
+// 4. The old enclave erases the secrets on `/migration_complete`, given the
+//    import response signed by the new enclave. Until then `/migration_abort`
+//    puts them back in use, so a failed import never loses the only copy.
+
+use super::chain::{enclave_public_key, read_move_object, sui_client};
+use super::ecies::{self, EncryptedPayload, EncryptionKeyPair};
+use super::keys::MasterSeed;
+use super::{
+    binding, derive_sui_address, get_current_timestamp, WalletState, ENCLAVE_CONFIG_ID, MASTER_SEED,
+    TRADING_WALLET,
+};
+use crate::attestation::{verify_with_policy, VerificationPolicy};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use fastcrypto::ed25519::{Ed25519PublicKey, Ed25519Signature};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{ToFromBytes, VerifyingKey};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Attestations older than this are rejected to prevent replays.
+const MAX_ATTESTATION_AGE_MS: u64 = 5 * 60 * 1000;
+
+lazy_static! {
+    /// Encryption key of the new enclave and when it was attested
+    static ref MIGRATION_KEY: Arc<RwLock<Option<(EncryptionKeyPair, u64)>>> = Arc::new(RwLock::new(None));
+    /// Secrets exported by the old enclave, kept until the import is acknowledged
+    static ref PENDING_EXPORT: Arc<RwLock<Option<PendingExport>>> = Arc::new(RwLock::new(None));
+}
+
+struct PendingExport {
+    target_public_key: Vec<u8>,
+    master_seed: MasterSeed,
+    wallet: Option<WalletState>,
+}
+
+/// BCS layout of `enclave::EnclaveConfig<T>`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct EnclaveConfigObject {
+    id: ObjectID,
+    name: String,
+    pcrs: (Vec<u8>, Vec<u8>, Vec<u8>),
+    capability_id: ObjectID,
+    version: u64,
+}
+
+/// Secrets handed from the old enclave to the new one. Wallet keys are
+/// re-derived from the master seed so only the derivation path is sent.
+#[derive(Serialize, Deserialize)]
+struct MigrationSecrets {
+    master_seed: Vec<u8>,
+    wallet_owner: Option<String>,
+    wallet_agent_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrationRequest {
+    pub api_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationRequestResponse {
+    /// Hex encoded attestation with the migration encryption key as user_data
+    pub attestation: String,
+    pub encryption_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrationExportRequest {
+    /// Attestation returned by `/migration_request` on the new enclave
+    pub attestation: String,
+    pub api_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationExportResponse {
+    /// Hex encoded Ed25519 key of the enclave the secrets are encrypted for
+    pub target_public_key: String,
+    pub seed_fingerprint: String,
+    pub payload: EncryptedPayload,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MigrationImportRequest {
+    /// Signed response of `/migration_export` on the old enclave
+    pub export: ProcessedDataResponse<IntentMessage<MigrationExportResponse>>,
+    /// `Enclave<T>` object registered for the old enclave
+    pub source_enclave_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationImportResponse {
+    pub wallet_address: Option<String>,
+    pub seed_fingerprint: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MigrationCompleteRequest {
+    /// Signed response of `/migration_import` on the new enclave
+    pub import: ProcessedDataResponse<IntentMessage<MigrationImportResponse>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationCompleteResponse {
+    pub target_public_key: String,
+    pub seed_fingerprint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationAbortResponse {
+    pub wallet_address: Option<String>,
+    pub seed_fingerprint: String,
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn migration_request_wrapper(
+    request: ProcessDataRequest<MigrationRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match migration_request_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn migration_export_wrapper(
+    request: ProcessDataRequest<MigrationExportRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match migration_export_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn migration_import_wrapper(
+    request: ProcessDataRequest<MigrationImportRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match migration_import_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn migration_complete_wrapper(
+    request: ProcessDataRequest<MigrationCompleteRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match migration_complete_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn migration_abort_wrapper(
+    request: ProcessDataRequest<MigrationRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match migration_abort_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+// ====== Internal Functions ======
+
+/// Both ends of a migration are operator actions, a caller without `API_KEY`
+/// must not be able to pull the seed into another enclave or wipe this one.
+fn check_api_key(state: &AppState, api_key: &str) -> Result<(), EnclaveError> {
+    if state.api_key.is_empty() || api_key != state.api_key {
+        return Err(EnclaveError::Unauthorized("Invalid API key".to_string()));
+    }
+    Ok(())
+}
+
+/// New enclave: create an encryption key and attest to it.
+async fn migration_request_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<MigrationRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<MigrationRequestResponse>>, EnclaveError> {
+    check_api_key(&state, &request.payload.api_key)?;
+    info!("Preparing to receive migrated wallet secrets");
+
+    // An export may already be encrypted to the pending key. It can only be
+    // replaced once its attestation is too old to be exported to.
+    let timestamp_ms = get_current_timestamp();
+    let mut key_guard = MIGRATION_KEY.write().await;
+    if let Some((_, attested_ms)) = key_guard.as_ref() {
+        if timestamp_ms.saturating_sub(*attested_ms) <= MAX_ATTESTATION_AGE_MS {
+            return Err(EnclaveError::Conflict("A migration key is already pending".to_string()));
+        }
+    }
+
+    let key = EncryptionKeyPair::generate();
+    let encryption_key = key.public_key();
+    let attestation = state.attestation.attest(
+        state.eph_kp.public().as_bytes().to_vec(),
+        Some(encryption_key.to_vec()),
+        None,
+    )?;
+    *key_guard = Some((key, timestamp_ms));
+
+    let response = MigrationRequestResponse {
+        attestation: Hex::encode(attestation),
+        encryption_key: Hex::encode(encryption_key),
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        timestamp_ms,
+        IntentScope::ProcessData,
+    ))
+}
+
+/// Old enclave: verify the new enclave and hand over the wallet secrets.
+async fn migration_export_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<MigrationExportRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<MigrationExportResponse>>, EnclaveError> {
+    check_api_key(&state, &request.payload.api_key)?;
+    let document = Hex::decode(&request.payload.attestation)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid attestation hex: {}", e)))?;
+
+    // The target must run the image currently allowed by the on-chain config
+    let client = sui_client().await?;
+    let config_id = ENCLAVE_CONFIG_ID
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid enclave config ID: {}", e)))?;
+    let (_, config) = read_move_object::<EnclaveConfigObject>(&client, config_id).await?;
+    let (pcr0, pcr1, pcr2) = config.pcrs;
+
+    let timestamp_ms = get_current_timestamp();
+    let policy = VerificationPolicy {
+        expected_pcrs: BTreeMap::from([(0, pcr0), (1, pcr1), (2, pcr2)]),
+        max_age_ms: Some(MAX_ATTESTATION_AGE_MS),
+        ..VerificationPolicy::aws(timestamp_ms)
+    };
+    let attestation = verify_with_policy(&document, &policy)?;
+
+    let target_public_key = attestation.public_key;
+    let encryption_key = attestation
+        .user_data
+        .ok_or_else(|| EnclaveError::GenericError("Attestation has no encryption key".to_string()))?;
+    if target_public_key == state.eph_kp.public().as_bytes() {
+        return Err(EnclaveError::GenericError("Cannot migrate to self".to_string()));
+    }
+
+    let mut pending_guard = PENDING_EXPORT.write().await;
+    if pending_guard.is_some() {
+        return Err(EnclaveError::Conflict("An export is awaiting acknowledgement".to_string()));
+    }
+    let mut wallet_guard = TRADING_WALLET.write().await;
+    let mut seed_guard = MASTER_SEED.write().await;
+    let master_seed = seed_guard
+        .as_ref()
+        .ok_or_else(|| EnclaveError::Internal("Master seed not loaded".to_string()))?;
+
+    let secrets = MigrationSecrets {
+        master_seed: master_seed.as_bytes().to_vec(),
+        wallet_owner: wallet_guard.as_ref().map(|wallet| wallet.owner.clone()),
+        wallet_agent_id: wallet_guard.as_ref().and_then(|wallet| wallet.agent_id.clone()),
+    };
+    let plaintext = bcs::to_bytes(&secrets)
+        .map_err(|e| EnclaveError::GenericError(format!("Failed to encode secrets: {}", e)))?;
+    let payload = ecies::encrypt(&encryption_key, &target_public_key, &plaintext)?;
+    let seed_fingerprint = master_seed.fingerprint();
+
+    // The secrets now belong to the new enclave, stop using them here but keep
+    // them until the new enclave acknowledges the import
+    *pending_guard = Some(PendingExport {
+        target_public_key: target_public_key.clone(),
+        master_seed: seed_guard.take().expect("checked above"),
+        wallet: wallet_guard.take(),
+    });
+    info!("Exported master seed {} to enclave {}", seed_fingerprint, Hex::encode(&target_public_key));
+
+    let response = MigrationExportResponse {
+        target_public_key: Hex::encode(target_public_key),
+        seed_fingerprint,
+        payload,
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        timestamp_ms,
+        IntentScope::ProcessData,
+    ))
+}
+
+/// New enclave: check the export came from a registered enclave and install it.
+async fn migration_import_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<MigrationImportRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<MigrationImportResponse>>, EnclaveError> {
+    let export = &request.payload.export;
+
+    let client = sui_client().await?;
+    let enclave_id = request
+        .payload
+        .source_enclave_id
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid enclave ID: {}", e)))?;
+    let source_public_key = enclave_public_key(&client, enclave_id).await?;
+    verify_signed(&source_public_key, export)
+        .map_err(|_| EnclaveError::Unauthorized("Export is not signed by the source enclave".to_string()))?;
+
+    let own_public_key = state.eph_kp.public().as_bytes().to_vec();
+    if Hex::decode(&export.response.data.target_public_key).ok() != Some(own_public_key.clone()) {
+        return Err(EnclaveError::GenericError(
+            "Export was encrypted for another enclave".to_string(),
+        ));
+    }
+
+    let mut key_guard = MIGRATION_KEY.write().await;
+    let (key, _) = key_guard
+        .as_ref()
+        .ok_or_else(|| EnclaveError::NotFound("No migration in progress".to_string()))?;
+    let plaintext = key.decrypt(&export.response.data.payload, &own_public_key)?;
+    let secrets: MigrationSecrets = bcs::from_bytes(&plaintext)
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid migration secrets: {}", e)))?;
+
+    let master_seed = MasterSeed::from_bytes(&secrets.master_seed)?;
+    let seed_fingerprint = master_seed.fingerprint();
+    if seed_fingerprint != export.response.data.seed_fingerprint {
+        return Err(EnclaveError::GenericError("Seed fingerprint mismatch".to_string()));
+    }
+
+    let mut wallet_guard = TRADING_WALLET.write().await;
+    let mut seed_guard = MASTER_SEED.write().await;
+    if wallet_guard.is_some() {
+        return Err(EnclaveError::Conflict("Wallet already initialized".to_string()));
+    }
+
+    let wallet = match secrets.wallet_owner {
+        Some(owner) => {
+            let owner_address = owner
+                .parse::<SuiAddress>()
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid owner address: {}", e)))?;
+            let agent_id = secrets
+                .wallet_agent_id
+                .as_ref()
+                .map(|id| id.parse::<ObjectID>())
+                .transpose()
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid agent ID: {}", e)))?;
+            let keypair = master_seed.derive_wallet_keypair(&owner_address, agent_id.as_ref())?;
+            Some(WalletState {
+                address: derive_sui_address(&keypair),
+                keypair: Arc::new(keypair),
+                owner,
+                agent_id: secrets.wallet_agent_id,
+            })
+        }
+        None => None,
+    };
+    let wallet_address = wallet.as_ref().map(|wallet| wallet.address.clone());
+
+    *wallet_guard = wallet;
+    *seed_guard = Some(master_seed);
+    *key_guard = None;
+    drop((wallet_guard, seed_guard, key_guard));
+    info!("Imported master seed {} from enclave {}", seed_fingerprint, enclave_id);
+
+    // The binding of the source enclave names its key, not ours
+    if let Err(e) = binding::resign(&state).await {
+        warn!("Failed to sign the agent binding: {}", e);
+    }
+
+    let response = MigrationImportResponse {
+        wallet_address,
+        seed_fingerprint,
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+/// Old enclave: erase the exported secrets once the new enclave has installed them.
+async fn migration_complete_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<MigrationCompleteRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<MigrationCompleteResponse>>, EnclaveError> {
+    let import = &request.payload.import;
+    let mut pending_guard = PENDING_EXPORT.write().await;
+    let pending = pending_guard
+        .as_ref()
+        .ok_or_else(|| EnclaveError::NotFound("No export awaiting acknowledgement".to_string()))?;
+
+    verify_signed(&pending.target_public_key, import).map_err(|_| {
+        EnclaveError::Unauthorized("Import is not signed by the target enclave".to_string())
+    })?;
+    let seed_fingerprint = pending.master_seed.fingerprint();
+    if import.response.data.seed_fingerprint != seed_fingerprint {
+        return Err(EnclaveError::InvalidRequest("Import is for another seed".to_string()));
+    }
+
+    let target_public_key = Hex::encode(&pending.target_public_key);
+    *pending_guard = None;
+    info!("Enclave {} acknowledged master seed {}, erased it here", target_public_key, seed_fingerprint);
+
+    let response = MigrationCompleteResponse {
+        target_public_key,
+        seed_fingerprint,
+    };
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+/// Old enclave: put exported secrets back in use after a failed import.
+async fn migration_abort_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<MigrationRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<MigrationAbortResponse>>, EnclaveError> {
+    check_api_key(&state, &request.payload.api_key)?;
+
+    let mut pending_guard = PENDING_EXPORT.write().await;
+    let pending = pending_guard
+        .take()
+        .ok_or_else(|| EnclaveError::NotFound("No export awaiting acknowledgement".to_string()))?;
+    let seed_fingerprint = pending.master_seed.fingerprint();
+    let wallet_address = pending.wallet.as_ref().map(|wallet| wallet.address.clone());
+
+    *TRADING_WALLET.write().await = pending.wallet;
+    *MASTER_SEED.write().await = Some(pending.master_seed);
+    info!("Aborted the export of master seed {}, using it again", seed_fingerprint);
+
+    let response = MigrationAbortResponse {
+        wallet_address,
+        seed_fingerprint,
+    };
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+/// Check that `signed` was signed by the Ed25519 key `public_key`.
+fn verify_signed<T: Serialize>(
+    public_key: &[u8],
+    signed: &ProcessedDataResponse<IntentMessage<T>>,
+) -> Result<(), EnclaveError> {
+    let public_key = Ed25519PublicKey::from_bytes(public_key)
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid enclave public key: {}", e)))?;
+    let signature = Hex::decode(&signed.signature)
+        .ok()
+        .and_then(|bytes| Ed25519Signature::from_bytes(&bytes).ok())
+        .ok_or_else(|| EnclaveError::Unauthorized("Invalid signature".to_string()))?;
+    let message = bcs::to_bytes(&signed.response)
+        .map_err(|e| EnclaveError::GenericError(format!("Failed to encode response: {}", e)))?;
+    public_key
+        .verify(&message, &signature)
+        .map_err(|_| EnclaveError::Unauthorized("Invalid signature".to_string()))
+}
@@ -3,14 +3,42 @@
 
 use crate::common::{IntentMessage as CommonIntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse, to_signed_response};
 use crate::{AppState, EnclaveError};
-use fastcrypto::ed25519::Ed25519KeyPair;
 use fastcrypto::encoding::{Encoding, Hex};
-use fastcrypto::traits::{KeyPair, Signer, ToFromBytes};
+use fastcrypto::traits::{Signer, ToFromBytes};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use lazy_static::lazy_static;
-use tracing::info;
+use tracing::{info, warn};
+
+pub mod aborts;
+pub mod agent;
+pub mod auth;
+pub mod balances;
+pub mod binding;
+pub mod chain;
+pub mod ecies;
+pub mod events;
+pub mod expiry;
+pub mod fees;
+pub mod journal;
+pub mod keys;
+pub mod migration;
+pub mod performance;
+pub mod recovery;
+pub mod registration;
+pub mod relayer;
+pub mod rotation;
+pub mod sealing;
+pub mod shamir;
+pub mod shares;
+pub mod snapshots;
+pub mod sponsor;
+pub mod subscriptions;
+
+use aborts::AbortContext;
+use journal::JournalEvent;
+use keys::MasterSeed;
 
 // Sui SDK imports
 #[cfg(feature = "trading")]
@@ -38,15 +66,30 @@ const SUI_RPC_URL: &str = "https://fullnode.devnet.sui.io:443";
 const SUBSCRIPTION_MANAGER_PACKAGE_ID: &str = "0xfd6a00339d853aae2473bab92a11d2db322604e33339bad08e8e52f97470fa9d";
 const SUBSCRIPTION_MANAGER_ID: &str = "0x83e0dd1f1df2c174f353a3b0cd0fc03141690f3f2ebd7bfbbea409f8db409454";
 
+// Enclave registration constants
+const ENCLAVE_PACKAGE_ID: &str = "0x9a9061d46c3fd8a08df5f31c09ef32867d8f4f51dac7cf23b36381fcecdcef82";
+const TRADING_PACKAGE_ID: &str = "0xb66ee07ed5037f71b209a9d068a84d73520bd92837924e065f9d02b537950eab";
+const ENCLAVE_CONFIG_ID: &str = "0x7afdb87c0421b3e44b18300c64ee322349705ca9815461f41fc06933c536ae5d";
+
+// Trade actions accepted by execute_trade
+const TRADE_ACTIONS: [&str; 2] = ["buy_sui", "sell_sui"];
+// Opaque strategy parameters the operator commits to in attestations
+const STRATEGY_PARAMS_ENV: &str = "STRATEGY_PARAMS";
+
+// Environment variable used to restore the master seed across restarts
+const SEALED_MASTER_SEED_ENV: &str = "SEALED_MASTER_SEED";
+
 // Lazy static for wallet state (ephemeral - exists only in memory)
 lazy_static! {
     static ref TRADING_WALLET: Arc<RwLock<Option<WalletState>>> = Arc::new(RwLock::new(None));
+    static ref MASTER_SEED: Arc<RwLock<Option<MasterSeed>>> = Arc::new(RwLock::new(None));
 }
 
 struct WalletState {
     keypair: Arc<SuiKeyPair>,
     address: String,
     owner: String,
+    agent_id: Option<String>,
 }
 
 // ====== Request/Response Types (matching sentinel pattern) ======
@@ -54,6 +97,8 @@ struct WalletState {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InitWalletRequest {
     pub owner_address: String,
+    /// Marketplace agent this wallet trades for, part of the key derivation path
+    pub agent_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -80,7 +125,8 @@ pub struct TradeResponse {
     pub tx_digest: String,
     pub action: String,
     pub amount: u64,
-    pub min_output: u64,
+    /// Output of the swap as reported by its `SwapEvent`, the `amount_out` verified on-chain
+    pub amount_out: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -95,6 +141,10 @@ pub struct WalletStatusResponse {
     pub owner: Option<String>,
     pub sui_balance: u64,
     pub usdc_balance: u64,
+    /// Balances valued at the pool price, None if the pool could not be read
+    pub nav: Option<shares::Nav>,
+    /// Every coin type and non-coin object owned, None if they could not be fetched
+    pub assets: Option<balances::WalletAssets>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -114,7 +164,11 @@ pub struct WithdrawResponse {
 pub struct SubscriptionWithdrawRequest {
     pub agent_id: String,
     pub amount: u64,
+    /// The subscriber, who receives the funds
     pub recipient: String,
+    pub timestamp_ms: u64,
+    /// Subscriber signature over [subscriptions::withdrawal_message]
+    pub signature: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -123,10 +177,168 @@ pub struct SubscriptionWithdrawResponse {
     pub agent_id: String,
     pub amount: u64,
     pub recipient: String,
+    /// Shares burned at the NAV before the withdrawal
+    pub shares_burned: u64,
+    pub remaining_shares: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedSeedRequest {
+    pub api_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SealedSeedResponse {
+    /// hex(nonce || AES-256-GCM ciphertext) of the master seed
+    pub sealed_seed: String,
+    pub seed_fingerprint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LastTrade {
+    pub tx_digest: String,
+    pub action: String,
+    pub timestamp_ms: u64,
+}
+
+/// Data committed to in the `user_data` of `/attestation` documents, as
+/// Blake2b256 of its BCS encoding.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttestationCommitment {
+    pub wallet_addresses: Vec<String>,
+    pub config_hash: String,
+    pub strategy_hash: String,
+}
+
+impl AttestationCommitment {
+    pub fn digest(&self) -> [u8; 32] {
+        use fastcrypto::hash::{Blake2b256, HashFunction};
+        Blake2b256::digest(bcs::to_bytes(self).expect("should not fail")).digest
+    }
+}
+
+/// Trading specific part of the `/health` response.
+#[derive(Debug, Serialize, Clone)]
+pub struct TradingHealth {
+    pub wallet_count: usize,
+    pub last_trade: Option<LastTrade>,
+    pub config_hash: String,
+    pub registration: registration::RegistrationStatus,
+    pub sponsor: Option<sponsor::SponsorStatus>,
+    pub events: events::EventStatus,
+    /// Marketplace agent metadata the enclave acts under
+    pub agent: Option<agent::AgentMetadata>,
+    /// Why the agent could not be loaded, if the last attempt failed
+    pub agent_error: Option<String>,
+    /// Binding to the agent signed by the current key
+    pub agent_binding: Option<binding::SignedBinding>,
 }
 
 // ====== Core Functions ======
 
+/// Load the master seed on boot. If a sealed seed is provided through the
+/// environment the previous seed is restored with the sealing key released to
+/// this enclave (see `sealing`), and failing to unseal it is an error rather
+/// than a reason to start over with new wallets. Otherwise a fresh seed is
+/// drawn from the NSM.
+pub async fn load_master_seed(state: &AppState) -> Result<(), EnclaveError> {
+    let sealing_key = sealing::release(state).await?;
+    let seed = match (std::env::var(SEALED_MASTER_SEED_ENV), sealing_key) {
+        (Ok(sealed), Some(sealing_key)) => {
+            let seed = MasterSeed::unseal(&sealed, &sealing_key)?;
+            info!("Restored sealed master seed {}", seed.fingerprint());
+            seed
+        }
+        (Ok(_), None) => {
+            return Err(EnclaveError::Internal(format!(
+                "{} is set but no sealing key was released",
+                SEALED_MASTER_SEED_ENV
+            )));
+        }
+        (Err(_), _) => {
+            let seed = MasterSeed::generate()?;
+            info!("Generated new master seed {}", seed.fingerprint());
+            seed
+        }
+    };
+
+    *MASTER_SEED.write().await = Some(seed);
+    Ok(())
+}
+
+/// RPC endpoints the agent depends on, probed by `/health`.
+pub fn rpc_endpoints() -> Vec<&'static str> {
+    vec![SUI_RPC_URL]
+}
+
+/// Blake2b256 over the compiled-in chain configuration, so clients can tell
+/// which packages and objects this build trades against.
+pub fn config_hash() -> String {
+    use fastcrypto::hash::{Blake2b256, HashFunction};
+    let config = [
+        SUI_RPC_URL,
+        DEX_PACKAGE_ID,
+        POOL_ID,
+        SUBSCRIPTION_MANAGER_PACKAGE_ID,
+        SUBSCRIPTION_MANAGER_ID,
+        ENCLAVE_PACKAGE_ID,
+        TRADING_PACKAGE_ID,
+        ENCLAVE_CONFIG_ID,
+    ];
+    let bytes = bcs::to_bytes(&config).expect("should not fail");
+    Hex::encode(Blake2b256::digest(&bytes))
+}
+
+/// Blake2b256 over the trading rules the enclave enforces: the supported
+/// actions and the operator supplied `STRATEGY_PARAMS`, if any.
+pub fn strategy_hash() -> String {
+    use fastcrypto::hash::{Blake2b256, HashFunction};
+    let params = std::env::var(STRATEGY_PARAMS_ENV).unwrap_or_default();
+    let bytes = bcs::to_bytes(&(TRADE_ACTIONS, params)).expect("should not fail");
+    Hex::encode(Blake2b256::digest(&bytes))
+}
+
+pub async fn attestation_commitment() -> AttestationCommitment {
+    AttestationCommitment {
+        wallet_addresses: TRADING_WALLET
+            .read()
+            .await
+            .iter()
+            .map(|wallet| wallet.address.clone())
+            .collect(),
+        config_hash: config_hash(),
+        strategy_hash: strategy_hash(),
+    }
+}
+
+pub async fn health_details() -> TradingHealth {
+    let wallet_count = TRADING_WALLET.read().await.iter().count();
+    let last_trade = journal::entries()
+        .await
+        .into_iter()
+        .rev()
+        .find(|entry| entry.record.data.event.kind == "trade")
+        .map(|entry| LastTrade {
+            tx_digest: entry.record.data.event.tx_digest,
+            action: serde_json::from_str::<TradeRequest>(&entry.record.data.event.request)
+                .map(|request| request.action)
+                .unwrap_or_default(),
+            timestamp_ms: entry.record.timestamp_ms,
+        });
+
+    TradingHealth {
+        wallet_count,
+        last_trade,
+        config_hash: config_hash(),
+        registration: registration::status().await,
+        sponsor: sponsor::status().await,
+        events: events::status().await,
+        agent: agent::current().await,
+        agent_error: agent::load_error().await,
+        agent_binding: binding::current().await,
+    }
+}
+
 // ====== Helper Functions ======
 
 fn derive_sui_address(keypair: &SuiKeyPair) -> String {
@@ -135,6 +347,10 @@ fn derive_sui_address(keypair: &SuiKeyPair) -> String {
     address.to_string()
 }
 
+fn request_json<T: Serialize>(payload: &T) -> String {
+    serde_json::to_string(payload).unwrap_or_default()
+}
+
 fn get_current_timestamp() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -149,7 +365,7 @@ async fn build_and_execute_swap_sui_to_usdc(
     _wallet_address: &str,
     amount: u64,
     min_output: u64,
-) -> Result<String, EnclaveError> {
+) -> Result<(String, u64), EnclaveError> {
     info!("Starting build_and_execute_swap_sui_to_usdc: amount={}, min_output={}", amount, min_output);
     
     #[cfg(feature = "trading")]
@@ -159,33 +375,35 @@ async fn build_and_execute_swap_sui_to_usdc(
         let client = SuiClientBuilder::default()
             .build(SUI_RPC_URL)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to create Sui client: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to create Sui client: {}", e)))?;
         info!("Sui client created successfully");
 
         let sender = derive_sui_address(keypair).parse::<SuiAddress>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid sender address: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid sender address: {}", e)))?;
         
         let pool_object_id = POOL_ID.parse::<ObjectID>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid pool ID: {}", e)))?;
+            .map_err(|e| EnclaveError::Internal(format!("Invalid pool ID: {}", e)))?;
         
         // Get SUI coins for the swap
         let coins = client
             .coin_read_api()
             .get_coins(sender, Some("0x2::sui::SUI".to_string()), None, None)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get SUI coins: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get SUI coins: {}", e)))?;
 
         if coins.data.is_empty() {
-            return Err(EnclaveError::GenericError("No SUI coins available".to_string()));
+            return Err(EnclaveError::InsufficientBalance("No SUI coins available".to_string()));
         }
 
         // Get gas coin following SDK examples (function_move_call.rs:46, sign_tx_guide.rs:104-111)
         let gas_budget = 50000000; // 0.05 SUI for DEX operations
-        let total_needed = amount + gas_budget;
+        // With a sponsor the coin only funds the swap, otherwise it pays for gas too
+        let sponsored = sponsor::is_sponsored(&sender, gas_budget).await;
+        let total_needed = if sponsored { amount } else { amount + gas_budget };
         
         let gas_coin = coins.data.into_iter()
             .find(|coin| coin.balance >= total_needed)
-            .ok_or_else(|| EnclaveError::GenericError(format!("Need at least {} SUI for swap + gas", total_needed)))?;
+            .ok_or_else(|| EnclaveError::InsufficientBalance(format!("Need at least {} SUI for swap + gas", total_needed)))?;
 
         // Build programmable transaction following SDK examples
         let mut ptb = sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder::new();
@@ -199,10 +417,17 @@ async fn build_and_execute_swap_sui_to_usdc(
             }
         )).unwrap();
         
-        // Split SUI from gas coin for the exact swap amount
+        // Split SUI from gas coin for the exact swap amount, or from the wallet's coin when sponsored
+        let source_coin = if sponsored {
+            ptb.input(sui_types::transaction::CallArg::Object(
+                sui_types::transaction::ObjectArg::ImmOrOwnedObject(gas_coin.object_ref())
+            )).unwrap()
+        } else {
+            sui_types::transaction::Argument::GasCoin
+        };
         let amount_arg = ptb.pure(amount).unwrap();
         let split_coin = ptb.command(sui_types::transaction::Command::SplitCoins(
-            sui_types::transaction::Argument::GasCoin, // Use GasCoin for splitting
+            source_coin,
             vec![amount_arg],
         ));
         
@@ -231,10 +456,17 @@ async fn build_and_execute_swap_sui_to_usdc(
         ));
         
         let pt = ptb.finish();
+
+        if sponsored {
+            info!("Submitting sponsored swap SUI to USDC transaction...");
+            let tx_response = sponsor::execute_sponsored(&client, keypair, sender, pt, gas_budget).await?;
+            info!("Swap SUI to USDC successful: {}", tx_response.digest);
+            return Ok((tx_response.digest.to_string(), chain::swap_output(&tx_response)?));
+        }
         
         // Get gas price
         let gas_price = client.read_api().get_reference_gas_price().await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get gas price: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
 
         // Create transaction data with proper gas coin (following SDK examples)
         let tx_data = sui_types::transaction::TransactionData::new_programmable(
@@ -269,11 +501,12 @@ async fn build_and_execute_swap_sui_to_usdc(
             .map_err(|e| {
                 let error_msg = format!("Transaction execution failed: {}", e);
                 info!("Swap SUI to USDC error: {}", error_msg);
-                EnclaveError::GenericError(error_msg)
+                EnclaveError::Submission(error_msg)
             })?;
+        chain::check_status(&tx_response)?;
 
         info!("Swap SUI to USDC successful: {}", tx_response.digest);
-        Ok(tx_response.digest.to_string())
+        Ok((tx_response.digest.to_string(), chain::swap_output(&tx_response)?))
     }
     
     #[cfg(not(feature = "trading"))]
@@ -283,7 +516,7 @@ async fn build_and_execute_swap_sui_to_usdc(
         let mut hasher = Blake2b256::default();
         hasher.update(b"swap_sui_to_usdc");
         let hash = hasher.finalize();
-        Ok(format!("0x{}", Hex::encode(&hash.as_ref()[..8])))
+        Ok((format!("0x{}", Hex::encode(&hash.as_ref()[..8])), min_output))
     }
 }
 
@@ -292,20 +525,20 @@ async fn build_and_execute_swap_usdc_to_sui(
     _wallet_address: &str,
     amount: u64,
     min_output: u64,
-) -> Result<String, EnclaveError> {
+) -> Result<(String, u64), EnclaveError> {
     #[cfg(feature = "trading")]
     {
         // Create Sui client
         let client = SuiClientBuilder::default()
             .build(SUI_RPC_URL)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to create Sui client: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to create Sui client: {}", e)))?;
 
         let sender = derive_sui_address(keypair).parse::<SuiAddress>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid sender address: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid sender address: {}", e)))?;
         
         let pool_object_id = POOL_ID.parse::<ObjectID>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid pool ID: {}", e)))?;
+            .map_err(|e| EnclaveError::Internal(format!("Invalid pool ID: {}", e)))?;
         
         // Get USDC coins for the swap
         let usdc_coin_type = format!("{}::mock_usdc::MOCK_USDC", DEX_PACKAGE_ID);
@@ -313,10 +546,10 @@ async fn build_and_execute_swap_usdc_to_sui(
             .coin_read_api()
             .get_coins(sender, Some(usdc_coin_type), None, None)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get USDC coins: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get USDC coins: {}", e)))?;
 
         if coins.data.is_empty() {
-            return Err(EnclaveError::GenericError("No USDC coins available".to_string()));
+            return Err(EnclaveError::InsufficientBalance("No USDC coins available".to_string()));
         }
 
         // Find suitable USDC coins for the swap
@@ -332,24 +565,24 @@ async fn build_and_execute_swap_usdc_to_sui(
         }
         
         if total_usdc_balance < amount {
-            return Err(EnclaveError::GenericError("Insufficient USDC balance for swap".to_string()));
+            return Err(EnclaveError::InsufficientBalance("Insufficient USDC balance for swap".to_string()));
         }
         
+        let gas_budget = 50000000; // 0.05 SUI for DEX operations
+        let sponsored = sponsor::is_sponsored(&sender, gas_budget).await;
+
         // Get SUI coins for gas
         let sui_coins = client
             .coin_read_api()
             .get_coins(sender, Some("0x2::sui::SUI".to_string()), None, None)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get SUI coins for gas: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get SUI coins for gas: {}", e)))?;
 
-        if sui_coins.data.is_empty() {
-            return Err(EnclaveError::GenericError("No SUI coins available for gas".to_string()));
-        }
-        
-        let gas_budget = 50000000; // 0.05 SUI for DEX operations
         let gas_coin = sui_coins.data.into_iter()
-            .find(|coin| coin.balance >= gas_budget)
-            .ok_or_else(|| EnclaveError::GenericError("Insufficient SUI for gas".to_string()))?;
+            .find(|coin| coin.balance >= gas_budget);
+        if gas_coin.is_none() && !sponsored {
+            return Err(EnclaveError::InsufficientBalance("Insufficient SUI for gas".to_string()));
+        }
 
         // Build programmable transaction
         let mut ptb = sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder::new();
@@ -418,10 +651,21 @@ async fn build_and_execute_swap_usdc_to_sui(
         ));
         
         let pt = ptb.finish();
+
+        // USDC-only wallets rely on the sponsor for gas
+        let gas_coin = match gas_coin {
+            Some(gas_coin) if !sponsored => gas_coin,
+            _ => {
+                info!("Submitting sponsored swap USDC to SUI transaction...");
+                let tx_response = sponsor::execute_sponsored(&client, keypair, sender, pt, gas_budget).await?;
+                info!("Swap USDC to SUI successful: {}", tx_response.digest);
+                return Ok((tx_response.digest.to_string(), chain::swap_output(&tx_response)?));
+            }
+        };
         
         // Get gas price
         let gas_price = client.read_api().get_reference_gas_price().await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get gas price: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
 
         // Use gas coin for transaction
         let gas_object = (gas_coin.coin_object_id, gas_coin.version, gas_coin.digest);
@@ -459,11 +703,12 @@ async fn build_and_execute_swap_usdc_to_sui(
             .map_err(|e| {
                 let error_msg = format!("Transaction execution failed: {}", e);
                 info!("Swap USDC to SUI error: {}", error_msg);
-                EnclaveError::GenericError(error_msg)
+                EnclaveError::Submission(error_msg)
             })?;
+        chain::check_status(&tx_response)?;
 
         info!("Swap USDC to SUI successful: {}", tx_response.digest);
-        Ok(tx_response.digest.to_string())
+        Ok((tx_response.digest.to_string(), chain::swap_output(&tx_response)?))
     }
     
     #[cfg(not(feature = "trading"))]
@@ -473,7 +718,7 @@ async fn build_and_execute_swap_usdc_to_sui(
         let mut hasher = Blake2b256::default();
         hasher.update(b"swap_usdc_to_sui");
         let hash = hasher.finalize();
-        Ok(format!("0x{}", Hex::encode(&hash.as_ref()[..8])))
+        Ok((format!("0x{}", Hex::encode(&hash.as_ref()[..8])), min_output))
     }
 }
 
@@ -503,42 +748,52 @@ async fn withdraw_from_subscription_manager(
         let client = SuiClientBuilder::default()
             .build(SUI_RPC_URL)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to create Sui client: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to create Sui client: {}", e)))?;
 
         let sender = derive_sui_address(keypair).parse::<SuiAddress>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid sender address: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid sender address: {}", e)))?;
         let recipient_addr = recipient.parse::<SuiAddress>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid recipient address: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid recipient address: {}", e)))?;
         let agent_object_id = agent_id.parse::<ObjectID>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid agent ID: {}", e)))?;
-
-        // Use simple Move call to check subscription status
-        // This is a simplified approach - just check if user has deposited funds
-        // In production, you might want to add more validation
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid agent ID: {}", e)))?;
 
         // Step 1: Get coins for transfer
         let coins = client
             .coin_read_api()
             .get_coins(sender, Some("0x2::sui::SUI".to_string()), None, None)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get coins: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get coins: {}", e)))?;
 
         if coins.data.is_empty() {
-            return Err(EnclaveError::GenericError("No SUI coins available".to_string()));
+            return Err(EnclaveError::InsufficientBalance("No SUI coins available".to_string()));
         }
 
         // Find a coin with sufficient balance for both transfer and gas
         let gas_coin = coins.data.into_iter()
-            .find(|coin| coin.balance >= amount + 10000000) // amount + gas (0.01 SUI)
-            .ok_or_else(|| EnclaveError::GenericError("Insufficient balance for withdrawal + gas".to_string()))?;
+            .find(|coin| coin.balance >= amount + 20000000) // amount + gas (0.02 SUI)
+            .ok_or_else(|| EnclaveError::InsufficientBalance("Insufficient balance for withdrawal + gas".to_string()))?;
 
         // Build simple transfer transaction using GasCoin argument
         let mut ptb = sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder::new();
         
         // Create pure arguments first to avoid borrow conflicts
+        let manager_id = SUBSCRIPTION_MANAGER_ID.parse::<ObjectID>()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid subscription manager ID: {}", e)))?;
+        let manager_arg = ptb.obj(chain::shared_object_arg(&client, manager_id, true).await?).unwrap();
+        let agent_arg = ptb.pure(agent_object_id).unwrap();
         let amount_arg = ptb.pure(amount).unwrap();
         let recipient_arg = ptb.pure(recipient_addr).unwrap();
 
+        // Deduct the withdrawal from the subscriber's deposit on-chain
+        ptb.programmable_move_call(
+            SUBSCRIPTION_MANAGER_PACKAGE_ID.parse::<ObjectID>()
+                .map_err(|e| EnclaveError::Internal(format!("Invalid package ID: {}", e)))?,
+            sui_types::Identifier::new("subscription_manager").unwrap(),
+            sui_types::Identifier::new("withdraw_from_agent").unwrap(),
+            vec![],
+            vec![manager_arg, agent_arg, amount_arg, recipient_arg],
+        );
+
         // Use Argument::GasCoin to reference the gas coin
         let split_coin = ptb.command(sui_types::transaction::Command::SplitCoins(
             sui_types::transaction::Argument::GasCoin,
@@ -554,9 +809,9 @@ async fn withdraw_from_subscription_manager(
         let pt = ptb.finish();
         
         // Get gas budget and price
-        let gas_budget = 10000000; // 0.01 SUI for simple transfer
+        let gas_budget = 20000000; // 0.02 SUI for the deposit update and transfer
         let gas_price = client.read_api().get_reference_gas_price().await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get gas price: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
 
         // Create transaction data
         let tx_data = sui_types::transaction::TransactionData::new_programmable(
@@ -595,7 +850,8 @@ async fn withdraw_from_subscription_manager(
                 None,
             )
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Transaction execution failed: {}", e)))?;
+            .map_err(|e| EnclaveError::Submission(format!("Transaction execution failed: {}", e)))?;
+        chain::check_status(&tx_response)?;
 
         Ok(tx_response.digest.to_string())
     }
@@ -619,36 +875,21 @@ async fn fetch_balances(address: &str) -> Result<(u64, u64), EnclaveError> {
         let client = SuiClientBuilder::default()
             .build(SUI_RPC_URL)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to create Sui client: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to create Sui client: {}", e)))?;
 
         // Parse the address
         let sui_address: SuiAddress = address.parse()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid address format: {}", e)))?;
-
-        // Get all coin objects for this address
-        let coins = client
-            .coin_read_api()
-            .get_all_coins(sui_address, None, None)
-            .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to fetch coins: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid address format: {}", e)))?;
 
+        // Sum the SUI and MOCK_USDC coins across all pages
+        let usdc_coin_type = balances::usdc_coin_type();
         let mut sui_balance = 0u64;
         let mut usdc_balance = 0u64;
-
-        // MOCK_USDC coin type from the DEX contract
-        let usdc_coin_type = format!("{}::mock_usdc::MOCK_USDC", DEX_PACKAGE_ID);
-
-        for coin in coins.data {
-            match coin.coin_type.as_str() {
-                "0x2::sui::SUI" => {
-                    sui_balance += coin.balance;
-                }
-                coin_type if coin_type == &usdc_coin_type => {
-                    usdc_balance += coin.balance;
-                }
-                _ => {
-                    // Other coin types, ignore for now
-                }
+        for (coin_type, (balance, _)) in balances::coin_balances(&client, sui_address).await? {
+            if chain::is_same_type(&coin_type, "0x2::sui::SUI") {
+                sui_balance += balance;
+            } else if chain::is_same_type(&coin_type, &usdc_coin_type) {
+                usdc_balance += balance;
             }
         }
 
@@ -671,10 +912,7 @@ pub async fn init_wallet_wrapper(
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     match init_wallet_internal(state, request).await {
         Ok(response) => Ok(Box::new(warp::reply::json(&response))),
-        Err(e) => Ok(Box::new(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))),
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
@@ -685,10 +923,7 @@ pub async fn execute_trade_wrapper(
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     match execute_trade_internal(state, request).await {
         Ok(response) => Ok(Box::new(warp::reply::json(&response))),
-        Err(e) => Ok(Box::new(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))),
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
@@ -698,10 +933,7 @@ pub async fn wallet_status_wrapper(
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     match wallet_status_internal(state, request).await {
         Ok(response) => Ok(Box::new(warp::reply::json(&response))),
-        Err(e) => Ok(Box::new(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))),
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
@@ -711,10 +943,7 @@ pub async fn withdraw_wrapper(
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     match withdraw_internal(state, request).await {
         Ok(response) => Ok(Box::new(warp::reply::json(&response))),
-        Err(e) => Ok(Box::new(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))),
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
@@ -724,10 +953,17 @@ pub async fn subscription_withdraw_wrapper(
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     match subscription_withdraw_internal(state, request).await {
         Ok(response) => Ok(Box::new(warp::reply::json(&response))),
-        Err(e) => Ok(Box::new(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn sealed_seed_wrapper(
+    request: ProcessDataRequest<SealedSeedRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match sealed_seed_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
@@ -737,10 +973,7 @@ pub async fn simple_transfer_wrapper(
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     match simple_transfer_internal(state, request).await {
         Ok(response) => Ok(Box::new(warp::reply::json(&response))),
-        Err(e) => Ok(Box::new(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))),
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
@@ -755,18 +988,33 @@ async fn init_wallet_internal(
     
     // Check if already initialized
     if wallet_guard.is_some() {
-        return Err(EnclaveError::GenericError("Wallet already initialized".to_string()));
+        return Err(EnclaveError::Conflict("Wallet already initialized".to_string()));
     }
     
-    // Generate new SuiKeyPair
-    let ed25519_keypair = Ed25519KeyPair::generate(&mut rand::thread_rng());
-    let keypair = SuiKeyPair::Ed25519(ed25519_keypair);
+    let owner = request.payload.owner_address.parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid owner address: {}", e)))?;
+    let agent_id = request.payload.agent_id.as_ref()
+        .map(|id| id.parse::<ObjectID>())
+        .transpose()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid agent ID: {}", e)))?;
+
+    // Derive the wallet key from the master seed so it can be re-derived after a restart
+    let seed_guard = MASTER_SEED.read().await;
+    let master_seed = seed_guard.as_ref()
+        .ok_or_else(|| EnclaveError::Internal("Master seed not loaded".to_string()))?;
+    let keypair = master_seed.derive_wallet_keypair(&owner, agent_id.as_ref())?;
     let address = derive_sui_address(&keypair);
+
+    // The binding is kept for /health and /bind_agent, the signed response must match the Move struct
+    if let Some(agent_id) = &agent_id {
+        binding::sign_binding(&state, agent_id, &address, &request.payload.owner_address).await;
+    }
     
     let wallet_state = WalletState {
         keypair: Arc::new(keypair),
         address: address.clone(),
         owner: request.payload.owner_address.clone(),
+        agent_id: request.payload.agent_id.clone(),
     };
     
     *wallet_guard = Some(wallet_state);
@@ -788,6 +1036,33 @@ async fn init_wallet_internal(
 }
 
 
+async fn sealed_seed_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<SealedSeedRequest>,
+) -> Result<ProcessedDataResponse<CommonIntentMessage<SealedSeedResponse>>, EnclaveError> {
+    if state.api_key.is_empty() || request.payload.api_key != state.api_key {
+        return Err(EnclaveError::Unauthorized("Invalid API key".to_string()));
+    }
+    info!("Exporting sealed master seed");
+    let sealing_key = sealing::sealing_key().await?;
+
+    let seed_guard = MASTER_SEED.read().await;
+    let master_seed = seed_guard.as_ref()
+        .ok_or_else(|| EnclaveError::Internal("Master seed not loaded".to_string()))?;
+
+    let response = SealedSeedResponse {
+        sealed_seed: master_seed.seal(&sealing_key)?,
+        seed_fingerprint: master_seed.fingerprint(),
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
 async fn execute_trade_internal(
     state: Arc<AppState>,
     request: ProcessDataRequest<TradeRequest>,
@@ -796,17 +1071,25 @@ async fn execute_trade_internal(
     
     let wallet_guard = TRADING_WALLET.read().await;
     let wallet_state = wallet_guard.as_ref()
-        .ok_or_else(|| EnclaveError::GenericError("Wallet not initialized".to_string()))?;
+        .ok_or_else(|| EnclaveError::NotFound("Wallet not initialized".to_string()))?;
+
+    // Deactivated agents do not trade, and funds of expired subscriptions are not traded
+    if let Some(agent_id) = &wallet_state.agent_id {
+        agent::check_active(agent_id).await?;
+        let (sui_balance, usdc_balance) = fetch_balances(&wallet_state.address).await?;
+        let balance = if request.payload.action == "buy_sui" { usdc_balance } else { sui_balance };
+        expiry::check_allocation(agent_id, balance, request.payload.amount).await?;
+    }
     
     // Execute trade based on action
-    let tx_digest = match request.payload.action.as_str() {
+    let (tx_digest, amount_out) = match request.payload.action.as_str() {
         "buy_sui" => {
             build_and_execute_swap_usdc_to_sui(
                 &*wallet_state.keypair,
                 &wallet_state.address,
                 request.payload.amount,
                 request.payload.min_output,
-            ).await?
+            ).await
         },
         "sell_sui" => {
             build_and_execute_swap_sui_to_usdc(
@@ -814,26 +1097,48 @@ async fn execute_trade_internal(
                 &wallet_state.address,
                 request.payload.amount,
                 request.payload.min_output,
-            ).await?
+            ).await
         },
-        _ => return Err(EnclaveError::GenericError("Invalid trade action".to_string())),
-    };
+        _ => return Err(EnclaveError::InvalidRequest("Invalid trade action".to_string())),
+    }
+    .map_err(|e| aborts::decode(e, &AbortContext::swap(request.payload.min_output)))
+    .inspect_err(|e| warn!("Trade failed: {}", e))?;
     
     let timestamp_ms = get_current_timestamp();
+
+    journal::record(&state.eph_kp, JournalEvent {
+        kind: "trade".to_string(),
+        wallet_address: wallet_state.address.clone(),
+        request: request_json(&request.payload),
+        quote: Some(request.payload.min_output),
+        tx_digest: tx_digest.clone(),
+        amount_in: request.payload.amount,
+        amount_out: Some(amount_out),
+        recipient: None,
+    }, timestamp_ms).await;
+    drop(wallet_guard);
+
+    // Value the wallet after the trade for the performance report
+    if let Err(e) = snapshots::take().await {
+        warn!("Failed to snapshot the NAV after the trade: {}", e);
+    }
     
     let response = TradeResponse {
         tx_digest,
         action: request.payload.action,
         amount: request.payload.amount,
-        min_output: request.payload.min_output,
+        amount_out,
     };
     
-    Ok(to_signed_response(
+    let signed = to_signed_response(
         &state.eph_kp,
         response,
         timestamp_ms,
         IntentScope::ProcessData,
-    ))
+    );
+    relayer::submit(&signed).await;
+
+    Ok(signed)
 }
 
 async fn wallet_status_internal(
@@ -849,18 +1154,51 @@ async fn wallet_status_internal(
     } else {
         (false, None, None)
     };
+    drop(wallet_guard);
     
-    // Fetch actual balances from blockchain
-    let (sui_balance, usdc_balance) = if initialized && wallet_address.is_some() {
-        match fetch_balances(wallet_address.as_ref().unwrap()).await {
+    // Value the wallet at the pool price, or just fetch the balances if the pool can't be read
+    let nav = match wallet_address.as_ref() {
+        Some(address) => {
+            let valued = match chain::sui_client().await {
+                Ok(client) => shares::nav(&client, address).await,
+                Err(e) => Err(e),
+            };
+            match valued {
+                Ok(nav) => Some(nav),
+                Err(e) => {
+                    info!("Failed to value the wallet: {}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let (sui_balance, usdc_balance) = match (&nav, wallet_address.as_ref()) {
+        (Some(nav), _) => (nav.sui_balance, nav.usdc_balance),
+        (None, Some(address)) => match fetch_balances(address).await {
             Ok((sui, usdc)) => (sui, usdc),
             Err(e) => {
                 info!("Failed to fetch balances: {}", e);
                 (0, 0) // Fallback to zero if fetch fails
             }
+        },
+        (None, None) => (0, 0),
+    };
+    let assets = match wallet_address.as_ref() {
+        Some(address) => {
+            let fetched = match chain::sui_client().await {
+                Ok(client) => balances::fetch(&client, address, nav.as_ref()).await,
+                Err(e) => Err(e),
+            };
+            match fetched {
+                Ok(assets) => Some(assets),
+                Err(e) => {
+                    info!("Failed to fetch the wallet's assets: {}", e);
+                    None
+                }
+            }
         }
-    } else {
-        (0, 0)
+        None => None,
     };
     
     let timestamp_ms = get_current_timestamp();
@@ -871,6 +1209,8 @@ async fn wallet_status_internal(
         owner,
         sui_balance,
         usdc_balance,
+        nav,
+        assets,
     };
     
     Ok(to_signed_response(
@@ -889,13 +1229,13 @@ async fn withdraw_internal(
     
     let wallet_guard = TRADING_WALLET.read().await;
     let wallet_state = wallet_guard.as_ref()
-        .ok_or_else(|| EnclaveError::GenericError("Wallet not initialized".to_string()))?;
+        .ok_or_else(|| EnclaveError::NotFound("Wallet not initialized".to_string()))?;
     
     // Owner-only check (example - implement proper auth)
     // In production, implement proper signature-based authentication
     // For now, we'll check if the recipient is the owner (simplified)
     if request.payload.recipient != wallet_state.owner {
-        return Err(EnclaveError::GenericError("Unauthorized: recipient must be owner".to_string()));
+        return Err(EnclaveError::Unauthorized("Unauthorized: recipient must be owner".to_string()));
     }
     
     // Execute withdrawal
@@ -906,6 +1246,17 @@ async fn withdraw_internal(
     ).await?;
     
     let timestamp_ms = get_current_timestamp();
+
+    journal::record(&state.eph_kp, JournalEvent {
+        kind: "withdraw".to_string(),
+        wallet_address: wallet_state.address.clone(),
+        request: request_json(&request.payload),
+        quote: None,
+        tx_digest: tx_digest.clone(),
+        amount_in: amount,
+        amount_out: None,
+        recipient: Some(request.payload.recipient.clone()),
+    }, timestamp_ms).await;
     
     let response = WithdrawResponse {
         tx_digest,
@@ -929,7 +1280,7 @@ async fn simple_transfer_internal(
     
     let wallet_guard = TRADING_WALLET.read().await;
     let wallet_state = wallet_guard.as_ref()
-        .ok_or_else(|| EnclaveError::GenericError("Wallet not initialized".to_string()))?;
+        .ok_or_else(|| EnclaveError::NotFound("Wallet not initialized".to_string()))?;
     
     #[cfg(feature = "trading")]
     let tx_digest = {
@@ -938,12 +1289,12 @@ async fn simple_transfer_internal(
         let client = SuiClientBuilder::default()
             .build(SUI_RPC_URL)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to create Sui client: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to create Sui client: {}", e)))?;
 
         let sender = derive_sui_address(&wallet_state.keypair).parse::<SuiAddress>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid sender address: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid sender address: {}", e)))?;
         let recipient_addr = request.payload.recipient.parse::<SuiAddress>()
-            .map_err(|e| EnclaveError::GenericError(format!("Invalid recipient address: {}", e)))?;
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid recipient address: {}", e)))?;
         
         let amount = request.payload.amount.unwrap_or(1000000000); // Default 1 SUI
 
@@ -952,15 +1303,15 @@ async fn simple_transfer_internal(
             .coin_read_api()
             .get_coins(sender, Some("0x2::sui::SUI".to_string()), None, None)
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get coins: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get coins: {}", e)))?;
 
         if coins.data.is_empty() {
-            return Err(EnclaveError::GenericError("No SUI coins available".to_string()));
+            return Err(EnclaveError::InsufficientBalance("No SUI coins available".to_string()));
         }
 
         let gas_coin = coins.data.into_iter()
             .find(|coin| coin.balance >= amount + 10000000) // amount + gas (0.01 SUI)
-            .ok_or_else(|| EnclaveError::GenericError("Insufficient balance".to_string()))?;
+            .ok_or_else(|| EnclaveError::InsufficientBalance("Insufficient balance".to_string()))?;
 
         // Build simple transfer using GasCoin argument (avoids double usage)
         let mut ptb = ProgrammableTransactionBuilder::new();
@@ -983,7 +1334,7 @@ async fn simple_transfer_internal(
         
         let gas_budget = 10000000; // 0.01 SUI for simple transfer
         let gas_price = client.read_api().get_reference_gas_price().await
-            .map_err(|e| EnclaveError::GenericError(format!("Failed to get gas price: {}", e)))?;
+            .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
 
         let tx_data = sui_types::transaction::TransactionData::new_programmable(
             sender,
@@ -1011,7 +1362,8 @@ async fn simple_transfer_internal(
                 None,
             )
             .await
-            .map_err(|e| EnclaveError::GenericError(format!("Transaction execution failed: {}", e)))?;
+            .map_err(|e| EnclaveError::Submission(format!("Transaction execution failed: {}", e)))?;
+        chain::check_status(&tx_response)?;
 
         tx_response.digest.to_string()
     };
@@ -1027,6 +1379,17 @@ async fn simple_transfer_internal(
     };
     
     let timestamp_ms = get_current_timestamp();
+
+    journal::record(&state.eph_kp, JournalEvent {
+        kind: "transfer".to_string(),
+        wallet_address: wallet_state.address.clone(),
+        request: request_json(&request.payload),
+        quote: None,
+        tx_digest: tx_digest.clone(),
+        amount_in: request.payload.amount.unwrap_or(1000000000),
+        amount_out: None,
+        recipient: Some(request.payload.recipient.clone()),
+    }, timestamp_ms).await;
     
     let response = WithdrawResponse {
         tx_digest,
@@ -1049,25 +1412,97 @@ async fn subscription_withdraw_internal(
     info!("Processing subscription withdrawal from agent: {} to: {} amount: {}", 
           request.payload.agent_id, request.payload.recipient, request.payload.amount);
     
+    // Without saved burns, a restart would mint the replayed deposits again at the current NAV
+    events::require_persistence("withdraw against the share ledger")?;
+
+    // Mint the shares of deposits not yet seen by the event listener
+    events::sync().await?;
+
     let wallet_guard = TRADING_WALLET.read().await;
     let wallet_state = wallet_guard.as_ref()
-        .ok_or_else(|| EnclaveError::GenericError("Wallet not initialized".to_string()))?;
+        .ok_or_else(|| EnclaveError::NotFound("Wallet not initialized".to_string()))?;
     
-    // This function validates subscription and executes withdrawal
-    let tx_digest = withdraw_from_subscription_manager(
+    let agent_object_id = request.payload.agent_id.parse::<ObjectID>()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid agent ID: {}", e)))?;
+    let subscriber = request.payload.recipient.parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid recipient address: {}", e)))?;
+
+    // Check the subscription, deposit and subscriber signature on-chain before touching the ledger
+    let client = chain::sui_client().await?;
+    let wallet_address = wallet_state.address.parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid wallet address: {}", e)))?;
+    let subscription = subscriptions::verify_withdrawal(
+        &client,
+        agent_object_id,
+        subscriber,
+        wallet_address,
+        request.payload.amount,
+        request.payload.timestamp_ms,
+        &request.payload.signature,
+    )
+    .await?;
+    info!("Withdrawing {} of deposit {} for {}", request.payload.amount, subscription.deposited, subscriber);
+
+    // Reserve the shares before funds move, so the ledger is not locked while the withdrawal executes
+    let nav = shares::nav(&client, &wallet_state.address).await?;
+    let shares_burned = shares::LEDGERS.lock().await
+        .entry(agent_object_id)
+        .or_default()
+        .reserve_withdrawal(&subscriber, request.payload.amount, nav.value)?;
+    events::save().await;
+
+    let result = withdraw_from_subscription_manager(
         &*wallet_state.keypair,
         &request.payload.agent_id,
         request.payload.amount,
         &request.payload.recipient,
-    ).await?;
+    ).await
+    .map_err(|e| aborts::decode(e, &AbortContext::withdrawal(request.payload.amount)))
+    .inspect_err(|e| warn!("Subscription withdrawal failed: {}", e));
+
+    let mut ledgers = shares::LEDGERS.lock().await;
+    let ledger = ledgers.entry(agent_object_id).or_default();
+    let tx_digest = match result {
+        Ok(tx_digest) => {
+            ledger.finish_withdrawal(request.payload.amount);
+            tx_digest
+        }
+        // The transaction may have executed, so the shares stay burned
+        Err(e @ EnclaveError::Submission(_)) => {
+            ledger.finish_withdrawal(request.payload.amount);
+            warn!("Withdrawal of {} for {} may have executed, its shares stay burned", request.payload.amount, subscriber);
+            return Err(e);
+        }
+        Err(e) => {
+            ledger.cancel_withdrawal(&subscriber, request.payload.amount, shares_burned);
+            drop(ledgers);
+            events::save().await;
+            return Err(e);
+        }
+    };
+    let remaining_shares = ledger.positions.get(&subscriber).map_or(0, |position| position.shares);
+    drop(ledgers);
     
     let timestamp_ms = get_current_timestamp();
+
+    journal::record(&state.eph_kp, JournalEvent {
+        kind: "subscription_withdraw".to_string(),
+        wallet_address: wallet_state.address.clone(),
+        request: request_json(&request.payload),
+        quote: None,
+        tx_digest: tx_digest.clone(),
+        amount_in: request.payload.amount,
+        amount_out: None,
+        recipient: Some(request.payload.recipient.clone()),
+    }, timestamp_ms).await;
     
     let response = SubscriptionWithdrawResponse {
         tx_digest,
         agent_id: request.payload.agent_id,
         amount: request.payload.amount,
         recipient: request.payload.recipient,
+        shares_burned,
+        remaining_shares,
     };
     
     Ok(to_signed_response(
@@ -1081,10 +1516,12 @@ async fn subscription_withdraw_internal(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use fastcrypto::ed25519::Ed25519KeyPair;
+    use fastcrypto::traits::KeyPair;
     
     #[test]
     fn test_address_derivation() {
-        let keypair = Ed25519KeyPair::generate(&mut rand::thread_rng());
+        let keypair = SuiKeyPair::Ed25519(Ed25519KeyPair::generate(&mut rand::thread_rng()));
         let address = derive_sui_address(&keypair);
         assert!(address.starts_with("0x"));
         assert_eq!(address.len(), 66); // "0x" + 64 hex chars
@@ -0,0 +1,201 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Performance report computed from the NAV snapshots and the trade journal,
+// signed under `IntentScope::Performance` so the marketplace can show numbers
+// the enclave vouches for. Returns are measured on the share price, so
+// deposits and withdrawals do not count as performance; a wallet without
+// subscribers is measured on its NAV. The report runs up to the latest stored
+// snapshot.
+//
+// Signed payloads are BCS encoded, which has no floating point, so ratios are
+// reported in basis points and the Sharpe estimate in thousandths.
+
+use super::journal::{self, JournalEntry};
+use super::snapshots::{self, NavSnapshot};
+use super::{get_current_timestamp, TradeRequest, TRADING_WALLET};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const BPS: f64 = 10_000.0;
+const YEAR_MS: f64 = 365.0 * 24.0 * 3600.0 * 1000.0;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PerformanceRequest {
+    /// Start of the reported period, defaults to the first snapshot
+    pub since_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PerformanceReport {
+    pub agent_id: Option<String>,
+    pub wallet_address: String,
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub snapshots: u64,
+    pub time_weighted_return_bps: i64,
+    pub max_drawdown_bps: u64,
+    /// Annualized mean over standard deviation of the returns between
+    /// snapshots, times 1000. None with fewer than two returns
+    pub sharpe_milli: Option<i64>,
+    pub trade_count: u64,
+    /// Amounts sold of each asset
+    pub volume_sui: u64,
+    pub volume_usdc: u64,
+    /// Trades after which the share price was higher at the next trade (or now)
+    pub winning_trades: u64,
+    pub win_rate_bps: Option<u64>,
+    /// Hash of the latest journal entry the report covers
+    pub journal_head: String,
+}
+
+/// Compute the report for `snapshots` (oldest first) and the journaled trades among `entries`.
+pub fn compute(snapshots: &[NavSnapshot], entries: &[JournalEntry], since_ms: u64) -> PerformanceReport {
+    // Cumulative value of one unit, chaining the returns between snapshots of the same kind
+    let mut index = vec![1.0f64];
+    let mut returns = Vec::new();
+    for pair in snapshots.windows(2) {
+        let (before, after) = (&pair[0], &pair[1]);
+        let r = if before.share_price.is_some() == after.share_price.is_some() && before.unit_value() > 0 {
+            after.unit_value() as f64 / before.unit_value() as f64 - 1.0
+        } else {
+            0.0
+        };
+        returns.push(r);
+        index.push(index.last().copied().unwrap_or(1.0) * (1.0 + r));
+    }
+
+    let mut peak = f64::MIN;
+    let mut max_drawdown = 0.0f64;
+    for value in &index {
+        peak = peak.max(*value);
+        max_drawdown = max_drawdown.max(1.0 - value / peak);
+    }
+
+    let sharpe_milli = match (returns.len(), snapshots.first(), snapshots.last()) {
+        (n, Some(first), Some(last)) if n >= 2 && last.timestamp_ms > first.timestamp_ms => {
+            let mean = returns.iter().sum::<f64>() / n as f64;
+            let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            let periods_per_year = YEAR_MS / ((last.timestamp_ms - first.timestamp_ms) as f64 / n as f64);
+            (variance > 0.0).then(|| (mean / variance.sqrt() * periods_per_year.sqrt() * 1000.0).round() as i64)
+        }
+        _ => None,
+    };
+
+    let trades: Vec<&JournalEntry> = entries
+        .iter()
+        .filter(|entry| entry.record.data.event.kind == "trade" && entry.record.timestamp_ms >= since_ms)
+        .collect();
+    let mut report = PerformanceReport {
+        from_ms: snapshots.first().map_or(since_ms, |snapshot| snapshot.timestamp_ms),
+        to_ms: snapshots.last().map_or(since_ms, |snapshot| snapshot.timestamp_ms),
+        snapshots: snapshots.len() as u64,
+        time_weighted_return_bps: ((index.last().copied().unwrap_or(1.0) - 1.0) * BPS).round() as i64,
+        max_drawdown_bps: (max_drawdown * BPS).round() as u64,
+        sharpe_milli,
+        trade_count: trades.len() as u64,
+        journal_head: entries.last().map(|entry| entry.hash.clone()).unwrap_or_default(),
+        ..PerformanceReport::default()
+    };
+
+    // A trade is judged by the index from the first snapshot after it to the first after the next trade
+    let first_after = |timestamp_ms: u64| snapshots.iter().position(|snapshot| snapshot.timestamp_ms >= timestamp_ms);
+    let mut judged = 0u64;
+    for (i, trade) in trades.iter().enumerate() {
+        let event = &trade.record.data.event;
+        match serde_json::from_str::<TradeRequest>(&event.request).map(|request| request.action) {
+            Ok(action) if action == "buy_sui" => report.volume_usdc += event.amount_in,
+            Ok(_) => report.volume_sui += event.amount_in,
+            Err(_) => {}
+        }
+
+        let start = first_after(trade.record.timestamp_ms);
+        let end = match trades.get(i + 1) {
+            Some(next) => first_after(next.record.timestamp_ms),
+            None => snapshots.len().checked_sub(1),
+        };
+        if let (Some(start), Some(end)) = (start, end) {
+            if end > start {
+                judged += 1;
+                if index[end] > index[start] {
+                    report.winning_trades += 1;
+                }
+            }
+        }
+    }
+    report.win_rate_bps = (judged > 0).then(|| report.winning_trades * BPS as u64 / judged);
+    report
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn performance_wrapper(
+    request: ProcessDataRequest<PerformanceRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match performance_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+async fn performance_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<PerformanceRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<PerformanceReport>>, EnclaveError> {
+    let (wallet_address, agent_id) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.address.clone(), wallet.agent_id.clone()),
+        None => return Err(EnclaveError::NotFound("Wallet not initialized".to_string())),
+    };
+
+    // Only stored snapshots: valuing the wallet here would let callers crowd
+    // the buffer with unevenly spaced snapshots and skew the returns
+    let since_ms = request.payload.since_ms.unwrap_or(0);
+    let snapshots = snapshots::since(since_ms).await;
+    let entries = journal::entries().await;
+
+    let report = PerformanceReport {
+        agent_id,
+        wallet_address,
+        ..compute(&snapshots, &entries, since_ms)
+    };
+    Ok(to_signed_response(
+        &state.eph_kp,
+        report,
+        get_current_timestamp(),
+        IntentScope::Performance,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::trading::shares::Nav;
+
+    #[test]
+    fn test_returns_and_drawdown() {
+        let snapshot = |timestamp_ms, share_price| NavSnapshot {
+            timestamp_ms,
+            nav: Nav::new(0, 0, 0, 0),
+            total_shares: 1,
+            share_price: Some(share_price),
+        };
+        // +20%, -25%, +25%: TWR +12.5%, drawdown 25% from the peak
+        let snapshots = vec![
+            snapshot(0, 1_000),
+            snapshot(1_000, 1_200),
+            snapshot(2_000, 900),
+            snapshot(3_000, 1_125),
+        ];
+        let report = compute(&snapshots, &[], 0);
+        assert_eq!(report.time_weighted_return_bps, 1_250);
+        assert_eq!(report.max_drawdown_bps, 2_500);
+        assert_eq!(report.snapshots, 4);
+        assert!(report.sharpe_milli.is_some());
+        assert_eq!(report.win_rate_bps, None);
+    }
+}
@@ -0,0 +1,516 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Owner-controlled recovery of the wallet key, for when the enclave service
+// is abandoned. The owner registers an encryption key and optionally a set of
+// guardians on `/recovery_setup`:
+//
+// - Without guardians, the whole wallet key is released to the owner.
+// - With guardians, the key is split with Shamir secret sharing. Every
+//   guardian receives an encrypted share immediately and the enclave keeps
+//   one, so `threshold` guardians can recover the key without the enclave.
+//
+// The enclave releases its part only after an owner-signed `/recovery_request`
+// followed by `RECOVERY_DELAY_MS`, during which the owner can cancel. Every
+// step is recorded in a log returned by `/recovery_status`; rejected requests
+// beyond the first `MAX_REJECTED_ENTRIES` are only counted.
+//
+// The setup, a pending request and the log are kept in memory only, since the
+// enclave's share must not leave it: a restart drops them, and the owner must
+// run `/recovery_setup` again. Shares already sent to guardians stay valid.
+
+use super::auth::{check_signature_timestamp, verify_personal_message};
+use super::ecies::{self, EncryptedPayload};
+use super::shamir::{self, Share};
+use super::{get_current_timestamp, EmptyRequest, WalletState, TRADING_WALLET};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use fastcrypto::encoding::{Encoding, Hex};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use sui_types::base_types::SuiAddress;
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// Time between an owner's recovery request and the release of the key.
+pub const RECOVERY_DELAY_MS: u64 = 48 * 60 * 60 * 1000;
+const MAX_GUARDIANS: usize = 16;
+/// Rejected requests kept in the log, later ones are only counted
+const MAX_REJECTED_ENTRIES: usize = 100;
+
+lazy_static! {
+    static ref RECOVERY: Arc<RwLock<RecoveryState>> = Arc::new(RwLock::new(RecoveryState::default()));
+}
+
+struct RecoveryConfig {
+    wallet_address: String,
+    owner_key: Vec<u8>,
+    guardians: Vec<String>,
+    threshold: u8,
+    enclave_share: Option<Share>,
+}
+
+#[derive(Default)]
+struct RecoveryState {
+    config: Option<RecoveryConfig>,
+    ready_at_ms: Option<u64>,
+    last_authorized_ms: u64,
+    log: Vec<RecoveryEvent>,
+    rejected_entries: usize,
+    rejections_not_logged: u64,
+}
+
+impl RecoveryState {
+    fn record(&mut self, action: &str, detail: String) {
+        info!("Recovery {}: {}", action, detail);
+        self.log.push(RecoveryEvent {
+            timestamp_ms: get_current_timestamp(),
+            action: action.to_string(),
+            detail,
+        });
+    }
+
+    /// Check a message signed by the wallet owner. Timestamps must increase so
+    /// a signed request cannot be replayed.
+    fn authorize(
+        &mut self,
+        wallet: &WalletState,
+        action: &str,
+        message: &str,
+        timestamp_ms: u64,
+        signature: &str,
+    ) -> Result<(), EnclaveError> {
+        let owner = wallet
+            .owner
+            .parse::<SuiAddress>()
+            .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid owner address: {}", e)))?;
+        let result = check_signature_timestamp(timestamp_ms, get_current_timestamp())
+            .and_then(|_| {
+                if timestamp_ms <= self.last_authorized_ms {
+                    Err(EnclaveError::Unauthorized("Signed message already used".to_string()))
+                } else {
+                    Ok(())
+                }
+            })
+            .and_then(|_| verify_personal_message(&owner, message.as_bytes(), signature));
+
+        match result {
+            Ok(()) => {
+                self.last_authorized_ms = timestamp_ms;
+                Ok(())
+            }
+            // Anyone can send requests, so unparseable ones and those past the
+            // cap are only counted to keep the log bounded
+            Err(e @ EnclaveError::InvalidRequest(_)) => {
+                self.rejections_not_logged += 1;
+                Err(e)
+            }
+            Err(e) if self.rejected_entries >= MAX_REJECTED_ENTRIES => {
+                self.rejections_not_logged += 1;
+                Err(e)
+            }
+            Err(e) => {
+                self.rejected_entries += 1;
+                self.record("rejected", format!("{} by {}: {}", action, owner, e));
+                Err(e)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveryEvent {
+    pub timestamp_ms: u64,
+    pub action: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoverySetupRequest {
+    /// Hex encoded ristretto255 key the owner receives the key or share under
+    pub encryption_key: String,
+    /// Hex encoded ristretto255 keys of the guardians, empty to export to the owner only
+    pub guardians: Vec<String>,
+    /// Number of shares needed to recover the key, ignored without guardians
+    pub threshold: u8,
+    pub timestamp_ms: u64,
+    /// Owner signature over [setup_message]
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryActionRequest {
+    pub timestamp_ms: u64,
+    /// Owner signature over [action_message]
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GuardianShare {
+    pub guardian: String,
+    pub index: u8,
+    pub payload: EncryptedPayload,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoverySetupResponse {
+    pub wallet_address: String,
+    pub threshold: u8,
+    pub guardian_shares: Vec<GuardianShare>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveryStatusResponse {
+    pub configured: bool,
+    pub guardians: Vec<String>,
+    pub threshold: u8,
+    pub ready_at_ms: Option<u64>,
+    pub log: Vec<RecoveryEvent>,
+    /// Rejected requests left out of the log
+    pub rejections_not_logged: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveryExecuteResponse {
+    pub wallet_address: String,
+    /// Shamir index of the released share, None if the whole key was released
+    pub share_index: Option<u8>,
+    /// Key or share encrypted to the owner, with the wallet address as AAD
+    pub payload: EncryptedPayload,
+}
+
+/// Message the owner signs to configure recovery.
+pub fn setup_message(
+    wallet_address: &str,
+    encryption_key: &str,
+    guardians: &[String],
+    threshold: u8,
+    timestamp_ms: u64,
+) -> String {
+    format!(
+        "nautilus-recovery:setup:{}:{}:{}:{}:{}",
+        wallet_address,
+        encryption_key,
+        threshold,
+        guardians.join(","),
+        timestamp_ms
+    )
+}
+
+/// Message the owner signs to request, cancel or execute a recovery.
+pub fn action_message(action: &str, wallet_address: &str, timestamp_ms: u64) -> String {
+    format!("nautilus-recovery:{}:{}:{}", action, wallet_address, timestamp_ms)
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn recovery_setup_wrapper(
+    request: ProcessDataRequest<RecoverySetupRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match recovery_setup_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn recovery_request_wrapper(
+    request: ProcessDataRequest<RecoveryActionRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match recovery_request_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn recovery_cancel_wrapper(
+    request: ProcessDataRequest<RecoveryActionRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match recovery_cancel_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn recovery_execute_wrapper(
+    request: ProcessDataRequest<RecoveryActionRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match recovery_execute_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn recovery_status_wrapper(
+    request: ProcessDataRequest<EmptyRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match recovery_status_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+// ====== Internal Functions ======
+
+async fn recovery_setup_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<RecoverySetupRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<RecoverySetupResponse>>, EnclaveError> {
+    let payload = request.payload;
+    let wallet_guard = TRADING_WALLET.read().await;
+    let wallet = wallet_guard
+        .as_ref()
+        .ok_or_else(|| EnclaveError::NotFound("Wallet not initialized".to_string()))?;
+
+    let mut recovery = RECOVERY.write().await;
+    let message = setup_message(
+        &wallet.address,
+        &payload.encryption_key,
+        &payload.guardians,
+        payload.threshold,
+        payload.timestamp_ms,
+    );
+    recovery.authorize(wallet, "setup", &message, payload.timestamp_ms, &payload.signature)?;
+    if recovery.ready_at_ms.is_some() {
+        return Err(EnclaveError::Conflict(
+            "Cannot reconfigure while a recovery is pending".to_string(),
+        ));
+    }
+    if payload.guardians.len() > MAX_GUARDIANS {
+        return Err(EnclaveError::InvalidRequest(format!(
+            "At most {} guardians are supported",
+            MAX_GUARDIANS
+        )));
+    }
+
+    let owner_key = hex_decode(&payload.encryption_key)?;
+    let guardian_keys = payload
+        .guardians
+        .iter()
+        .map(|guardian| hex_decode(guardian))
+        .collect::<Result<Vec<_>, _>>()?;
+    let aad = wallet.address.as_bytes();
+
+    let (threshold, enclave_share, guardian_shares) = if guardian_keys.is_empty() {
+        (0, None, Vec::new())
+    } else {
+        // Guardians alone must be able to recover if the enclave is gone,
+        // and a single share must not reveal the key
+        if payload.threshold < 2 || payload.threshold as usize > guardian_keys.len() {
+            return Err(EnclaveError::InvalidRequest(format!(
+                "Threshold must be between 2 and the number of guardians ({})",
+                guardian_keys.len()
+            )));
+        }
+        let mut shares = shamir::split(
+            &wallet.keypair.to_bytes(),
+            payload.threshold,
+            guardian_keys.len() as u8 + 1,
+        )?;
+        let enclave_share = shares.remove(0);
+
+        let mut guardian_shares = Vec::with_capacity(shares.len());
+        for ((guardian, key), share) in payload.guardians.iter().zip(&guardian_keys).zip(shares) {
+            guardian_shares.push(GuardianShare {
+                guardian: guardian.clone(),
+                index: share.index,
+                payload: ecies::encrypt(key, aad, &share.data)?,
+            });
+        }
+        (payload.threshold, Some(enclave_share), guardian_shares)
+    };
+
+    // Fail early if the owner key cannot be encrypted to
+    ecies::encrypt(&owner_key, aad, &[])?;
+
+    recovery.config = Some(RecoveryConfig {
+        wallet_address: wallet.address.clone(),
+        owner_key,
+        guardians: payload.guardians.clone(),
+        threshold,
+        enclave_share,
+    });
+    recovery.record(
+        "setup",
+        format!(
+            "wallet {} with {} guardians, threshold {}",
+            wallet.address,
+            payload.guardians.len(),
+            threshold
+        ),
+    );
+
+    let response = RecoverySetupResponse {
+        wallet_address: wallet.address.clone(),
+        threshold,
+        guardian_shares,
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+async fn recovery_request_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<RecoveryActionRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<RecoveryStatusResponse>>, EnclaveError> {
+    let wallet_guard = TRADING_WALLET.read().await;
+    let wallet = configured_wallet(&wallet_guard).await?;
+
+    let mut recovery = RECOVERY.write().await;
+    let message = action_message("request", &wallet.address, request.payload.timestamp_ms);
+    recovery.authorize(wallet, "request", &message, request.payload.timestamp_ms, &request.payload.signature)?;
+    if recovery.ready_at_ms.is_some() {
+        return Err(EnclaveError::Conflict("Recovery already pending".to_string()));
+    }
+
+    let ready_at_ms = get_current_timestamp() + RECOVERY_DELAY_MS;
+    recovery.ready_at_ms = Some(ready_at_ms);
+    recovery.record("request", format!("key release possible at {}", ready_at_ms));
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        status_response(&recovery),
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+async fn recovery_cancel_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<RecoveryActionRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<RecoveryStatusResponse>>, EnclaveError> {
+    let wallet_guard = TRADING_WALLET.read().await;
+    let wallet = configured_wallet(&wallet_guard).await?;
+
+    let mut recovery = RECOVERY.write().await;
+    let message = action_message("cancel", &wallet.address, request.payload.timestamp_ms);
+    recovery.authorize(wallet, "cancel", &message, request.payload.timestamp_ms, &request.payload.signature)?;
+    if recovery.ready_at_ms.take().is_none() {
+        return Err(EnclaveError::NotFound("No recovery pending".to_string()));
+    }
+    recovery.record("cancel", "pending recovery cancelled".to_string());
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        status_response(&recovery),
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+async fn recovery_execute_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<RecoveryActionRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<RecoveryExecuteResponse>>, EnclaveError> {
+    let wallet_guard = TRADING_WALLET.read().await;
+    let wallet = configured_wallet(&wallet_guard).await?;
+
+    let mut recovery = RECOVERY.write().await;
+    let message = action_message("execute", &wallet.address, request.payload.timestamp_ms);
+    recovery.authorize(wallet, "execute", &message, request.payload.timestamp_ms, &request.payload.signature)?;
+
+    let timestamp_ms = get_current_timestamp();
+    match recovery.ready_at_ms {
+        None => return Err(EnclaveError::NotFound("No recovery pending".to_string())),
+        Some(ready_at_ms) if timestamp_ms < ready_at_ms => {
+            return Err(EnclaveError::Conflict(format!(
+                "Recovery delay has not passed, retry after {}",
+                ready_at_ms
+            )))
+        }
+        Some(_) => {}
+    }
+
+    let config = recovery
+        .config
+        .as_ref()
+        .ok_or_else(|| EnclaveError::NotFound("Recovery not configured".to_string()))?;
+    let aad = wallet.address.as_bytes();
+    let (share_index, payload) = match &config.enclave_share {
+        Some(share) => (Some(share.index), ecies::encrypt(&config.owner_key, aad, &share.data)?),
+        None => (None, ecies::encrypt(&config.owner_key, aad, &wallet.keypair.to_bytes())?),
+    };
+
+    recovery.ready_at_ms = None;
+    recovery.record(
+        "execute",
+        match share_index {
+            Some(index) => format!("released enclave share {} to owner", index),
+            None => "released wallet key to owner".to_string(),
+        },
+    );
+
+    let response = RecoveryExecuteResponse {
+        wallet_address: wallet.address.clone(),
+        share_index,
+        payload,
+    };
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        timestamp_ms,
+        IntentScope::ProcessData,
+    ))
+}
+
+async fn recovery_status_internal(
+    state: Arc<AppState>,
+    _request: ProcessDataRequest<EmptyRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<RecoveryStatusResponse>>, EnclaveError> {
+    let recovery = RECOVERY.read().await;
+
+    Ok(to_signed_response(
+        &state.eph_kp,
+        status_response(&recovery),
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+/// The wallet, provided recovery was set up for it.
+async fn configured_wallet(
+    wallet_guard: &Option<WalletState>,
+) -> Result<&WalletState, EnclaveError> {
+    let wallet = wallet_guard
+        .as_ref()
+        .ok_or_else(|| EnclaveError::NotFound("Wallet not initialized".to_string()))?;
+    let recovery = RECOVERY.read().await;
+    match &recovery.config {
+        Some(config) if config.wallet_address == wallet.address => Ok(wallet),
+        _ => Err(EnclaveError::NotFound(
+            "Recovery not configured for this wallet".to_string(),
+        )),
+    }
+}
+
+fn status_response(recovery: &RecoveryState) -> RecoveryStatusResponse {
+    RecoveryStatusResponse {
+        configured: recovery.config.is_some(),
+        guardians: recovery
+            .config
+            .as_ref()
+            .map(|config| config.guardians.clone())
+            .unwrap_or_default(),
+        threshold: recovery.config.as_ref().map_or(0, |config| config.threshold),
+        ready_at_ms: recovery.ready_at_ms,
+        log: recovery.log.clone(),
+        rejections_not_logged: recovery.rejections_not_logged,
+    }
+}
+
+fn hex_decode(value: &str) -> Result<Vec<u8>, EnclaveError> {
+    Hex::decode(value).map_err(|e| EnclaveError::InvalidRequest(format!("Invalid hex: {}", e)))
+}
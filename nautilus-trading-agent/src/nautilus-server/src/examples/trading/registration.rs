@@ -0,0 +1,232 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Optional self-registration on boot. Instead of an operator running
+// `register_enclave.sh`, the enclave attests its ephemeral key and submits
+// `enclave::register_enclave` itself, sent from the address of that key with
+// gas sponsored by the host (see `sponsor`). The resulting `Enclave<T>` object
+// is reported in `/health` and used by the trade relayer. Keys created by a
+// rotation are registered the same way, one `Enclave<T>` per key epoch.
+//
+// Enabled by setting `SELF_REGISTER=true` together with the sponsor settings.
+
+use super::chain::{shared_object_arg, sui_client};
+use super::{sponsor, ENCLAVE_CONFIG_ID, ENCLAVE_PACKAGE_ID, TRADING_PACKAGE_ID};
+use crate::{AppState, EnclaveError};
+use fastcrypto::ed25519::Ed25519KeyPair;
+use fastcrypto::traits::{KeyPair, ToFromBytes};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use sui_json_rpc_types::ObjectChange;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::crypto::SuiKeyPair;
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use std::collections::BTreeMap;
+use sui_types::transaction::ObjectArg;
+use sui_types::{Identifier, SUI_CLOCK_OBJECT_ID, SUI_CLOCK_OBJECT_SHARED_VERSION, SUI_FRAMEWORK_PACKAGE_ID};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const SELF_REGISTER_ENV: &str = "SELF_REGISTER";
+const REGISTRATION_GAS_BUDGET: u64 = 100_000_000;
+const MAX_REGISTRATION_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// Registration of each key epoch.
+    static ref REGISTRATIONS: Arc<RwLock<BTreeMap<u64, RegistrationStatus>>> = Arc::new(RwLock::new(BTreeMap::new()));
+}
+
+/// Outcome of the self-registration, reported in `/health`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RegistrationStatus {
+    /// "disabled", "pending", "registered", "failed" or "retired"
+    pub state: String,
+    pub key_epoch: u64,
+    pub enclave_object_id: Option<String>,
+    pub tx_digest: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Whether the enclave registers its own keys.
+pub fn is_enabled() -> bool {
+    std::env::var(SELF_REGISTER_ENV)
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// Spawn the registration task for the boot key if `SELF_REGISTER` is set.
+pub async fn start_self_registration(state: Arc<AppState>) -> Result<(), EnclaveError> {
+    let key = state.eph_kp.current();
+    if !is_enabled() {
+        set_status(key.epoch, "disabled", None).await;
+        return Ok(());
+    }
+    if !sponsor::is_configured() {
+        return Err(EnclaveError::Internal(format!(
+            "{} requires {} and {}",
+            SELF_REGISTER_ENV,
+            sponsor::SPONSOR_URL_ENV,
+            sponsor::SPONSOR_GAS_COIN_ENV
+        )));
+    }
+
+    set_status(key.epoch, "pending", None).await;
+    tokio::spawn(async move {
+        for attempt in 1..=MAX_REGISTRATION_ATTEMPTS {
+            match register_key(&state, key.epoch, &key.keypair).await {
+                Ok(_) => return,
+                Err(e) => {
+                    warn!("Enclave registration failed (attempt {}): {}", attempt, e);
+                    if attempt < MAX_REGISTRATION_ATTEMPTS {
+                        tokio::time::sleep(RETRY_DELAY * attempt).await;
+                    }
+                }
+            }
+        }
+        set_status(key.epoch, "failed", None).await;
+    });
+    Ok(())
+}
+
+/// Registration of the latest key epoch.
+pub async fn status() -> RegistrationStatus {
+    REGISTRATIONS
+        .read()
+        .await
+        .values()
+        .next_back()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// The `Enclave<T>` object registered for the key of `epoch`, if any.
+pub async fn registered_enclave_id(epoch: u64) -> Option<ObjectID> {
+    REGISTRATIONS
+        .read()
+        .await
+        .get(&epoch)
+        .and_then(|status| status.enclave_object_id.as_ref())
+        .and_then(|id| id.parse().ok())
+}
+
+/// Mark the registration of a destroyed key.
+pub async fn mark_retired(epoch: u64) {
+    if let Some(status) = REGISTRATIONS.write().await.get_mut(&epoch) {
+        status.state = "retired".to_string();
+    }
+}
+
+async fn set_status(epoch: u64, state: &str, error: Option<String>) {
+    let mut registrations = REGISTRATIONS.write().await;
+    let status = registrations.entry(epoch).or_default();
+    status.key_epoch = epoch;
+    status.state = state.to_string();
+    if error.is_some() {
+        status.error = error;
+    }
+}
+
+/// Attest `keypair` and submit `register_enclave` for it, recording the
+/// created `Enclave<T>` under `epoch`.
+pub async fn register_key(
+    state: &AppState,
+    epoch: u64,
+    keypair: &Ed25519KeyPair,
+) -> Result<ObjectID, EnclaveError> {
+    match register(state, keypair).await {
+        Ok((enclave_id, digest)) => {
+            info!("Registered enclave {} for key epoch {} in {}", enclave_id, epoch, digest);
+            REGISTRATIONS.write().await.insert(
+                epoch,
+                RegistrationStatus {
+                    state: "registered".to_string(),
+                    key_epoch: epoch,
+                    enclave_object_id: Some(enclave_id.to_string()),
+                    tx_digest: Some(digest),
+                    error: None,
+                },
+            );
+            Ok(enclave_id)
+        }
+        Err(e) => {
+            set_status(epoch, "pending", Some(e.to_string())).await;
+            Err(e)
+        }
+    }
+}
+
+async fn register(state: &AppState, keypair: &Ed25519KeyPair) -> Result<(ObjectID, String), EnclaveError> {
+    let document = state
+        .attestation
+        .attest(keypair.public().as_bytes().to_vec(), None, None)?;
+    let keypair = SuiKeyPair::Ed25519(keypair.copy());
+    let sender = SuiAddress::from(&keypair.public());
+
+    let client = sui_client().await?;
+    let config_id: ObjectID = ENCLAVE_CONFIG_ID
+        .parse()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid config ID: {}", e)))?;
+
+    let mut ptb = ProgrammableTransactionBuilder::new();
+    let arguments = vec![
+        ptb.pure(document),
+        ptb.obj(ObjectArg::SharedObject {
+            id: SUI_CLOCK_OBJECT_ID,
+            initial_shared_version: SUI_CLOCK_OBJECT_SHARED_VERSION,
+            mutable: false,
+        }),
+    ]
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+    let attestation = ptb.programmable_move_call(
+        SUI_FRAMEWORK_PACKAGE_ID,
+        Identifier::new("nitro_attestation").expect("valid identifier"),
+        Identifier::new("load_nitro_attestation").expect("valid identifier"),
+        vec![],
+        arguments,
+    );
+
+    let config_arg = ptb
+        .obj(shared_object_arg(&client, config_id, false).await?)
+        .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+    let witness = sui_types::parse_sui_type_tag(&format!(
+        "{}::trading_agent::TRADING_AGENT",
+        TRADING_PACKAGE_ID
+    ))
+    .map_err(|e| EnclaveError::Internal(format!("Invalid witness type: {}", e)))?;
+    ptb.programmable_move_call(
+        ENCLAVE_PACKAGE_ID
+            .parse()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid package ID: {}", e)))?,
+        Identifier::new("enclave").expect("valid identifier"),
+        Identifier::new("register_enclave").expect("valid identifier"),
+        vec![witness],
+        vec![config_arg, attestation],
+    );
+
+    let response =
+        sponsor::execute_sponsored(&client, &keypair, sender, ptb.finish(), REGISTRATION_GAS_BUDGET).await?;
+
+    let enclave_id = response
+        .object_changes
+        .iter()
+        .flatten()
+        .find_map(|change| match change {
+            ObjectChange::Created {
+                object_id,
+                object_type,
+                ..
+            } if object_type.module.as_str() == "enclave" && object_type.name.as_str() == "Enclave" => {
+                Some(*object_id)
+            }
+            _ => None,
+        })
+        .ok_or_else(|| {
+            EnclaveError::Internal(format!("No Enclave object created in {}", response.digest))
+        })?;
+    Ok((enclave_id, response.digest.to_string()))
+}
@@ -0,0 +1,190 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Optional background relayer that records every successful swap on-chain by
+// submitting the signed `TradeResponse` to `trading_agent::verify_trade`. This
+// updates the `AgentConfig` statistics, emits `TradeExecuted` and hands the
+// resulting `TradeRecord` to the trading wallet, which also pays for gas unless
+// the host sponsors it.
+//
+// Enabled by setting `AGENT_CONFIG_ID` (the shared `AgentConfig`). Signatures
+// are checked against the `Enclave<T>` self-registered for the signing key's
+// epoch, or the one in `ENCLAVE_OBJECT_ID` when the enclave was registered by hand.
+
+use super::chain::{gas_coin, shared_object_arg, sign_and_execute, sui_client};
+use super::{registration, sponsor, TradeResponse, TRADING_PACKAGE_ID, TRADING_WALLET};
+use crate::common::{IntentMessage, ProcessedDataResponse};
+use crate::EnclaveError;
+use fastcrypto::encoding::{Encoding, Hex};
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use std::time::Duration;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_types::transaction::{Command, TransactionData};
+use sui_types::Identifier;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const AGENT_CONFIG_ID_ENV: &str = "AGENT_CONFIG_ID";
+pub const ENCLAVE_OBJECT_ID_ENV: &str = "ENCLAVE_OBJECT_ID";
+const RELAY_GAS_BUDGET: u64 = 20_000_000;
+const MAX_RELAY_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref RELAY_QUEUE: Arc<RwLock<Option<UnboundedSender<RelayJob>>>> = Arc::new(RwLock::new(None));
+}
+
+/// A signed trade response waiting to be submitted.
+struct RelayJob {
+    response: IntentMessage<TradeResponse>,
+    signature: String,
+    key_epoch: u64,
+}
+
+struct RelayerConfig {
+    agent_config_id: ObjectID,
+    /// Used when the signing key was not self-registered
+    enclave_id: Option<ObjectID>,
+}
+
+/// Start the relayer task if it is configured through the environment.
+pub async fn start_relayer() -> Result<(), EnclaveError> {
+    let agent_config_id = match std::env::var(AGENT_CONFIG_ID_ENV) {
+        Ok(agent_config_id) => agent_config_id,
+        Err(_) => {
+            info!("Trade relayer disabled, set {} to enable it", AGENT_CONFIG_ID_ENV);
+            return Ok(());
+        }
+    };
+
+    let config = RelayerConfig {
+        agent_config_id: agent_config_id
+            .parse()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", AGENT_CONFIG_ID_ENV, e)))?,
+        enclave_id: std::env::var(ENCLAVE_OBJECT_ID_ENV)
+            .ok()
+            .map(|enclave_id| enclave_id.parse::<ObjectID>())
+            .transpose()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", ENCLAVE_OBJECT_ID_ENV, e)))?,
+    };
+
+    let (sender, receiver) = unbounded_channel();
+    *RELAY_QUEUE.write().await = Some(sender);
+    tokio::spawn(run_relayer(config, receiver));
+    info!("Trade relayer started");
+    Ok(())
+}
+
+/// Queue a signed trade response for on-chain recording. A no-op when the relayer is disabled.
+pub async fn submit(signed: &ProcessedDataResponse<IntentMessage<TradeResponse>>) {
+    if let Some(queue) = RELAY_QUEUE.read().await.as_ref() {
+        let job = RelayJob {
+            response: signed.response.clone(),
+            signature: signed.signature.clone(),
+            key_epoch: signed.key_epoch,
+        };
+        if queue.send(job).is_err() {
+            warn!("Trade relayer stopped, {} not recorded", signed.response.data.tx_digest);
+        }
+    }
+}
+
+async fn run_relayer(config: RelayerConfig, mut receiver: UnboundedReceiver<RelayJob>) {
+    while let Some(job) = receiver.recv().await {
+        let tx_digest = job.response.data.tx_digest.clone();
+        for attempt in 1..=MAX_RELAY_ATTEMPTS {
+            match relay_trade(&config, &job).await {
+                Ok(digest) => {
+                    info!("Recorded trade {} on-chain in {}", tx_digest, digest);
+                    break;
+                }
+                Err(e) if attempt < MAX_RELAY_ATTEMPTS => {
+                    warn!("Recording trade {} failed (attempt {}): {}", tx_digest, attempt, e);
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                }
+                Err(e) => warn!("Giving up on recording trade {}: {}", tx_digest, e),
+            }
+        }
+    }
+}
+
+async fn relay_trade(config: &RelayerConfig, job: &RelayJob) -> Result<String, EnclaveError> {
+    let (keypair, address) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.keypair.clone(), wallet.address.clone()),
+        None => return Err(EnclaveError::NotFound("Wallet not initialized".to_string())),
+    };
+    let sender = address
+        .parse::<SuiAddress>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid sender address: {}", e)))?;
+    let signature = Hex::decode(&job.signature)
+        .map_err(|e| EnclaveError::Internal(format!("Invalid signature: {}", e)))?;
+
+    let enclave_id = registration::registered_enclave_id(job.key_epoch)
+        .await
+        .or(config.enclave_id)
+        .ok_or_else(|| {
+            EnclaveError::Conflict(format!("Key epoch {} is not registered yet", job.key_epoch))
+        })?;
+
+    let client = sui_client().await?;
+    let mut ptb = ProgrammableTransactionBuilder::new();
+    let trade = &job.response.data;
+    let arguments = vec![
+        ptb.obj(shared_object_arg(&client, config.agent_config_id, true).await?),
+        ptb.obj(shared_object_arg(&client, enclave_id, false).await?),
+        ptb.pure(trade.tx_digest.clone()),
+        ptb.pure(trade.action.clone()),
+        ptb.pure(trade.amount),
+        ptb.pure(trade.amount_out),
+        ptb.pure(job.response.timestamp_ms),
+        ptb.pure(signature),
+    ]
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+
+    let witness = sui_types::parse_sui_type_tag(&format!(
+        "{}::trading_agent::TRADING_AGENT",
+        TRADING_PACKAGE_ID
+    ))
+    .map_err(|e| EnclaveError::Internal(format!("Invalid witness type: {}", e)))?;
+    let record = ptb.programmable_move_call(
+        TRADING_PACKAGE_ID
+            .parse()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid package ID: {}", e)))?,
+        Identifier::new("trading_agent").expect("valid identifier"),
+        Identifier::new("verify_trade").expect("valid identifier"),
+        vec![witness],
+        arguments,
+    );
+
+    // The TradeRecord has no drop ability, keep it in the trading wallet
+    let sender_arg = ptb
+        .pure(sender)
+        .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+    ptb.command(Command::TransferObjects(vec![record], sender_arg));
+
+    if sponsor::is_sponsored(&sender, RELAY_GAS_BUDGET).await {
+        let response = sponsor::execute_sponsored(&client, &keypair, sender, ptb.finish(), RELAY_GAS_BUDGET).await?;
+        return Ok(response.digest.to_string());
+    }
+
+    let gas_price = client
+        .read_api()
+        .get_reference_gas_price()
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
+    let tx_data = TransactionData::new_programmable(
+        sender,
+        vec![gas_coin(&client, sender, RELAY_GAS_BUDGET).await?],
+        ptb.finish(),
+        RELAY_GAS_BUDGET,
+        gas_price,
+    );
+
+    let response = sign_and_execute(&client, &keypair, tx_data).await?;
+    Ok(response.digest.to_string())
+}
@@ -0,0 +1,236 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Rotation of the enclave signing key, on demand through `/rotate_key` or
+// every `KEY_ROTATION_INTERVAL_SECS`. A rotation:
+//
+// 1. generates a new key and, with self-registration enabled, attests and
+//    registers it as a new `Enclave<T>` before it is used,
+// 2. signs a `KeyHandover` to the new key with the old one,
+// 3. keeps the old key for `KEY_GRACE_PERIOD_SECS`, then destroys it and
+//    deletes its `Enclave<T>` object.
+//
+// The agent binding names the signing key, so it is re-signed with the new one.
+//
+// The chain of handovers is served by `/key_handovers` and journaled.
+
+use super::chain::{shared_object_arg, sui_client};
+use super::journal::{self, JournalEvent};
+use super::{binding, get_current_timestamp, registration, sponsor, EmptyRequest, ENCLAVE_PACKAGE_ID, TRADING_PACKAGE_ID};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::enclave_keys::{EpochKey, KeyHandover};
+use crate::{AppState, EnclaveError};
+use fastcrypto::ed25519::Ed25519KeyPair;
+use fastcrypto::traits::KeyPair;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::crypto::SuiKeyPair;
+use sui_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use sui_types::Identifier;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+const KEY_ROTATION_INTERVAL_ENV: &str = "KEY_ROTATION_INTERVAL_SECS";
+const KEY_GRACE_PERIOD_ENV: &str = "KEY_GRACE_PERIOD_SECS";
+const DEFAULT_GRACE_PERIOD_SECS: u64 = 60 * 60;
+const RETIREMENT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const DESTROY_GAS_BUDGET: u64 = 20_000_000;
+
+lazy_static! {
+    static ref ROTATION_LOCK: Mutex<()> = Mutex::new(());
+    /// Keys in their grace period, kept to delete their `Enclave<T>` once retired.
+    static ref RETIRING_KEYS: Mutex<BTreeMap<u64, Arc<EpochKey>>> = Mutex::new(BTreeMap::new());
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateKeyRequest {
+    /// Must match the server's `API_KEY`
+    pub api_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyHandoversResponse {
+    pub key_epoch: u64,
+    pub handovers: Vec<ProcessedDataResponse<IntentMessage<KeyHandover>>>,
+}
+
+/// Start the retirement task and, if `KEY_ROTATION_INTERVAL_SECS` is set, timed rotation.
+pub async fn start_key_rotation(state: Arc<AppState>) -> Result<(), EnclaveError> {
+    let interval = std::env::var(KEY_ROTATION_INTERVAL_ENV)
+        .ok()
+        .map(|secs| secs.parse::<u64>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", KEY_ROTATION_INTERVAL_ENV, e)))?;
+    grace_period_ms()?;
+
+    tokio::spawn(retire_keys(state.clone()));
+    if let Some(interval) = interval {
+        info!("Rotating the enclave key every {} seconds", interval);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+                if let Err(e) = rotate(&state).await {
+                    warn!("Key rotation failed: {}", e);
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+pub async fn rotate_key_wrapper(
+    request: ProcessDataRequest<RotateKeyRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match rotate_key_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+pub async fn key_handovers_wrapper(
+    request: ProcessDataRequest<EmptyRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match key_handovers_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+async fn rotate_key_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<RotateKeyRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<KeyHandover>>, EnclaveError> {
+    if state.api_key.is_empty() || request.payload.api_key != state.api_key {
+        return Err(EnclaveError::Unauthorized("Invalid API key".to_string()));
+    }
+    rotate(&state).await
+}
+
+async fn key_handovers_internal(
+    state: Arc<AppState>,
+    _request: ProcessDataRequest<EmptyRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<KeyHandoversResponse>>, EnclaveError> {
+    let response = KeyHandoversResponse {
+        key_epoch: state.eph_kp.epoch(),
+        handovers: state.eph_kp.handovers(),
+    };
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+/// Replace the enclave key, registering the new one first when self-registration is enabled.
+pub async fn rotate(state: &AppState) -> Result<ProcessedDataResponse<IntentMessage<KeyHandover>>, EnclaveError> {
+    let _guard = ROTATION_LOCK.lock().await;
+    let grace_period_ms = grace_period_ms()?;
+    let old = state.eph_kp.current();
+    let next_epoch = old.epoch + 1;
+    let next = Ed25519KeyPair::generate(&mut rand::thread_rng());
+
+    let enclave_id = if registration::is_enabled() {
+        Some(registration::register_key(state, next_epoch, &next).await?.to_string())
+    } else {
+        warn!("Self-registration disabled, register key epoch {} with register_enclave.sh", next_epoch);
+        None
+    };
+
+    let handover = state
+        .eph_kp
+        .rotate(next, enclave_id, grace_period_ms, get_current_timestamp());
+    RETIRING_KEYS.lock().await.insert(old.epoch, old);
+    info!("Rotated enclave key to epoch {}", next_epoch);
+
+    // Signed by the new key, this lets a reloaded journal trace entries of the
+    // old key to a registered one after its `Enclave<T>` is deleted
+    journal::record(
+        &state.eph_kp,
+        JournalEvent {
+            kind: "key_handover".to_string(),
+            wallet_address: String::new(),
+            request: serde_json::to_string(&handover).unwrap_or_default(),
+            quote: None,
+            tx_digest: String::new(),
+            amount_in: 0,
+            amount_out: None,
+            recipient: None,
+        },
+        handover.response.timestamp_ms,
+    )
+    .await;
+    if let Err(e) = binding::resign(state).await {
+        warn!("Failed to re-sign the agent binding: {}", e);
+    }
+    Ok(handover)
+}
+
+fn grace_period_ms() -> Result<u64, EnclaveError> {
+    let secs = std::env::var(KEY_GRACE_PERIOD_ENV)
+        .ok()
+        .map(|secs| secs.parse::<u64>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", KEY_GRACE_PERIOD_ENV, e)))?
+        .unwrap_or(DEFAULT_GRACE_PERIOD_SECS);
+    secs.checked_mul(1000)
+        .ok_or_else(|| EnclaveError::Internal(format!("{} is too large", KEY_GRACE_PERIOD_ENV)))
+}
+
+/// Destroy keys past their grace period along with their `Enclave<T>` objects.
+async fn retire_keys(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(RETIREMENT_CHECK_INTERVAL).await;
+        for epoch in state.eph_kp.retire_expired(get_current_timestamp()) {
+            let key = RETIRING_KEYS.lock().await.remove(&epoch);
+            if let (Some(key), Some(enclave_id)) = (key, registration::registered_enclave_id(epoch).await) {
+                match destroy_enclave(&key, enclave_id).await {
+                    Ok(digest) => info!("Deleted enclave {} of key epoch {} in {}", enclave_id, epoch, digest),
+                    Err(e) => warn!("Failed to delete enclave {} of key epoch {}: {}", enclave_id, epoch, e),
+                }
+            }
+            registration::mark_retired(epoch).await;
+            info!("Destroyed enclave key of epoch {}", epoch);
+        }
+    }
+}
+
+/// Delete the `Enclave<T>` registered by `key`, which is its owner.
+async fn destroy_enclave(key: &EpochKey, enclave_id: ObjectID) -> Result<String, EnclaveError> {
+    if !sponsor::is_configured() {
+        return Err(EnclaveError::Internal("No gas sponsor configured".to_string()));
+    }
+    let keypair = SuiKeyPair::Ed25519(key.keypair.copy());
+    let sender = SuiAddress::from(&keypair.public());
+
+    let client = sui_client().await?;
+    let mut ptb = ProgrammableTransactionBuilder::new();
+    let enclave_arg = ptb
+        .obj(shared_object_arg(&client, enclave_id, true).await?)
+        .map_err(|e| EnclaveError::Internal(format!("Failed to build arguments: {}", e)))?;
+    let witness = sui_types::parse_sui_type_tag(&format!(
+        "{}::trading_agent::TRADING_AGENT",
+        TRADING_PACKAGE_ID
+    ))
+    .map_err(|e| EnclaveError::Internal(format!("Invalid witness type: {}", e)))?;
+    ptb.programmable_move_call(
+        ENCLAVE_PACKAGE_ID
+            .parse()
+            .map_err(|e| EnclaveError::Internal(format!("Invalid package ID: {}", e)))?,
+        Identifier::new("enclave").expect("valid identifier"),
+        Identifier::new("deploy_old_enclave_by_owner").expect("valid identifier"),
+        vec![witness],
+        vec![enclave_arg],
+    );
+
+    let response = sponsor::execute_sponsored(&client, &keypair, sender, ptb.finish(), DESTROY_GAS_BUDGET).await?;
+    Ok(response.digest.to_string())
+}
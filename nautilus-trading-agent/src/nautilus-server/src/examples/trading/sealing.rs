@@ -0,0 +1,83 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// The key the master seed is sealed under. It is held by a key release service
+// at `SEALING_KEY_URL` (e.g. a KMS policy bound to the enclave image) and only
+// ever reaches the enclave encrypted, so the host sees neither the key nor the
+// seed. On boot the enclave attests to a fresh encryption key and the service,
+// after checking the PCRs, returns the sealing key encrypted to it:
+//
+//   POST {SEALING_KEY_URL} {"attestation": "<hex attestation document>"}
+//   -> {"ephemeral_key": ..., "nonce": ..., "ciphertext": ...}
+//
+// The response is an `ecies::EncryptedPayload` to the ristretto255 key in the
+// attestation's user_data, with `SEALING_KEY_AAD` as associated data.
+
+use super::ecies::{EncryptedPayload, EncryptionKeyPair};
+use crate::{AppState, EnclaveError};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::ToFromBytes;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::info;
+
+const SEALING_KEY_URL_ENV: &str = "SEALING_KEY_URL";
+const SEALING_KEY_AAD: &[u8] = b"nautilus-sealing-key-v1";
+const SEALING_KEY_LENGTH: usize = 32;
+
+lazy_static! {
+    static ref SEALING_KEY: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+}
+
+#[derive(Serialize)]
+struct ReleaseRequest {
+    attestation: String,
+}
+
+/// Obtain the sealing key from the release service. None if `SEALING_KEY_URL`
+/// is not set, in which case the seed cannot be sealed.
+pub async fn release(state: &AppState) -> Result<Option<Vec<u8>>, EnclaveError> {
+    let Ok(url) = std::env::var(SEALING_KEY_URL_ENV) else {
+        return Ok(None);
+    };
+
+    let key = EncryptionKeyPair::generate();
+    let attestation = state.attestation.attest(
+        state.eph_kp.public().as_bytes().to_vec(),
+        Some(key.public_key().to_vec()),
+        None,
+    )?;
+    let payload: EncryptedPayload = reqwest::Client::new()
+        .post(&url)
+        .json(&ReleaseRequest {
+            attestation: Hex::encode(attestation),
+        })
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| EnclaveError::Rpc(format!("Sealing key request failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Invalid sealing key response: {}", e)))?;
+
+    let sealing_key = key.decrypt(&payload, SEALING_KEY_AAD)?;
+    if sealing_key.len() != SEALING_KEY_LENGTH {
+        return Err(EnclaveError::Internal(format!(
+            "Sealing key must be {} bytes",
+            SEALING_KEY_LENGTH
+        )));
+    }
+    info!("Sealing key released by {}", url);
+    *SEALING_KEY.write().await = Some(sealing_key.clone());
+    Ok(Some(sealing_key))
+}
+
+/// The key released on boot.
+pub async fn sealing_key() -> Result<Vec<u8>, EnclaveError> {
+    SEALING_KEY
+        .read()
+        .await
+        .clone()
+        .ok_or_else(|| EnclaveError::NotFound(format!("No sealing key, set {} to seal the master seed", SEALING_KEY_URL_ENV)))
+}
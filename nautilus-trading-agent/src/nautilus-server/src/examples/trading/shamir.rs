@@ -0,0 +1,120 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Shamir secret sharing over GF(256), applied byte by byte. Share indices
+// start at 1, index 0 would be the secret itself.
+
+use crate::EnclaveError;
+use rand::RngCore;
+
+/// One share of a split secret.
+#[derive(Debug, Clone)]
+pub struct Share {
+    pub index: u8,
+    pub data: Vec<u8>,
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which recover it.
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>, EnclaveError> {
+    if threshold < 2 || threshold > count {
+        return Err(EnclaveError::InvalidRequest(format!(
+            "Invalid threshold {} for {} shares",
+            threshold, count
+        )));
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share {
+            index,
+            data: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    let mut coefficients = vec![0u8; threshold as usize];
+    for &byte in secret {
+        coefficients[0] = byte;
+        rng.fill_bytes(&mut coefficients[1..]);
+        for share in shares.iter_mut() {
+            share.data.push(evaluate(&coefficients, share.index));
+        }
+    }
+    Ok(shares)
+}
+
+/// Recover the secret from at least `threshold` distinct shares.
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>, EnclaveError> {
+    let first = shares
+        .first()
+        .ok_or_else(|| EnclaveError::InvalidRequest("No shares given".to_string()))?;
+    for (i, share) in shares.iter().enumerate() {
+        if share.index == 0 || share.data.len() != first.data.len() {
+            return Err(EnclaveError::InvalidRequest("Malformed share".to_string()));
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(EnclaveError::InvalidRequest("Duplicate share".to_string()));
+        }
+    }
+
+    // Lagrange interpolation at x = 0
+    let mut secret = vec![0u8; first.data.len()];
+    for share in shares {
+        let mut basis = 1u8;
+        for other in shares.iter().filter(|other| other.index != share.index) {
+            basis = mul(basis, div(other.index, other.index ^ share.index));
+        }
+        for (out, &y) in secret.iter_mut().zip(share.data.iter()) {
+            *out ^= mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0u8, |acc, &coefficient| mul(acc, x) ^ coefficient)
+}
+
+/// Multiplication modulo the AES polynomial x^8 + x^4 + x^3 + x + 1.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// a / b, using b^254 as the inverse of b.
+fn div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1u8;
+    for _ in 0..254 {
+        inverse = mul(inverse, b);
+    }
+    mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_combine() {
+        let secret = b"32 byte wallet private key bytes".to_vec();
+        let shares = split(&secret, 3, 5).unwrap();
+
+        assert_eq!(combine(&shares[..3]).unwrap(), secret);
+        assert_eq!(combine(&[shares[4].clone(), shares[1].clone(), shares[3].clone()]).unwrap(), secret);
+        assert_ne!(combine(&shares[..2]).unwrap(), secret);
+        assert!(split(&secret, 4, 3).is_err());
+    }
+}
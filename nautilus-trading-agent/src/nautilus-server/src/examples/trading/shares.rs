@@ -0,0 +1,539 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Share ledger of a pooled agent wallet. Subscribers deposit through
+// `subscription_manager::deposit_to_agent` into the same wallet, so after
+// trades their claim is tracked in shares rather than deposited amounts:
+//
+// - the NAV is the wallet's SUI plus its USDC valued at the pool's spot price,
+// - a deposit mints `amount * total_shares / nav_before` shares (1:1 for the
+//   first deposit),
+// - a withdrawal burns the shares worth the withdrawn amount at the current NAV.
+//
+// Shares are priced on the NAV less `unowned`, the value already in the wallet
+// when the first shares were minted (e.g. the creator's own funds), so the
+// first depositor does not acquire it. Withdrawals reserve their shares and
+// amount before the transaction and release the ledger while it executes.
+//
+// Deposits are read from `UserDeposited` events by the event listener. All
+// deposits not yet minted, across every page of events, are priced together at
+// the NAV less all of them, which ignores trades in between.
+
+use super::chain::{read_move_object, sui_client};
+use super::events;
+use super::{fetch_balances, get_current_timestamp, POOL_ID, TRADING_WALLET};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::event::EventID;
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// Fixed point scale of share prices.
+pub const PRICE_SCALE: u64 = 1_000_000_000;
+
+lazy_static! {
+    /// Ledger of each agent the wallet holds deposits for.
+    pub static ref LEDGERS: Mutex<BTreeMap<ObjectID, ShareLedger>> = Mutex::new(BTreeMap::new());
+}
+
+/// BCS layout of `dex::Pool`; balances encode as their value.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct PoolObject {
+    id: ObjectID,
+    sui_reserve: u64,
+    usdc_reserve: u64,
+    fee_rate: u64,
+    total_lp_supply: u64,
+}
+
+/// Value of the agent wallet in MIST.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Nav {
+    pub sui_balance: u64,
+    pub usdc_balance: u64,
+    pub sui_reserve: u64,
+    pub usdc_reserve: u64,
+    /// SUI balance plus the USDC balance at the pool price
+    pub value: u64,
+}
+
+impl Nav {
+    pub fn new(sui_balance: u64, usdc_balance: u64, sui_reserve: u64, usdc_reserve: u64) -> Self {
+        let usdc_value = if usdc_reserve == 0 {
+            0
+        } else {
+            (usdc_balance as u128 * sui_reserve as u128 / usdc_reserve as u128) as u64
+        };
+        Self {
+            sui_balance,
+            usdc_balance,
+            sui_reserve,
+            usdc_reserve,
+            value: sui_balance.saturating_add(usdc_value),
+        }
+    }
+}
+
+/// A subscriber's shares and flows, in MIST.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub shares: u64,
+    pub deposited: u64,
+    pub withdrawn: u64,
+    /// Share price up to which performance fees were charged, see `share_price`
+    #[serde(default)]
+    pub high_water_mark: u64,
+    /// Performance fees charged so far
+    #[serde(default)]
+    pub fees_paid: u64,
+    /// When the subscription was found expired, the shares are no longer traded
+    #[serde(default)]
+    pub expired_at_ms: Option<u64>,
+}
+
+/// A deposit rejected by the agent's deposit limits, owed back to the depositor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Refund {
+    /// The rejected `UserDeposited` event
+    pub deposit: EventID,
+    pub user: SuiAddress,
+    pub amount: u64,
+    /// When the refund was submitted, saved before submitting it. A refund
+    /// whose transaction may have executed is never submitted again
+    #[serde(default)]
+    pub submitted_ms: Option<u64>,
+    /// Digest of the submitted transaction, to confirm it on later syncs
+    #[serde(default)]
+    pub tx_digest: Option<String>,
+}
+
+/// A `UserDeposited` event for the ledger's agent.
+#[derive(Debug, Clone)]
+pub struct Deposit {
+    pub id: EventID,
+    pub user: SuiAddress,
+    pub amount: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ShareLedger {
+    pub total_shares: u64,
+    pub positions: BTreeMap<SuiAddress, Position>,
+    /// Deposits already minted, by transaction digest and event sequence
+    applied: BTreeSet<(String, u64)>,
+    /// Shares taken as performance fees, held for the agent creator
+    #[serde(default)]
+    pub fee_shares: u64,
+    /// Number of fee settlements and the time of the last one
+    #[serde(default)]
+    pub settlements: u64,
+    #[serde(default)]
+    pub last_settlement_ms: u64,
+    /// Deposits rejected by the agent's deposit limits, until refunded
+    #[serde(default)]
+    pub refunds: Vec<Refund>,
+    /// Value in the wallet when the first shares were minted, owned by no share
+    #[serde(default)]
+    pub unowned: u64,
+    /// Reserved withdrawals still being executed, in the wallet but no longer owned by shares
+    #[serde(skip)]
+    pending_withdrawals: u64,
+}
+
+impl ShareLedger {
+    /// Mint shares for a deposit of `amount` into a wallet worth `nav_before` without it.
+    pub fn mint(&mut self, user: SuiAddress, amount: u64, nav_before: u64) -> u64 {
+        if self.total_shares == 0 {
+            self.unowned = nav_before.saturating_sub(self.pending_withdrawals);
+        }
+        let owned_before = self.owned(nav_before);
+        let shares = if self.total_shares == 0 || owned_before == 0 {
+            amount
+        } else {
+            (amount as u128 * self.total_shares as u128 / owned_before as u128) as u64
+        };
+        self.total_shares += shares;
+        let position = self.positions.entry(user).or_default();
+        // The high-water mark moves to the average entry price, so gains made
+        // before a deposit are not charged to it
+        if shares > 0 {
+            let entry_price = amount as u128 * PRICE_SCALE as u128 / shares as u128;
+            let held = position.shares as u128;
+            position.high_water_mark = ((position.high_water_mark as u128 * held + entry_price * shares as u128)
+                / (held + shares as u128)) as u64;
+        }
+        position.shares += shares;
+        position.deposited += amount;
+        shares
+    }
+
+    /// Part of the wallet's `nav` owned by the shares.
+    fn owned(&self, nav: u64) -> u64 {
+        nav.saturating_sub(self.unowned.saturating_add(self.pending_withdrawals))
+    }
+
+    /// Value of `PRICE_SCALE` shares at `nav`, `PRICE_SCALE` (1 MIST per share) before any deposit.
+    pub fn share_price(&self, nav: u64) -> u64 {
+        if self.total_shares == 0 {
+            return PRICE_SCALE;
+        }
+        (self.owned(nav) as u128 * PRICE_SCALE as u128 / self.total_shares as u128) as u64
+    }
+
+    /// Shares worth `amount` at `nav`, rounded up so the pool never loses.
+    pub fn shares_for(&self, amount: u64, nav: u64) -> Result<u64, EnclaveError> {
+        let owned = self.owned(nav);
+        if owned == 0 {
+            return Err(EnclaveError::InsufficientBalance("Agent wallet is empty".to_string()));
+        }
+        let total = self.total_shares as u128;
+        Ok(((amount as u128 * total).div_ceil(owned as u128)) as u64)
+    }
+
+    /// Shares of `user` a withdrawal of `amount` at `nav` would burn, failing if they are not held.
+    pub fn check_burn(&self, user: &SuiAddress, amount: u64, nav: u64) -> Result<u64, EnclaveError> {
+        let shares = self.shares_for(amount, nav)?;
+        let held = self.positions.get(user).map_or(0, |position| position.shares);
+        if held < shares {
+            return Err(EnclaveError::InsufficientBalance(format!(
+                "{} does not hold shares worth {}",
+                user, amount
+            )));
+        }
+        Ok(shares)
+    }
+
+    /// Burn the shares of `user` worth `amount` at `nav` and return their number.
+    pub fn burn(&mut self, user: &SuiAddress, amount: u64, nav: u64) -> Result<u64, EnclaveError> {
+        let shares = self.check_burn(user, amount, nav)?;
+        let position = self.positions.entry(*user).or_default();
+        position.shares -= shares;
+        position.withdrawn += amount;
+        self.total_shares -= shares;
+        Ok(shares)
+    }
+
+    /// Burn the shares of `user` worth `amount` at `nav` for a withdrawal about
+    /// to be executed, keeping `amount` out of the shares' NAV until it is
+    /// finished or cancelled.
+    pub fn reserve_withdrawal(&mut self, user: &SuiAddress, amount: u64, nav: u64) -> Result<u64, EnclaveError> {
+        let shares = self.burn(user, amount, nav)?;
+        self.pending_withdrawals += amount;
+        Ok(shares)
+    }
+
+    /// The reserved withdrawal of `amount` left the wallet.
+    pub fn finish_withdrawal(&mut self, amount: u64) {
+        self.pending_withdrawals = self.pending_withdrawals.saturating_sub(amount);
+    }
+
+    /// The reserved withdrawal of `amount` failed, give `user` its `shares` back.
+    pub fn cancel_withdrawal(&mut self, user: &SuiAddress, amount: u64, shares: u64) {
+        self.finish_withdrawal(amount);
+        let position = self.positions.entry(*user).or_default();
+        position.shares += shares;
+        position.withdrawn = position.withdrawn.saturating_sub(amount);
+        self.total_shares += shares;
+    }
+
+    /// Burn the fee shares for a payout about to be executed and return them
+    /// with their value at `nav`, kept out of the shares' NAV like a withdrawal.
+    pub fn reserve_fees(&mut self, nav: u64) -> (u64, u64) {
+        let shares = self.fee_shares;
+        let value = self.value_of(shares, nav);
+        self.fee_shares = 0;
+        self.total_shares -= shares;
+        self.pending_withdrawals += value;
+        (shares, value)
+    }
+
+    /// The reserved fee payout of `value` failed, hold its `shares` again.
+    pub fn cancel_fees(&mut self, shares: u64, value: u64) {
+        self.finish_withdrawal(value);
+        self.fee_shares += shares;
+        self.total_shares += shares;
+    }
+
+    /// Close the position of `user` for a payout about to be executed and
+    /// return the shares burned with their value at `nav`, kept out of the
+    /// shares' NAV like a withdrawal.
+    pub fn reserve_close(&mut self, user: &SuiAddress, nav: u64) -> (u64, u64) {
+        let shares = self.positions.get(user).map_or(0, |position| position.shares);
+        let value = self.value_of(shares, nav);
+        self.close(user, value);
+        self.pending_withdrawals += value;
+        (shares, value)
+    }
+
+    /// Close the position of `user`, paid out `value`, and return the shares burned.
+    pub fn close(&mut self, user: &SuiAddress, value: u64) -> u64 {
+        let position = self.positions.entry(*user).or_default();
+        let shares = position.shares;
+        position.shares = 0;
+        position.withdrawn += value;
+        self.total_shares -= shares;
+        shares
+    }
+
+    /// Shares still traded, i.e. not held by expired subscribers.
+    pub fn active_shares(&self) -> u64 {
+        let expired: u64 = self
+            .positions
+            .values()
+            .filter(|position| position.expired_at_ms.is_some())
+            .map(|position| position.shares)
+            .sum();
+        self.total_shares - expired
+    }
+
+    /// Value of `shares` at `nav`.
+    pub fn value_of(&self, shares: u64, nav: u64) -> u64 {
+        if self.total_shares == 0 {
+            return 0;
+        }
+        (shares as u128 * self.owned(nav) as u128 / self.total_shares as u128) as u64
+    }
+
+    /// Mint shares for new deposits, all priced at the NAV before any of them.
+    /// `nav` must include every deposit not yet minted, and nothing else.
+    /// Deposits outside the agent's `(min_deposit, max_deposit)` limits mint
+    /// nothing and are owed back to the depositor in `refunds`, one per event.
+    pub fn apply_deposits(&mut self, deposits: Vec<Deposit>, nav: u64, limits: Option<(u64, u64)>) -> usize {
+        let new: Vec<Deposit> = deposits
+            .into_iter()
+            .filter(|deposit| !self.applied.contains(&event_key(&deposit.id)))
+            .collect();
+        let total: u64 = new.iter().map(|deposit| deposit.amount).sum();
+        // Refunds are still in the wallet until their transaction is confirmed
+        let owed: u64 = self.refunds.iter().map(|refund| refund.amount).sum();
+        let mut nav_before = nav.saturating_sub(total + owed);
+        for deposit in &new {
+            self.applied.insert(event_key(&deposit.id));
+            if let Some((min_deposit, max_deposit)) = limits {
+                let held = self
+                    .positions
+                    .get(&deposit.user)
+                    .map_or(0, |position| position.deposited.saturating_sub(position.withdrawn));
+                if deposit.amount < min_deposit || (max_deposit > 0 && held + deposit.amount > max_deposit) {
+                    info!(
+                        "Deposit of {} by {} is outside the limits {}..{}, refunding it",
+                        deposit.amount, deposit.user, min_deposit, max_deposit
+                    );
+                    self.refunds.push(Refund {
+                        deposit: deposit.id.clone(),
+                        user: deposit.user,
+                        amount: deposit.amount,
+                        submitted_ms: None,
+                        tx_digest: None,
+                    });
+                    continue;
+                }
+            }
+            let shares = self.mint(deposit.user, deposit.amount, nav_before);
+            nav_before += deposit.amount;
+            info!("Minted {} shares for a deposit of {} by {}", shares, deposit.amount, deposit.user);
+        }
+        new.len()
+    }
+}
+
+fn event_key(id: &EventID) -> (String, u64) {
+    (id.tx_digest.to_string(), id.event_seq)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PositionsRequest {
+    /// Agent to report, defaults to the agent the wallet is bound to
+    pub agent_id: Option<String>,
+    /// Only report this subscriber
+    pub subscriber: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PositionView {
+    pub subscriber: String,
+    pub shares: u64,
+    /// Value of the shares at the current NAV
+    pub value: u64,
+    pub deposited: u64,
+    pub withdrawn: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PositionsResponse {
+    pub agent_id: String,
+    pub wallet_address: String,
+    pub nav: Nav,
+    pub total_shares: u64,
+    pub positions: Vec<PositionView>,
+}
+
+/// NAV of `wallet_address` at the current pool price.
+pub async fn nav(client: &SuiClient, wallet_address: &str) -> Result<Nav, EnclaveError> {
+    let (sui_balance, usdc_balance) = fetch_balances(wallet_address).await?;
+    let pool_id = POOL_ID
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid pool ID: {}", e)))?;
+    let (_, pool) = read_move_object::<PoolObject>(client, pool_id).await?;
+    Ok(Nav::new(sui_balance, usdc_balance, pool.sui_reserve, pool.usdc_reserve))
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn positions_wrapper(
+    request: ProcessDataRequest<PositionsRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match positions_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+async fn positions_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<PositionsRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<PositionsResponse>>, EnclaveError> {
+    let (wallet_address, wallet_agent) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.address.clone(), wallet.agent_id.clone()),
+        None => return Err(EnclaveError::NotFound("Wallet not initialized".to_string())),
+    };
+    let agent_id = request
+        .payload
+        .agent_id
+        .or(wallet_agent)
+        .ok_or_else(|| EnclaveError::InvalidRequest("No agent_id given and the wallet is not bound to an agent".to_string()))?
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid agent ID: {}", e)))?;
+    let subscriber = request
+        .payload
+        .subscriber
+        .map(|subscriber| subscriber.parse::<SuiAddress>())
+        .transpose()
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Invalid subscriber address: {}", e)))?;
+
+    events::sync().await?;
+    let client = sui_client().await?;
+    let nav = nav(&client, &wallet_address).await?;
+    let mut ledgers = LEDGERS.lock().await;
+    let ledger = ledgers.entry(agent_id).or_default();
+
+    let positions = ledger
+        .positions
+        .iter()
+        .filter(|(address, _)| subscriber.as_ref().map_or(true, |subscriber| subscriber == *address))
+        .map(|(address, position)| PositionView {
+            subscriber: address.to_string(),
+            shares: position.shares,
+            value: ledger.value_of(position.shares, nav.value),
+            deposited: position.deposited,
+            withdrawn: position.withdrawn,
+        })
+        .collect();
+
+    let response = PositionsResponse {
+        agent_id: agent_id.to_string(),
+        wallet_address,
+        total_shares: ledger.total_shares,
+        nav,
+        positions,
+    };
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mint_and_burn_at_nav() {
+        let alice = SuiAddress::random_for_testing_only();
+        let bob = SuiAddress::random_for_testing_only();
+        let mut ledger = ShareLedger::default();
+
+        assert_eq!(ledger.mint(alice, 1_000, 0), 1_000);
+        // The wallet doubled in value before bob deposits
+        assert_eq!(ledger.mint(bob, 1_000, 2_000), 500);
+        assert_eq!(ledger.total_shares, 1_500);
+        assert_eq!(ledger.value_of(1_000, 3_000), 2_000);
+
+        assert_eq!(ledger.burn(&alice, 1_000, 3_000).unwrap(), 500);
+        assert_eq!(ledger.positions[&alice].shares, 500);
+        assert!(ledger.burn(&bob, 1_001, 2_000).is_err());
+
+        // 20 USDC at 10 SUI per 20 USDC
+        let nav = Nav::new(1_000, 20, 10, 20);
+        assert_eq!(nav.value, 1_010);
+    }
+
+    #[test]
+    fn test_unowned_balance_and_reserved_withdrawals() {
+        let alice = SuiAddress::random_for_testing_only();
+        let bob = SuiAddress::random_for_testing_only();
+        let mut ledger = ShareLedger::default();
+
+        // 5_000 was in the wallet before the first deposit and is not alice's
+        assert_eq!(ledger.mint(alice, 1_000, 5_000), 1_000);
+        assert_eq!(ledger.unowned, 5_000);
+        assert_eq!(ledger.value_of(1_000, 6_000), 1_000);
+        assert_eq!(ledger.mint(bob, 1_000, 6_000), 1_000);
+
+        // While alice's withdrawal executes, bob's shares keep their value
+        assert_eq!(ledger.reserve_withdrawal(&alice, 500, 7_000).unwrap(), 500);
+        assert_eq!(ledger.value_of(1_000, 7_000), 1_000);
+        ledger.cancel_withdrawal(&alice, 500, 500);
+        assert_eq!(ledger.positions[&alice].shares, 1_000);
+        assert_eq!(ledger.total_shares, 2_000);
+
+        ledger.reserve_withdrawal(&alice, 500, 7_000).unwrap();
+        ledger.finish_withdrawal(500);
+        assert_eq!(ledger.value_of(1_000, 6_500), 1_000);
+
+        // A failed fee payout holds the fee shares again
+        ledger.fee_shares = 100;
+        ledger.total_shares += 100;
+        assert_eq!(ledger.reserve_fees(6_600), (100, 100));
+        assert_eq!(ledger.fee_shares, 0);
+        assert_eq!(ledger.value_of(1_000, 6_600), 1_000);
+        ledger.cancel_fees(100, 100);
+        assert_eq!(ledger.fee_shares, 100);
+        assert_eq!(ledger.total_shares, 1_600);
+    }
+
+    #[test]
+    fn test_deposit_limits() {
+        let alice = SuiAddress::random_for_testing_only();
+        let deposit = |amount, event_seq| Deposit {
+            id: EventID {
+                tx_digest: sui_types::digests::TransactionDigest::random(),
+                event_seq,
+            },
+            user: alice,
+            amount,
+        };
+        let mut ledger = ShareLedger::default();
+
+        // Below the minimum, then within the limits, then above the maximum in total
+        ledger.apply_deposits(vec![deposit(10, 0), deposit(500, 1)], 510, Some((100, 1_000)));
+        ledger.apply_deposits(vec![deposit(600, 2)], 1_110, Some((100, 1_000)));
+        assert_eq!(ledger.positions[&alice].deposited, 500);
+        assert_eq!(ledger.total_shares, 500);
+        assert_eq!(ledger.refunds.len(), 2);
+        assert!(ledger.refunds.iter().all(|refund| refund.user == alice));
+        assert_eq!(ledger.refunds.iter().map(|refund| refund.amount).sum::<u64>(), 610);
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// NAV snapshots of the trading wallet, kept in a ring buffer of the most recent
+// `NAV_SNAPSHOT_CAPACITY` (default 10000). A snapshot values the wallet at the
+// pool price (see `shares::nav`) and records the share price of the wallet's
+// agent, which is unaffected by deposits and withdrawals. Snapshots are taken
+// every `NAV_SNAPSHOT_INTERVAL_SECS` (default 60) and after every trade, and
+// served by `/equity_curve`.
+//
+// With `NAV_SNAPSHOT_PATH` set, the buffer is saved there after every snapshot
+// and restored on boot; otherwise the history starts over on every restart.
+
+use super::chain::sui_client;
+use super::shares::{self, Nav, LEDGERS};
+use super::{get_current_timestamp, TRADING_WALLET};
+use crate::common::{
+    to_signed_response, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::{AppState, EnclaveError};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use sui_types::base_types::ObjectID;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const NAV_SNAPSHOT_INTERVAL_ENV: &str = "NAV_SNAPSHOT_INTERVAL_SECS";
+const NAV_SNAPSHOT_CAPACITY_ENV: &str = "NAV_SNAPSHOT_CAPACITY";
+const NAV_SNAPSHOT_PATH_ENV: &str = "NAV_SNAPSHOT_PATH";
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+const DEFAULT_CAPACITY: usize = 10_000;
+const MAX_POINTS: usize = 1_000;
+
+lazy_static! {
+    static ref SNAPSHOTS: RwLock<VecDeque<NavSnapshot>> = RwLock::new(VecDeque::new());
+}
+static CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_CAPACITY);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NavSnapshot {
+    pub timestamp_ms: u64,
+    pub nav: Nav,
+    pub total_shares: u64,
+    /// Value of `PRICE_SCALE` shares, None while the agent has no shares
+    pub share_price: Option<u64>,
+}
+
+impl NavSnapshot {
+    /// Value of the wallet per unit, the share price if there are shares,
+    /// otherwise the NAV itself.
+    pub fn unit_value(&self) -> u64 {
+        self.share_price.unwrap_or(self.nav.value)
+    }
+}
+
+/// Start taking snapshots on a schedule.
+pub async fn start_nav_snapshots() -> Result<(), EnclaveError> {
+    let interval = env_number::<u64>(NAV_SNAPSHOT_INTERVAL_ENV)?.unwrap_or(DEFAULT_INTERVAL_SECS);
+    let capacity = env_number::<usize>(NAV_SNAPSHOT_CAPACITY_ENV)?.unwrap_or(DEFAULT_CAPACITY);
+    if capacity == 0 {
+        return Err(EnclaveError::Internal(format!("{} must be positive", NAV_SNAPSHOT_CAPACITY_ENV)));
+    }
+    CAPACITY.store(capacity, Ordering::Relaxed);
+
+    if let Ok(path) = std::env::var(NAV_SNAPSHOT_PATH_ENV) {
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                let mut saved: VecDeque<NavSnapshot> = serde_json::from_slice(&bytes)
+                    .map_err(|e| EnclaveError::Internal(format!("Invalid NAV snapshots {}: {}", path, e)))?;
+                while saved.len() > capacity {
+                    saved.pop_front();
+                }
+                info!("Restored {} NAV snapshots from {}", saved.len(), path);
+                *SNAPSHOTS.write().await = saved;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(EnclaveError::Internal(format!("Failed to read {}: {}", path, e))),
+        }
+    } else {
+        info!("{} not set, NAV snapshots are lost on restart", NAV_SNAPSHOT_PATH_ENV);
+    }
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = take().await {
+                warn!("Failed to snapshot the NAV: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+    Ok(())
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Result<Option<T>, EnclaveError>
+where
+    T::Err: std::fmt::Display,
+{
+    std::env::var(name)
+        .ok()
+        .map(|value| value.parse::<T>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", name, e)))
+}
+
+/// Value the wallet now and append the snapshot. None if the wallet is not initialized.
+pub async fn take() -> Result<Option<NavSnapshot>, EnclaveError> {
+    let (wallet_address, agent_id) = match TRADING_WALLET.read().await.as_ref() {
+        Some(wallet) => (wallet.address.clone(), wallet.agent_id.clone()),
+        None => return Ok(None),
+    };
+    let agent_id = agent_id
+        .map(|agent_id| agent_id.parse::<ObjectID>())
+        .transpose()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid agent ID: {}", e)))?;
+
+    let client = sui_client().await?;
+    let nav = shares::nav(&client, &wallet_address).await?;
+    let (total_shares, share_price) = match agent_id {
+        Some(agent_id) => match LEDGERS.lock().await.get(&agent_id) {
+            Some(ledger) if ledger.total_shares > 0 => (ledger.total_shares, Some(ledger.share_price(nav.value))),
+            _ => (0, None),
+        },
+        None => (0, None),
+    };
+
+    let snapshot = NavSnapshot {
+        timestamp_ms: get_current_timestamp(),
+        nav,
+        total_shares,
+        share_price,
+    };
+    let mut snapshots = SNAPSHOTS.write().await;
+    while snapshots.len() >= CAPACITY.load(Ordering::Relaxed) {
+        snapshots.pop_front();
+    }
+    snapshots.push_back(snapshot.clone());
+    save_locked(&snapshots);
+    Ok(Some(snapshot))
+}
+
+/// Whether snapshots survive a restart.
+pub fn is_persistent() -> bool {
+    std::env::var(NAV_SNAPSHOT_PATH_ENV).is_ok()
+}
+
+fn save_locked(snapshots: &VecDeque<NavSnapshot>) {
+    let Ok(path) = std::env::var(NAV_SNAPSHOT_PATH_ENV) else {
+        return;
+    };
+    // Write then rename, so a crash never leaves a partial file
+    let tmp = format!("{}.tmp", path);
+    let result = serde_json::to_vec(snapshots)
+        .map_err(std::io::Error::other)
+        .and_then(|bytes| std::fs::write(&tmp, bytes))
+        .and_then(|_| std::fs::rename(&tmp, &path));
+    if let Err(e) = result {
+        warn!("Failed to save NAV snapshots to {}: {}", path, e);
+    }
+}
+
+/// Snapshots taken at or after `since_ms`, oldest first.
+pub async fn since(since_ms: u64) -> Vec<NavSnapshot> {
+    SNAPSHOTS
+        .read()
+        .await
+        .iter()
+        .filter(|snapshot| snapshot.timestamp_ms >= since_ms)
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EquityCurveRequest {
+    /// Start of the curve, defaults to the oldest snapshot
+    pub since_ms: Option<u64>,
+    /// Width of the buckets, each represented by its last snapshot. Defaults to
+    /// the snapshot interval, i.e. every snapshot
+    pub resolution_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EquityPoint {
+    pub timestamp_ms: u64,
+    pub nav: u64,
+    pub sui_balance: u64,
+    pub usdc_balance: u64,
+    pub share_price: Option<u64>,
+    /// Fall of the unit value from its running peak
+    pub drawdown_bps: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EquityCurveResponse {
+    pub resolution_secs: u64,
+    pub points: Vec<EquityPoint>,
+    /// Points dropped from the start to stay within the response limit
+    pub truncated: bool,
+    /// Whether snapshots are saved to `NAV_SNAPSHOT_PATH`. If not, the curve
+    /// only starts at the enclave's last restart
+    pub persistent: bool,
+}
+
+/// Keep the last snapshot of every `resolution_ms` bucket and add drawdowns.
+pub fn equity_curve(snapshots: &[NavSnapshot], resolution_ms: u64) -> Vec<EquityPoint> {
+    let mut points: Vec<EquityPoint> = Vec::new();
+    let mut peak = 0u64;
+    let mut bucket = None;
+    for snapshot in snapshots {
+        // The peak restarts when the unit changes from NAV to share price
+        let value = snapshot.unit_value();
+        if points.last().is_some_and(|last| last.share_price.is_some() != snapshot.share_price.is_some()) {
+            peak = 0;
+        }
+        peak = peak.max(value);
+        let point = EquityPoint {
+            timestamp_ms: snapshot.timestamp_ms,
+            nav: snapshot.nav.value,
+            sui_balance: snapshot.nav.sui_balance,
+            usdc_balance: snapshot.nav.usdc_balance,
+            share_price: snapshot.share_price,
+            drawdown_bps: if peak == 0 { 0 } else { ((peak - value) as u128 * 10_000 / peak as u128) as u64 },
+        };
+
+        let current = snapshot.timestamp_ms / resolution_ms.max(1);
+        if bucket == Some(current) {
+            *points.last_mut().expect("bucket has a point") = point;
+        } else {
+            points.push(point);
+            bucket = Some(current);
+        }
+    }
+    points
+}
+
+// ====== Warp Wrapper Functions ======
+
+pub async fn equity_curve_wrapper(
+    request: ProcessDataRequest<EquityCurveRequest>,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match equity_curve_internal(state, request).await {
+        Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+        Err(e) => Ok(e.into_reply()),
+    }
+}
+
+async fn equity_curve_internal(
+    state: Arc<AppState>,
+    request: ProcessDataRequest<EquityCurveRequest>,
+) -> Result<ProcessedDataResponse<IntentMessage<EquityCurveResponse>>, EnclaveError> {
+    let resolution_secs = match request.payload.resolution_secs {
+        Some(0) => return Err(EnclaveError::InvalidRequest("resolution_secs must be positive".to_string())),
+        Some(resolution_secs) => resolution_secs,
+        None => env_number::<u64>(NAV_SNAPSHOT_INTERVAL_ENV)?.unwrap_or(DEFAULT_INTERVAL_SECS),
+    };
+    let snapshots = since(request.payload.since_ms.unwrap_or(0)).await;
+    let mut points = equity_curve(&snapshots, resolution_secs * 1000);
+    let truncated = points.len() > MAX_POINTS;
+    if truncated {
+        points.drain(..points.len() - MAX_POINTS);
+    }
+
+    let response = EquityCurveResponse {
+        resolution_secs,
+        points,
+        truncated,
+        persistent: is_persistent(),
+    };
+    Ok(to_signed_response(
+        &state.eph_kp,
+        response,
+        get_current_timestamp(),
+        IntentScope::ProcessData,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equity_curve_resolution() {
+        let snapshot = |timestamp_ms, value| NavSnapshot {
+            timestamp_ms,
+            nav: Nav::new(value, 0, 0, 0),
+            total_shares: 0,
+            share_price: None,
+        };
+        let snapshots = vec![
+            snapshot(0, 1_000),
+            snapshot(30_000, 1_200),
+            snapshot(60_000, 900),
+            snapshot(90_000, 1_000),
+            snapshot(120_000, 1_300),
+        ];
+
+        let points = equity_curve(&snapshots, 60_000);
+        let navs: Vec<u64> = points.iter().map(|point| point.nav).collect();
+        assert_eq!(navs, vec![1_200, 1_000, 1_300]);
+        // 1000 is 1/6 below the peak of 1200
+        assert_eq!(points[1].drawdown_bps, 1_666);
+        assert_eq!(points[2].drawdown_bps, 0);
+        assert_eq!(equity_curve(&snapshots, 1_000).len(), 5);
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Gas sponsorship by the host. The enclave's own keys hold no SUI on boot and
+// trading wallets may only hold USDC, so transactions are paid from a gas coin
+// the host supplies through `SPONSOR_GAS_COIN`. The coin's owner co-signs
+// through the sponsor service at `SPONSOR_URL` (see `bin/sponsor_service.rs`
+// for a local stand-in):
+//
+//   POST {SPONSOR_URL}/sponsor {"tx_bytes": "<base64 TransactionData>"}
+//   -> {"signature": "<base64 Sui signature>"}
+//
+// Each trading wallet may spend up to `SPONSOR_BUDGET_MIST` of sponsored gas,
+// after which it pays gas itself again. The budget is checked and charged
+// under `SPONSOR_LOCK`, so concurrent transactions cannot overspend it. Usage
+// is kept in memory only: every wallet's budget resets when the enclave restarts.
+
+use super::chain::{execute_transaction, sign_transaction};
+use crate::EnclaveError;
+use fastcrypto::encoding::{Base64, Encoding};
+use fastcrypto::traits::ToFromBytes;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use sui_json_rpc_types::{SuiObjectDataOptions, SuiTransactionBlockEffectsAPI, SuiTransactionBlockResponse};
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, ObjectRef, SuiAddress};
+use sui_types::crypto::{Signature, SuiKeyPair};
+use sui_types::object::Owner;
+use sui_types::transaction::{ProgrammableTransaction, TransactionData};
+use tokio::sync::{Mutex, RwLock};
+
+pub const SPONSOR_URL_ENV: &str = "SPONSOR_URL";
+pub const SPONSOR_GAS_COIN_ENV: &str = "SPONSOR_GAS_COIN";
+const SPONSOR_BUDGET_ENV: &str = "SPONSOR_BUDGET_MIST";
+/// Default sponsored gas per wallet, 1 SUI.
+const DEFAULT_SPONSOR_BUDGET: u64 = 1_000_000_000;
+
+lazy_static! {
+    /// Sponsored gas spent per wallet.
+    static ref SPONSORED_GAS: Arc<RwLock<BTreeMap<SuiAddress, u64>>> = Arc::new(RwLock::new(BTreeMap::new()));
+    /// The sponsor pays from a single coin, so sponsored transactions run one at a time.
+    static ref SPONSOR_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Sponsorship usage, reported in `/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SponsorStatus {
+    pub budget_per_wallet: u64,
+    pub gas_used: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct SponsorRequest {
+    tx_bytes: String,
+}
+
+#[derive(Deserialize)]
+struct SponsorResponse {
+    signature: String,
+}
+
+/// Whether the host supplied a sponsor.
+pub fn is_configured() -> bool {
+    std::env::var(SPONSOR_URL_ENV).is_ok() && std::env::var(SPONSOR_GAS_COIN_ENV).is_ok()
+}
+
+/// Gas each wallet may have sponsored.
+pub fn budget_per_wallet() -> u64 {
+    std::env::var(SPONSOR_BUDGET_ENV)
+        .ok()
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(DEFAULT_SPONSOR_BUDGET)
+}
+
+/// Whether a transaction of `wallet` with `gas_budget` can be sponsored. The
+/// budget is checked again when the transaction executes.
+pub async fn is_sponsored(wallet: &SuiAddress, gas_budget: u64) -> bool {
+    is_configured() && within_budget(wallet, gas_budget).await
+}
+
+async fn within_budget(wallet: &SuiAddress, gas_budget: u64) -> bool {
+    let used = SPONSORED_GAS.read().await.get(wallet).copied().unwrap_or(0);
+    used.saturating_add(gas_budget) <= budget_per_wallet()
+}
+
+/// Sponsorship usage, `None` without a sponsor.
+pub async fn status() -> Option<SponsorStatus> {
+    if !is_configured() {
+        return None;
+    }
+    Some(SponsorStatus {
+        budget_per_wallet: budget_per_wallet(),
+        gas_used: SPONSORED_GAS
+            .read()
+            .await
+            .iter()
+            .map(|(wallet, used)| (wallet.to_string(), *used))
+            .collect(),
+    })
+}
+
+/// Execute `pt` sent by `keypair`'s wallet with gas paid by the sponsor, and
+/// charge the gas used to the wallet's budget. Fails with `Conflict` if the
+/// budget no longer covers `gas_budget`, callers check `is_sponsored` first.
+pub async fn execute_sponsored(
+    client: &SuiClient,
+    keypair: &SuiKeyPair,
+    sender: SuiAddress,
+    pt: ProgrammableTransaction,
+    gas_budget: u64,
+) -> Result<SuiTransactionBlockResponse, EnclaveError> {
+    let _guard = SPONSOR_LOCK.lock().await;
+    if !within_budget(&sender, gas_budget).await {
+        return Err(EnclaveError::Conflict(format!("Sponsored gas budget of {} is used up", sender)));
+    }
+    let (sponsor, gas) = sponsor_gas(client).await?;
+    let gas_price = client
+        .read_api()
+        .get_reference_gas_price()
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to get gas price: {}", e)))?;
+    let tx_data = TransactionData::new_programmable_allow_sponsor(sender, vec![gas], pt, gas_budget, gas_price, sponsor);
+
+    let signatures = vec![sign_transaction(keypair, &tx_data), sponsor_signature(&tx_data).await?];
+    let result = execute_transaction(client, tx_data, signatures).await;
+
+    // Failed transactions are charged too, assume the whole budget when the effects are unknown
+    let gas_used = match &result {
+        Ok(response) => response
+            .effects
+            .as_ref()
+            .map(|effects| effects.gas_cost_summary().net_gas_usage().max(0) as u64)
+            .unwrap_or(gas_budget),
+        Err(_) => gas_budget,
+    };
+    *SPONSORED_GAS.write().await.entry(sender).or_insert(0) += gas_used;
+    result
+}
+
+/// The sponsor's address and the current reference of its gas coin.
+pub async fn sponsor_gas(client: &SuiClient) -> Result<(SuiAddress, ObjectRef), EnclaveError> {
+    let coin_id: ObjectID = std::env::var(SPONSOR_GAS_COIN_ENV)
+        .map_err(|_| EnclaveError::Internal(format!("{} is not set", SPONSOR_GAS_COIN_ENV)))?
+        .parse()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid {}: {}", SPONSOR_GAS_COIN_ENV, e)))?;
+
+    let data = client
+        .read_api()
+        .get_object_with_options(coin_id, SuiObjectDataOptions::new().with_owner())
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Failed to fetch gas coin {}: {}", coin_id, e)))?
+        .data
+        .ok_or_else(|| EnclaveError::NotFound(format!("Gas coin {} not found", coin_id)))?;
+
+    match data.owner {
+        Some(Owner::AddressOwner(sponsor)) => Ok((sponsor, data.object_ref())),
+        _ => Err(EnclaveError::Internal(format!(
+            "Gas coin {} is not owned by an address",
+            coin_id
+        ))),
+    }
+}
+
+/// Ask the host sponsor to co-sign `tx_data` as gas owner.
+pub async fn sponsor_signature(tx_data: &TransactionData) -> Result<Signature, EnclaveError> {
+    let url = std::env::var(SPONSOR_URL_ENV)
+        .map_err(|_| EnclaveError::Internal(format!("{} is not set", SPONSOR_URL_ENV)))?;
+    let tx_bytes = bcs::to_bytes(tx_data)
+        .map_err(|e| EnclaveError::Internal(format!("Failed to serialize transaction: {}", e)))?;
+
+    let response: SponsorResponse = reqwest::Client::new()
+        .post(format!("{}/sponsor", url.trim_end_matches('/')))
+        .json(&SponsorRequest {
+            tx_bytes: Base64::encode(tx_bytes),
+        })
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| EnclaveError::Rpc(format!("Sponsor request failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| EnclaveError::Rpc(format!("Invalid sponsor response: {}", e)))?;
+
+    let bytes = Base64::decode(&response.signature)
+        .map_err(|e| EnclaveError::Rpc(format!("Invalid sponsor signature encoding: {}", e)))?;
+    Signature::from_bytes(&bytes)
+        .map_err(|e| EnclaveError::Rpc(format!("Invalid sponsor signature: {}", e)))
+}
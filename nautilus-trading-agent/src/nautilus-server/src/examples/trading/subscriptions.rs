@@ -0,0 +1,315 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// On-chain checks for subscription withdrawals. Before funds leave the agent
+// wallet the enclave reads the `SubscriptionManager` tables, the equivalent of
+// `is_subscribed`, `get_subscription_info` and `get_user_deposit`, and requires
+// the withdrawal to be signed by the subscriber:
+//
+// 1. the subscriber has an active, unexpired `UserSubscription` to the agent,
+// 2. the agent's wallet in the manager is this enclave's wallet,
+// 3. the amount does not exceed the subscriber's recorded deposit,
+// 4. the subscriber signed [withdrawal_message] with a fresh timestamp.
+//
+// Each signature is accepted once, in order: the last accepted timestamp of
+// every subscriber is saved with the event state before funds move, so a
+// restart cannot accept it again.
+
+use super::auth::{check_signature_timestamp, verify_personal_message};
+use super::chain::{read_move_object, read_table_entry, TableObject};
+use super::{events, get_current_timestamp, SUBSCRIPTION_MANAGER_ID};
+use crate::EnclaveError;
+use serde::{Deserialize, Serialize};
+use sui_sdk::SuiClient;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use sui_types::TypeTag;
+
+/// BCS layout of `subscription_manager::SubscriptionManager`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct SubscriptionManagerObject {
+    id: ObjectID,
+    deployer: SuiAddress,
+    user_subscriptions: TableObject,
+    agent_wallets: TableObject,
+    withdrawal_requests: TableObject,
+}
+
+/// BCS layout of `subscription_manager::UserSubscription`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct UserSubscriptionObject {
+    id: ObjectID,
+    agent_id: ObjectID,
+    subscriber: SuiAddress,
+    subscription_end: u64,
+    total_deposited: u64,
+    is_active: bool,
+    subscribed_at: u64,
+}
+
+/// BCS layout of `subscription_manager::AgentWallet`.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct AgentWalletObject {
+    wallet_address: SuiAddress,
+    total_deposited: u64,
+    user_deposits: TableObject,
+}
+
+/// A subscriber's subscription and deposit as recorded on-chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub subscription_id: ObjectID,
+    pub agent_id: ObjectID,
+    pub subscriber: SuiAddress,
+    pub subscription_end: u64,
+    pub is_active: bool,
+    pub subscribed_at: u64,
+    /// Current deposit of the subscriber, zero if the agent wallet is not set
+    pub deposited: u64,
+    /// Agent wallet registered in the manager, if set
+    pub agent_wallet: Option<SuiAddress>,
+}
+
+/// Message the subscriber signs to withdraw from an agent.
+pub fn withdrawal_message(agent_id: &str, recipient: &str, amount: u64, timestamp_ms: u64) -> String {
+    format!(
+        "suibian-subscription:withdraw:{}:{}:{}:{}",
+        agent_id, recipient, amount, timestamp_ms
+    )
+}
+
+/// Read the subscription of `subscriber` to `agent_id`, None if not subscribed.
+pub async fn load_subscription(
+    client: &SuiClient,
+    agent_id: ObjectID,
+    subscriber: SuiAddress,
+) -> Result<Option<Subscription>, EnclaveError> {
+    let manager_id = SUBSCRIPTION_MANAGER_ID
+        .parse::<ObjectID>()
+        .map_err(|e| EnclaveError::Internal(format!("Invalid subscription manager ID: {}", e)))?;
+    let (_, manager) = read_move_object::<SubscriptionManagerObject>(client, manager_id).await?;
+
+    // Same key as `generate_subscription_key`: agent ID bytes followed by the address bytes
+    let mut key = agent_id.to_vec();
+    key.extend_from_slice(subscriber.as_ref());
+    let subscription_id = read_table_entry::<Vec<u8>, ObjectID>(
+        client,
+        manager.user_subscriptions.id,
+        TypeTag::Vector(Box::new(TypeTag::U8)),
+        serde_json::json!(key),
+    )
+    .await?;
+    let Some(subscription_id) = subscription_id else {
+        return Ok(None);
+    };
+    let (_, subscription) = read_move_object::<UserSubscriptionObject>(client, subscription_id).await?;
+
+    let agent_wallet = read_table_entry::<ObjectID, AgentWalletObject>(
+        client,
+        manager.agent_wallets.id,
+        object_id_type(),
+        serde_json::json!(agent_id.to_hex_literal()),
+    )
+    .await?;
+    let deposited = match &agent_wallet {
+        Some(wallet) => read_table_entry::<SuiAddress, u64>(
+            client,
+            wallet.user_deposits.id,
+            TypeTag::Address,
+            serde_json::json!(subscriber.to_string()),
+        )
+        .await?
+        .unwrap_or(0),
+        None => 0,
+    };
+
+    Ok(Some(Subscription {
+        subscription_id,
+        agent_id: subscription.agent_id,
+        subscriber: subscription.subscriber,
+        subscription_end: subscription.subscription_end,
+        is_active: subscription.is_active,
+        subscribed_at: subscription.subscribed_at,
+        deposited,
+        agent_wallet: agent_wallet.map(|wallet| wallet.wallet_address),
+    }))
+}
+
+/// Check a withdrawal of `amount` by `subscriber` from the agent managed by
+/// `wallet_address` and return the subscription it is drawn from.
+pub async fn verify_withdrawal(
+    client: &SuiClient,
+    agent_id: ObjectID,
+    subscriber: SuiAddress,
+    wallet_address: SuiAddress,
+    amount: u64,
+    timestamp_ms: u64,
+    signature: &str,
+) -> Result<Subscription, EnclaveError> {
+    let now_ms = get_current_timestamp();
+    check_withdrawal_signature(agent_id, subscriber, amount, timestamp_ms, signature, now_ms)?;
+    let subscription = load_subscription(client, agent_id, subscriber).await?;
+    let subscription = check_subscription(subscription, agent_id, subscriber, wallet_address, amount, now_ms)?;
+    events::authorize_withdrawal(subscriber, timestamp_ms).await?;
+    Ok(subscription)
+}
+
+/// Check that `subscriber` signed the withdrawal with a fresh timestamp.
+fn check_withdrawal_signature(
+    agent_id: ObjectID,
+    subscriber: SuiAddress,
+    amount: u64,
+    timestamp_ms: u64,
+    signature: &str,
+    now_ms: u64,
+) -> Result<(), EnclaveError> {
+    check_signature_timestamp(timestamp_ms, now_ms)?;
+    let message = withdrawal_message(
+        &agent_id.to_hex_literal(),
+        &subscriber.to_string(),
+        amount,
+        timestamp_ms,
+    );
+    verify_personal_message(&subscriber, message.as_bytes(), signature)
+}
+
+/// Check that the withdrawal is drawn from an active subscription of an agent
+/// managed by `wallet_address`, within the subscriber's deposit.
+fn check_subscription(
+    subscription: Option<Subscription>,
+    agent_id: ObjectID,
+    subscriber: SuiAddress,
+    wallet_address: SuiAddress,
+    amount: u64,
+    now_ms: u64,
+) -> Result<Subscription, EnclaveError> {
+    let subscription = subscription.ok_or_else(|| {
+        EnclaveError::Unauthorized(format!("{} is not subscribed to agent {}", subscriber, agent_id))
+    })?;
+    if !subscription.is_active || subscription.subscription_end <= now_ms {
+        return Err(EnclaveError::Unauthorized(format!(
+            "Subscription of {} to agent {} is not active",
+            subscriber, agent_id
+        )));
+    }
+    if subscription.agent_wallet != Some(wallet_address) {
+        return Err(EnclaveError::Conflict(format!(
+            "Agent {} is not managed by wallet {}",
+            agent_id, wallet_address
+        )));
+    }
+    if amount > subscription.deposited {
+        return Err(EnclaveError::InsufficientBalance(format!(
+            "Withdrawal of {} exceeds the deposit of {}",
+            amount, subscription.deposited
+        )));
+    }
+    Ok(subscription)
+}
+
+fn object_id_type() -> TypeTag {
+    sui_types::parse_sui_type_tag("0x2::object::ID").expect("valid type")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::auth::MAX_SIGNATURE_AGE_MS;
+    use fastcrypto::ed25519::Ed25519KeyPair;
+    use fastcrypto::encoding::{Base64, Encoding};
+    use fastcrypto::traits::KeyPair;
+    use shared_crypto::intent::{Intent, IntentMessage, PersonalMessage};
+    use sui_types::crypto::{Signature, SuiKeyPair};
+
+    const NOW_MS: u64 = 1_700_000_000_000;
+
+    fn sign(keypair: &SuiKeyPair, message: &str) -> String {
+        let intent_msg = IntentMessage::new(
+            Intent::personal_message(),
+            PersonalMessage {
+                message: message.as_bytes().to_vec(),
+            },
+        );
+        Base64::encode(Signature::new_secure(&intent_msg, keypair).as_ref())
+    }
+
+    fn subscription(agent_id: ObjectID, subscriber: SuiAddress, wallet: SuiAddress) -> Subscription {
+        Subscription {
+            subscription_id: ObjectID::random(),
+            agent_id,
+            subscriber,
+            subscription_end: NOW_MS + 1_000,
+            is_active: true,
+            subscribed_at: NOW_MS - 1_000,
+            deposited: 1_000,
+            agent_wallet: Some(wallet),
+        }
+    }
+
+    #[test]
+    fn test_withdrawal_signature() {
+        let keypair = SuiKeyPair::Ed25519(Ed25519KeyPair::generate(&mut rand::thread_rng()));
+        let subscriber = SuiAddress::from(&keypair.public());
+        let agent_id = ObjectID::random();
+        let message = |amount, timestamp_ms| {
+            withdrawal_message(&agent_id.to_hex_literal(), &subscriber.to_string(), amount, timestamp_ms)
+        };
+
+        let signature = sign(&keypair, &message(100, NOW_MS));
+        assert!(check_withdrawal_signature(agent_id, subscriber, 100, NOW_MS, &signature, NOW_MS).is_ok());
+
+        // Signed for another amount, or by someone else
+        assert!(matches!(
+            check_withdrawal_signature(agent_id, subscriber, 200, NOW_MS, &signature, NOW_MS),
+            Err(EnclaveError::Unauthorized(_))
+        ));
+        let other = SuiAddress::random_for_testing_only();
+        assert!(matches!(
+            check_withdrawal_signature(agent_id, other, 100, NOW_MS, &signature, NOW_MS),
+            Err(EnclaveError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            check_withdrawal_signature(agent_id, subscriber, 100, NOW_MS, "not base64!", NOW_MS),
+            Err(EnclaveError::InvalidRequest(_))
+        ));
+
+        // A stale signature is rejected even if valid
+        let stale_ms = NOW_MS - MAX_SIGNATURE_AGE_MS - 1;
+        let stale = sign(&keypair, &message(100, stale_ms));
+        assert!(matches!(
+            check_withdrawal_signature(agent_id, subscriber, 100, stale_ms, &stale, NOW_MS),
+            Err(EnclaveError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_subscription_checks() {
+        let agent_id = ObjectID::random();
+        let subscriber = SuiAddress::random_for_testing_only();
+        let wallet = SuiAddress::random_for_testing_only();
+        let check = |subscription: Option<Subscription>, amount| {
+            check_subscription(subscription, agent_id, subscriber, wallet, amount, NOW_MS)
+        };
+
+        assert!(check(Some(subscription(agent_id, subscriber, wallet)), 1_000).is_ok());
+        assert!(matches!(check(None, 100), Err(EnclaveError::Unauthorized(_))));
+
+        let mut inactive = subscription(agent_id, subscriber, wallet);
+        inactive.is_active = false;
+        assert!(matches!(check(Some(inactive), 100), Err(EnclaveError::Unauthorized(_))));
+
+        let mut expired = subscription(agent_id, subscriber, wallet);
+        expired.subscription_end = NOW_MS;
+        assert!(matches!(check(Some(expired), 100), Err(EnclaveError::Unauthorized(_))));
+
+        let other_wallet = subscription(agent_id, subscriber, SuiAddress::random_for_testing_only());
+        assert!(matches!(check(Some(other_wallet), 100), Err(EnclaveError::Conflict(_))));
+
+        assert!(matches!(
+            check(Some(subscription(agent_id, subscriber, wallet)), 1_001),
+            Err(EnclaveError::InsufficientBalance(_))
+        ));
+    }
+}
@@ -1,9 +1,6 @@
 // Copyright (c), Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use fastcrypto::ed25519::Ed25519KeyPair;
-use std::fmt;
-
 pub mod examples {
     #[cfg(feature = "trading")]
     pub mod trading;
@@ -18,29 +15,24 @@ pub mod app {
     };
 }
 
+pub mod attestation;
 pub mod common;
+pub mod eif;
+pub mod enclave_keys;
+pub mod error;
+pub mod nsm;
+
+pub use error::EnclaveError;
+use enclave_keys::EnclaveKeys;
+use nsm::AttestationProvider;
 
 /// App state, at minimum needs to maintain the ephemeral keypair
 pub struct AppState {
-    /// Ephemeral keypair on boot
-    pub eph_kp: Ed25519KeyPair,
+    /// Ephemeral keypair on boot, rotated by epoch
+    pub eph_kp: EnclaveKeys,
     /// API key for external services (optional)
     pub api_key: String,
+    /// Source of attestation documents
+    pub attestation: Box<dyn AttestationProvider>,
 }
 
-
-/// Enclave errors enum
-#[derive(Debug)]
-pub enum EnclaveError {
-    GenericError(String),
-}
-
-impl fmt::Display for EnclaveError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            EnclaveError::GenericError(e) => write!(f, "{}", e),
-        }
-    }
-}
-
-impl std::error::Error for EnclaveError {}
\ No newline at end of file
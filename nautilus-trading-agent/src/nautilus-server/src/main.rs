@@ -2,25 +2,40 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::Result;
+use fastcrypto::encoding::{Encoding, Hex};
 use fastcrypto::{ed25519::Ed25519KeyPair, traits::KeyPair};
-use nautilus_server::AppState;
+use nautilus_server::enclave_keys::EnclaveKeys;
+use nautilus_server::{AppState, EnclaveError};
 use nautilus_server::common::{get_attestation, health_check};
+use serde::Deserialize;
 use std::sync::Arc;
 use tracing::info;
 use warp::Filter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let eph_kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+    let eph_kp = EnclaveKeys::new(Ed25519KeyPair::generate(&mut rand::thread_rng()));
 
     // API key for external services - kept for compatibility
     let api_key = std::env::var("API_KEY").unwrap_or_else(|_| String::new());
 
-    let state = Arc::new(AppState { eph_kp, api_key });
+    let attestation = nautilus_server::nsm::provider_from_env()?;
+
+    let state = Arc::new(AppState { eph_kp, api_key, attestation });
 
     // Trading Agent startup
     #[cfg(feature = "trading")]
     {
+        nautilus_server::examples::trading::load_master_seed(&state).await?;
+        nautilus_server::examples::trading::journal::load_journal().await?;
+        nautilus_server::examples::trading::agent::load_agent().await?;
+        nautilus_server::examples::trading::registration::start_self_registration(state.clone()).await?;
+        nautilus_server::examples::trading::relayer::start_relayer().await?;
+        nautilus_server::examples::trading::events::start_event_listener().await?;
+        nautilus_server::examples::trading::expiry::start_expiry_checks(state.clone()).await?;
+        nautilus_server::examples::trading::rotation::start_key_rotation(state.clone()).await?;
+        nautilus_server::examples::trading::snapshots::start_nav_snapshots().await?;
+
         println!("🚀 Starting Nautilus Trading Agent...");
         println!("📍 Trading endpoints available:");
         println!("   POST /init_wallet         - Initialize trading wallet & get address for deposits");
@@ -29,6 +44,26 @@ async fn main() -> Result<()> {
         println!("   POST /withdraw            - Withdraw funds (owner only)");
         println!("   POST /simple_transfer     - Simple SUI transfer (test signature)");
         println!("   POST /subscription_withdraw - Withdraw funds through subscription manager (subscribers only)");
+        println!("   POST /sealed_seed         - Export the sealed master seed for backup (requires API_KEY)");
+        println!("   POST /migration_request   - Attest a migration key (new enclave, requires API_KEY)");
+        println!("   POST /migration_export    - Hand wallet secrets to an attested enclave (old enclave, requires API_KEY)");
+        println!("   POST /migration_import    - Install wallet secrets from the old enclave (new enclave)");
+        println!("   POST /migration_complete  - Erase exported secrets given the signed import (old enclave)");
+        println!("   POST /migration_abort     - Use exported secrets again after a failed import (old enclave, requires API_KEY)");
+        println!("   POST /recovery_setup      - Register owner/guardian recovery keys (owner signed)");
+        println!("   POST /recovery_request    - Start the recovery delay (owner signed)");
+        println!("   POST /recovery_cancel     - Cancel a pending recovery (owner signed)");
+        println!("   POST /recovery_execute    - Release the key or enclave share after the delay (owner signed)");
+        println!("   POST /recovery_status     - Recovery configuration and audit log");
+        println!("   POST /history             - Signed, hash-chained journal of executed transactions");
+        println!("   POST /positions           - Subscriber shares and their value at the current NAV");
+        println!("   POST /rotate_key          - Rotate the enclave key (requires API_KEY)");
+        println!("   POST /key_handovers       - Handovers signed by every rotated key");
+        println!("   POST /bind_agent          - Re-issue the signed agent binding (requires owner signature)");
+        println!("   POST /settle              - Charge high-water-mark performance fees (requires API_KEY)");
+        println!("   POST /claim_fees          - Pay the fee shares out to the agent creator (requires API_KEY)");
+        println!("   POST /performance         - Signed returns, drawdown, Sharpe and trade statistics");
+        println!("   POST /equity_curve        - NAV snapshots at a chosen resolution");
     }
 
     let cors = warp::cors()
@@ -43,10 +78,12 @@ async fn main() -> Result<()> {
 
     let health = warp::path("health")
         .and(warp::get())
-        .map(|| warp::reply::json(&health_check()));
+        .and(with_state(state.clone()))
+        .and_then(health_handler);
 
     let attestation = warp::path("attestation")
         .and(warp::get())
+        .and(warp::query::<AttestationQuery>())
         .and(with_state(state.clone()))
         .and_then(attestation_handler);
 
@@ -89,7 +126,132 @@ async fn main() -> Result<()> {
             .and(with_state(state.clone()))
             .and_then(nautilus_server::examples::trading::subscription_withdraw_wrapper);
 
-        init_wallet.or(execute_trade).or(wallet_status).or(withdraw).or(simple_transfer).or(subscription_withdraw)
+        let sealed_seed = warp::path("sealed_seed")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::sealed_seed_wrapper);
+
+        let migration_request = warp::path("migration_request")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::migration::migration_request_wrapper);
+
+        let migration_export = warp::path("migration_export")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::migration::migration_export_wrapper);
+
+        let migration_import = warp::path("migration_import")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::migration::migration_import_wrapper);
+
+        let migration_complete = warp::path("migration_complete")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::migration::migration_complete_wrapper);
+
+        let migration_abort = warp::path("migration_abort")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::migration::migration_abort_wrapper);
+
+        let recovery_setup = warp::path("recovery_setup")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::recovery::recovery_setup_wrapper);
+
+        let recovery_request = warp::path("recovery_request")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::recovery::recovery_request_wrapper);
+
+        let recovery_cancel = warp::path("recovery_cancel")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::recovery::recovery_cancel_wrapper);
+
+        let recovery_execute = warp::path("recovery_execute")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::recovery::recovery_execute_wrapper);
+
+        let recovery_status = warp::path("recovery_status")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::recovery::recovery_status_wrapper);
+
+        let history = warp::path("history")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::journal::history_wrapper);
+
+        let positions = warp::path("positions")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::shares::positions_wrapper);
+
+        let rotate_key = warp::path("rotate_key")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::rotation::rotate_key_wrapper);
+
+        let key_handovers = warp::path("key_handovers")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::rotation::key_handovers_wrapper);
+
+        let bind_agent = warp::path("bind_agent")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::binding::bind_agent_wrapper);
+
+        let settle = warp::path("settle")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::fees::settle_wrapper);
+
+        let claim_fees = warp::path("claim_fees")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::fees::claim_fees_wrapper);
+
+        let performance = warp::path("performance")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::performance::performance_wrapper);
+
+        let equity_curve = warp::path("equity_curve")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .and_then(nautilus_server::examples::trading::snapshots::equity_curve_wrapper);
+
+        init_wallet.or(execute_trade).or(wallet_status).or(withdraw).or(simple_transfer).or(subscription_withdraw).or(sealed_seed)
+            .or(migration_request).or(migration_export).or(migration_import).or(migration_complete).or(migration_abort)
+            .or(recovery_setup).or(recovery_request).or(recovery_cancel).or(recovery_execute).or(recovery_status)
+            .or(history).or(positions)
+            .or(rotate_key).or(key_handovers).or(bind_agent).or(settle).or(claim_fees)
+            .or(performance).or(equity_curve)
     };
 
     let routes = ping.or(health).or(attestation);
@@ -119,14 +281,56 @@ fn with_state(state: Arc<AppState>) -> impl Filter<Extract = (Arc<AppState>,), E
     warp::any().map(move || state.clone())
 }
 
-async fn attestation_handler(state: Arc<AppState>) -> Result<impl warp::Reply, warp::Rejection> {
-    match get_attestation(&state).await {
-        Ok(att) => Ok(warp::reply::json(&att)),
-        Err(_) => Err(warp::reject::custom(ServerError)),
+#[derive(Debug, Deserialize)]
+struct AttestationQuery {
+    /// Hex encoded nonce to include in the document
+    nonce: Option<String>,
+}
+
+async fn attestation_handler(
+    query: AttestationQuery,
+    state: Arc<AppState>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let nonce = match query.nonce.map(|nonce| Hex::decode(&nonce)).transpose() {
+        Ok(nonce) => nonce,
+        Err(e) => return Ok(EnclaveError::InvalidRequest(format!("Invalid nonce: {}", e)).into_reply()),
+    };
+
+    // Bind the wallets and configuration this enclave key controls
+    #[cfg(feature = "trading")]
+    let (user_data, preimage) = {
+        let commitment = nautilus_server::examples::trading::attestation_commitment().await;
+        (Some(commitment.digest().to_vec()), serde_json::to_value(&commitment).ok())
+    };
+    #[cfg(not(feature = "trading"))]
+    let (user_data, preimage) = (None, None);
+
+    match get_attestation(&state, nonce, user_data).await {
+        Ok(mut att) => {
+            att.user_data = preimage;
+            Ok(Box::new(warp::reply::json(&att)))
+        }
+        Err(e) => Ok(e.into_reply()),
     }
 }
 
-#[derive(Debug)]
-struct ServerError;
+async fn health_handler(state: Arc<AppState>) -> Result<impl warp::Reply, warp::Rejection> {
+    #[cfg(feature = "trading")]
+    let endpoints = nautilus_server::examples::trading::rpc_endpoints();
+    #[cfg(not(feature = "trading"))]
+    let endpoints: Vec<&str> = vec![];
+
+    #[allow(unused_mut)]
+    let mut response = health_check(&state, &endpoints).await;
+    #[cfg(feature = "trading")]
+    {
+        response.app = serde_json::to_value(nautilus_server::examples::trading::health_details().await).ok();
+    }
 
-impl warp::reject::Reject for ServerError {}
\ No newline at end of file
+    let status = if response.is_healthy() {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&response), status))
+}
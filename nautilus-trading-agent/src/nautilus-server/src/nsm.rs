@@ -0,0 +1,95 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::EnclaveError;
+use nsm_api::api::{Request as NsmRequest, Response as NsmResponse};
+use nsm_api::driver;
+use serde_bytes::ByteBuf;
+use tracing::info;
+
+#[cfg(feature = "mock-nsm")]
+pub mod mock;
+
+/// Selects the attestation provider, "nitro" or "mock" (requires the `mock-nsm` feature).
+pub const ATTESTATION_PROVIDER_ENV: &str = "ATTESTATION_PROVIDER";
+
+/// Source of attestation documents.
+pub trait AttestationProvider: Send + Sync {
+    /// Short name reported by `/health`.
+    fn name(&self) -> &'static str;
+
+    /// Whether attestations can currently be produced.
+    fn is_available(&self) -> bool;
+
+    /// Produce a COSE_Sign1 attestation document committing to the given
+    /// public key, and optionally to user data and a nonce.
+    fn attest(
+        &self,
+        public_key: Vec<u8>,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError>;
+}
+
+/// The Nitro Secure Module of the enclave.
+pub struct NitroNsm;
+
+impl AttestationProvider for NitroNsm {
+    fn name(&self) -> &'static str {
+        "nitro"
+    }
+
+    fn is_available(&self) -> bool {
+        let fd = driver::nsm_init();
+        if fd < 0 {
+            return false;
+        }
+        driver::nsm_exit(fd);
+        true
+    }
+
+    fn attest(
+        &self,
+        public_key: Vec<u8>,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError> {
+        let fd = driver::nsm_init();
+
+        let request = NsmRequest::Attestation {
+            user_data: user_data.map(ByteBuf::from),
+            nonce: nonce.map(ByteBuf::from),
+            public_key: Some(ByteBuf::from(public_key)),
+        };
+
+        let response = driver::nsm_process_request(fd, request);
+        driver::nsm_exit(fd);
+        match response {
+            NsmResponse::Attestation { document } => Ok(document),
+            _ => Err(EnclaveError::GenericError(
+                "unexpected response".to_string(),
+            )),
+        }
+    }
+}
+
+/// Pick the attestation provider from `ATTESTATION_PROVIDER`. Builds with the
+/// `mock-nsm` feature default to the mock, all others to the Nitro NSM.
+pub fn provider_from_env() -> Result<Box<dyn AttestationProvider>, EnclaveError> {
+    let default = if cfg!(feature = "mock-nsm") { "mock" } else { "nitro" };
+    let selected = std::env::var(ATTESTATION_PROVIDER_ENV).unwrap_or_else(|_| default.to_string());
+
+    let provider: Box<dyn AttestationProvider> = match selected.as_str() {
+        "nitro" => Box::new(NitroNsm),
+        #[cfg(feature = "mock-nsm")]
+        "mock" => Box::new(mock::MockNsm::from_env()?),
+        other => {
+            return Err(EnclaveError::GenericError(format!(
+                "Unsupported attestation provider: {}",
+                other
+            )))
+        }
+    };
+    info!("Using {} attestation provider", provider.name());
+    Ok(provider)
+}
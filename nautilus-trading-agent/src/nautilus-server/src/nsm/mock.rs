@@ -0,0 +1,190 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Software attestation for machines without a Nitro Secure Module. Documents
+// have the same COSE_Sign1 structure as the NSM's, but are signed by a leaf
+// certificate issued by a test CA generated at startup. They only verify
+// against that CA (see `MockNsm::root_fingerprint`), never the AWS root.
+
+use super::AttestationProvider;
+use crate::EnclaveError;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::hash::{HashFunction, Sha256};
+use nsm_api::api::{AttestationDoc, Digest};
+use p384::ecdsa::signature::Signer;
+use p384::ecdsa::{Signature, SigningKey};
+use p384::pkcs8::DecodePrivateKey;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, PKCS_ECDSA_P384_SHA384};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// Comma separated hex PCR0, PCR1 and PCR2 reported by the mock.
+pub const MOCK_PCRS_ENV: &str = "MOCK_PCRS";
+const PCR_LENGTH: usize = 48;
+const PCR_COUNT: usize = 16;
+/// COSE algorithm identifier of ECDSA with SHA-384.
+const COSE_ALG_ES384: i128 = -35;
+
+pub struct MockNsm {
+    pcrs: [Vec<u8>; 3],
+    root_certificate: Vec<u8>,
+    leaf_certificate: Vec<u8>,
+    leaf_key: SigningKey,
+}
+
+impl MockNsm {
+    /// Create a mock with a fresh test CA reporting the given PCR0-2.
+    pub fn new(pcrs: [Vec<u8>; 3]) -> Result<Self, EnclaveError> {
+        if pcrs.iter().any(|pcr| pcr.len() != PCR_LENGTH) {
+            return Err(EnclaveError::GenericError(format!(
+                "PCRs must be {} bytes",
+                PCR_LENGTH
+            )));
+        }
+
+        let ca_key = KeyPair::generate_for(&PKCS_ECDSA_P384_SHA384).map_err(cert_error)?;
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).map_err(cert_error)?;
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "nautilus mock attestation root");
+        let ca_cert = ca_params.self_signed(&ca_key).map_err(cert_error)?;
+
+        let leaf_key = KeyPair::generate_for(&PKCS_ECDSA_P384_SHA384).map_err(cert_error)?;
+        let mut leaf_params = CertificateParams::new(Vec::<String>::new()).map_err(cert_error)?;
+        leaf_params
+            .distinguished_name
+            .push(DnType::CommonName, "nautilus mock enclave");
+        let leaf_cert = leaf_params
+            .signed_by(&leaf_key, &ca_cert, &ca_key)
+            .map_err(cert_error)?;
+
+        Ok(Self {
+            pcrs,
+            root_certificate: ca_cert.der().to_vec(),
+            leaf_certificate: leaf_cert.der().to_vec(),
+            leaf_key: SigningKey::from_pkcs8_der(&leaf_key.serialize_der())
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid mock key: {}", e)))?,
+        })
+    }
+
+    /// Create a mock reporting the PCRs in `MOCK_PCRS`, all zero if unset.
+    pub fn from_env() -> Result<Self, EnclaveError> {
+        let pcrs = match std::env::var(MOCK_PCRS_ENV) {
+            Ok(value) => {
+                let pcrs = value
+                    .split(',')
+                    .map(|pcr| Hex::decode(pcr.trim()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| EnclaveError::GenericError(format!("Invalid {}: {}", MOCK_PCRS_ENV, e)))?;
+                pcrs.try_into().map_err(|_| {
+                    EnclaveError::GenericError(format!("{} must list PCR0, PCR1 and PCR2", MOCK_PCRS_ENV))
+                })?
+            }
+            Err(_) => [vec![0u8; PCR_LENGTH], vec![0u8; PCR_LENGTH], vec![0u8; PCR_LENGTH]],
+        };
+        let nsm = Self::new(pcrs)?;
+        info!("Mock attestation root certificate SHA-256: {}", nsm.root_fingerprint());
+        Ok(nsm)
+    }
+
+    /// DER encoded test CA certificate.
+    pub fn root_certificate(&self) -> &[u8] {
+        &self.root_certificate
+    }
+
+    /// Hex SHA-256 of the test CA certificate, to pin in verifiers.
+    pub fn root_fingerprint(&self) -> String {
+        Hex::encode(Sha256::digest(&self.root_certificate))
+    }
+}
+
+impl AttestationProvider for MockNsm {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn attest(
+        &self,
+        public_key: Vec<u8>,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError> {
+        let pcrs = (0..PCR_COUNT)
+            .map(|index| {
+                let value = self.pcrs.get(index).cloned().unwrap_or_else(|| vec![0u8; PCR_LENGTH]);
+                (index, value)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let payload = AttestationDoc::new(
+            "i-mock-enc0000000000000000".to_string(),
+            Digest::SHA384,
+            timestamp,
+            pcrs,
+            self.leaf_certificate.clone(),
+            vec![self.root_certificate.clone()],
+            user_data,
+            nonce,
+            Some(public_key),
+        )
+        .to_binary();
+
+        let protected = serde_cbor::to_vec(&Value::Map(BTreeMap::from([(
+            Value::Integer(1),
+            Value::Integer(COSE_ALG_ES384),
+        )])))
+        .map_err(cbor_error)?;
+        let sig_structure = serde_cbor::to_vec(&Value::Array(vec![
+            Value::Text("Signature1".to_string()),
+            Value::Bytes(protected.clone()),
+            Value::Bytes(vec![]),
+            Value::Bytes(payload.clone()),
+        ]))
+        .map_err(cbor_error)?;
+        let signature: Signature = self.leaf_key.sign(&sig_structure);
+
+        serde_cbor::to_vec(&Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(BTreeMap::new()),
+            Value::Bytes(payload),
+            Value::Bytes(signature.to_bytes().to_vec()),
+        ]))
+        .map_err(cbor_error)
+    }
+}
+
+fn cert_error(e: rcgen::Error) -> EnclaveError {
+    EnclaveError::GenericError(format!("Failed to create mock certificate: {}", e))
+}
+
+fn cbor_error(e: serde_cbor::Error) -> EnclaveError {
+    EnclaveError::GenericError(format!("Failed to encode attestation: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::verify_attestation_with_root;
+
+    #[test]
+    fn test_mock_attestation_verifies() {
+        let pcr0 = vec![1u8; PCR_LENGTH];
+        let nsm = MockNsm::new([pcr0.clone(), vec![2u8; PCR_LENGTH], vec![3u8; PCR_LENGTH]]).unwrap();
+        let document = nsm.attest(vec![7u8; 32], Some(b"data".to_vec()), None).unwrap();
+
+        let doc = verify_attestation_with_root(&document, &nsm.root_fingerprint()).unwrap();
+        assert_eq!(doc.pcrs[&0].as_slice(), pcr0.as_slice());
+        assert_eq!(doc.public_key.unwrap().as_slice(), [7u8; 32]);
+        assert!(crate::attestation::verify_attestation(&document).is_err());
+    }
+}